@@ -0,0 +1,150 @@
+use bevy::prelude::Resource;
+use bevy_egui::egui;
+
+use crate::items::{ItemTag, ProcessedType};
+use crate::planner::plan;
+use crate::recipes::{IngredientKind, Recipe, RecipeBook};
+use crate::research::Research;
+use crate::tile_data::*;
+
+///Deepest ingredient tree shown, in case a recipe ever uses its own product.
+const MAX_TREE_DEPTH: usize = 8;
+
+///Whether the candy codex is open and which candy it shows.
+#[derive(Resource, Debug)]
+pub struct CodexState {
+    pub open: bool,
+    pub selected: Option<ProcessedType>,
+    pub plan_rate: f32, //candies per minute the production planner plans for
+}
+
+impl Default for CodexState {
+    fn default() -> Self {
+        CodexState { open: false, selected: None, plan_rate: 10. }
+    }
+}
+
+///Draws the candy codex: every candy on the left, and how to make the selected one on the right.
+pub fn show_codex(ctx: &egui::Context, state: &mut CodexState, book: &RecipeBook, research: &Research) {
+    let mut open = state.open;
+    let candies: Vec<ProcessedType> = ProcessedType::ALL.into_iter()
+        .filter(|candy| ResourceItemType::Processed(*candy).has_tag(ItemTag::Candy))
+        .collect();
+    let known = candies.iter().filter(|candy| research.knows(book, ResourceItemType::Processed(**candy))).count();
+
+    egui::Window::new(format!("Candy codex ({}/{})", known, candies.len()))
+        .open(&mut open)
+        .default_size([560., 420.])
+        .show(ctx, |ui| {
+            ui.columns(2, |columns| {
+                egui::ScrollArea::vertical().id_source("codex_list").show(&mut columns[0], |ui| {
+                    for candy in &candies {
+                        let item = ResourceItemType::Processed(*candy);
+                        let text = if research.knows(book, item) {
+                            egui::RichText::new(item.info().name)
+                        } else {
+                            egui::RichText::new(format!("{} (undiscovered)", item.info().name)).weak()
+                        };
+                        if ui.selectable_label(state.selected == Some(*candy), text).clicked() {
+                            state.selected = Some(*candy);
+                        }
+                    }
+                });
+                egui::ScrollArea::vertical().id_source("codex_entry").show(&mut columns[1], |ui| {
+                    match state.selected {
+                        Some(candy) => {
+                            candy_entry(ui, ResourceItemType::Processed(candy), book, research);
+                            ui.separator();
+                            plan_entry(ui, ResourceItemType::Processed(candy), book, &mut state.plan_rate);
+                        },
+                        None => {ui.label("Pick a candy.");},
+                    }
+                });
+            });
+        });
+    state.open = open;
+}
+
+///The selected candy's page: discovery, production count, and every recipe with its processors and ingredient tree.
+fn candy_entry(ui: &mut egui::Ui, item: ResourceItemType, book: &RecipeBook, research: &Research) {
+    ui.heading(item.info().name);
+    ui.label(if research.knows(book, item) {"Discovered"} else {"Not yet discovered"});
+    ui.label(format!("Produced: {}", research.produced(item)));
+    for (index, recipe) in book.recipes_for(item).enumerate() {
+        ui.separator();
+        ui.label(format!("Recipe {}: {}", index + 1, processor_chain(recipe)));
+        ingredient_tree(ui, recipe, book, 0);
+    }
+}
+
+///The production planner for the selected candy: what it takes to make the chosen amount per minute.
+fn plan_entry(ui: &mut egui::Ui, item: ResourceItemType, book: &RecipeBook, rate: &mut f32) {
+    ui.horizontal(|ui| {
+        ui.label("Plan for");
+        ui.add(egui::DragValue::new(rate).range(0.1..=1000.).speed(0.5));
+        ui.label("per minute");
+    });
+    let plan = plan(book, item, *rate);
+    ui.label("Raw resources per minute:");
+    for (kind, per_minute) in &plan.raw {
+        ui.label(format!("  {}: {:.1}", ingredient_name(kind), per_minute));
+    }
+    ui.label("Made per minute:");
+    for (made, per_minute) in &plan.made {
+        ui.label(format!("  {}: {:.1}", made.info().name, per_minute));
+    }
+    let mut processors: Vec<_> = plan.processors.iter().collect();
+    processors.sort_by_key(|(processor, _)| processor.name());
+    let processors: Vec<String> = processors.into_iter().map(|(processor, count)| format!("{} {}", count, processor.name())).collect();
+    ui.label(format!("Processors: {}", processors.join(", ")));
+    ui.label(format!("Harvesters: {}  Factory workers: {}", plan.harvesters, plan.factory_workers));
+}
+
+///Lists a recipe's ingredients, expanding processed ones into the recipe that makes them and raw ones into where they spawn.
+fn ingredient_tree(ui: &mut egui::Ui, recipe: &Recipe, book: &RecipeBook, depth: usize) {
+    for ingredient in &recipe.ingredients {
+        let label = format!("{} x{}", ingredient_name(&ingredient.kind), ingredient.amount);
+        match &ingredient.kind {
+            IngredientKind::Item(item @ ResourceItemType::Processed(_)) if depth < MAX_TREE_DEPTH => {
+                match book.recipes_for(*item).next() {
+                    Some(sub_recipe) => {
+                        egui::CollapsingHeader::new(format!("{} ({})", label, processor_chain(sub_recipe)))
+                            .id_source((depth, item.info().name, recipe.output.info().name))
+                            .show(ui, |ui| ingredient_tree(ui, sub_recipe, book, depth + 1));
+                    },
+                    None => {ui.label(label);},
+                }
+            },
+            IngredientKind::Item(item) => {ui.label(format!("{}: {}", label, source(*item)));},
+            _ => {ui.label(label);},
+        }
+    }
+}
+
+///Names an ingredient slot the way the recipe file writes it.
+fn ingredient_name(kind: &IngredientKind) -> String {
+    match kind {
+        IngredientKind::Item(item) => item.info().name.to_string(),
+        IngredientKind::Tag(tag) => format!("any {}", tag.name()),
+        IngredientKind::AnyOf(items) => items.iter().map(|item| item.info().name).collect::<Vec<_>>().join(" or "),
+    }
+}
+
+///The processors a recipe passes through, in order.
+fn processor_chain(recipe: &Recipe) -> String {
+    recipe.processors.iter().map(|processor| processor.name()).collect::<Vec<_>>().join(" > ")
+}
+
+///Where a raw item can be found.
+fn source(item: ResourceItemType) -> String {
+    match item {
+        ResourceItemType::Water => "any water tile".to_string(),
+        ResourceItemType::Bee(_) => "raiding beeple billages".to_string(),
+        ResourceItemType::Processed(_) => "made in processors".to_string(),
+        _ => {
+            let biomes = spawn_biomes(item);
+            if biomes.is_empty() {"found nowhere".to_string()}
+            else {biomes.iter().map(|biome| format!("{:?}", biome)).collect::<Vec<_>>().join(", ")}
+        },
+    }
+}
@@ -0,0 +1,412 @@
+use crate::tile_data::*;
+
+///Sprite sheet index of an item without its own icon yet: the first blank cell after the loonka sprites.
+///Processed goods have no art in the sprite sheet yet, so they all use it.
+pub const PLACEHOLDER_ICON_INDEX: usize = 264;
+
+///Stores type of an intermediate or finished good made by processors.
+#[derive(PartialEq, Hash, Clone, Copy, Debug)]
+pub enum ProcessedType {
+    //Intermediates
+    LiquidSugar, Caramel, Ice, RoastedNuts, RoastedAlmonds, RoastedHazelnuts, RoastedChestnuts, LiquidNutMilk, LiquidMilk, LiquidCream,
+    CandiedFruit, CandiedCoconut, CandiedPlum, CocoaPowder, Raisins, LiquidJelly, LiquidChocolate, Fondant,
+    FruitExtract, RaspberryExtract, PineappleExtract, MintExtract, PeppermintExtract, GingerExtract, CoffeeExtract, LicoriceExtract, MaltExtract, Sourspice,
+    //Candies
+    Toffee, Marzipan, Calisson, CottonCandy, CaramelFruit, Nougat, Taffy, SaltwaterTaffy, DarkChocolate, Chocolate, WhiteChocolate, ChocolateBar,
+    ChocoNuts, FruitJelly, JellyCandies, CoffeeHardCandy, FruitHardCandy, CandyCanes, GummyBears, SourGummyBears, GummyWorms, SourGummyWorms,
+    MintCandy, RockCandy, Polkagris, Poz, SoftLicorice, HardLicorice, SaltedSoftLicorice, SaltedHardLicorice, ChewingGum, TurkishDelight, IceCream,
+    NutBrittle, VioletteDeToulouse, ChocolateTruffle, Coucougnette, MarronGlace, Szaloncukor, FerrariRichards, JordanAlmonds, Raffiellis, Hopjes,
+    Fudge, Krowki, PtasieMleczko, ChocolateWater, Pastila, DamnBar, TobysOwn, Bentie, FruitSally, BlackJake, Cronchie, MaltBalls, MarksBar,
+    Skittlers, MapleTaffy, Saladitos, ChocolateRaisins, HoneyBar, OperaCream, ReesesRounds, PeppermintBark, JellyTobys, TobysRolls, Gobstoppers,
+    SourApacheBits, JellyBeans,
+}
+impl Eq for ProcessedType {} //partialEq is implemented
+
+///Categories an item belongs to, used by recipes asking for e.g. "any nut".
+#[derive(PartialEq, Hash, Clone, Copy, Debug)]
+pub enum ItemTag {
+    Raw, Intermediate, Candy, //stage of production, every item has exactly one
+    Nut, Berry, Fruit, Citrus, Mineral, Spice, Mint, Beeple, Sugar, Extract, Chocolate, RoastedNut, Liquid,
+}
+impl Eq for ItemTag {} //partialEq is implemented
+
+impl ItemTag {
+    pub const ALL: [ItemTag; 16] = [Raw, Intermediate, Candy, Nut, Berry, Fruit, Citrus, Mineral, Spice, Mint, Beeple, Sugar, Extract, Chocolate, RoastedNut, Liquid];
+
+    ///The name recipes use for the category, e.g. "roasted nut".
+    pub fn name(self) -> &'static str {
+        match self {
+            Raw => "raw",
+            Intermediate => "intermediate",
+            Candy => "candy",
+            Nut => "nut",
+            Berry => "berry",
+            Fruit => "fruit",
+            Citrus => "citrus",
+            Mineral => "mineral",
+            Spice => "spice",
+            Mint => "mint",
+            Beeple => "beeple",
+            Sugar => "sugar",
+            Extract => "extract",
+            Chocolate => "chocolate",
+            RoastedNut => "roasted nut",
+            Liquid => "liquid",
+        }
+    }
+
+    ///Finds a category by the name recipes use for it.
+    pub fn from_name(name: &str) -> Option<ItemTag> {
+        let name = name.to_lowercase();
+        ItemTag::ALL.into_iter().find(|tag| tag.name() == name)
+    }
+}
+
+///How good a batch of goods is, from 0 to 100. Harvesters and factory workers set it, outposts pay for it.
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct Quality(u32);
+
+impl Default for Quality {
+    fn default() -> Self {
+        Quality::STANDARD
+    }
+}
+
+impl Quality {
+    ///Quality of goods nobody in particular worked on.
+    pub const STANDARD: Quality = Quality(50);
+
+    pub fn new(value: i32) -> Quality {
+        Quality(value.clamp(0, 100) as u32)
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    ///Quality of goods harvested from a tile with the given modifier by a harvester with the given dexterity.
+    pub fn harvested(tile_mod: i32, dexterity: i32) -> Quality {
+        Quality::new((tile_mod + dexterity) / 2)
+    }
+
+    ///Quality after a processing step by workers of the given average dexterity.
+    ///Workers of 50 dexterity keep it as it is, better ones improve it and worse ones spoil it.
+    pub fn worked(self, skill: i32) -> Quality {
+        Quality::new(self.0 as i32 + (skill - 50) / 5)
+    }
+
+    ///Quality of two lots of goods mixed together.
+    pub fn blend(self, count: u32, other: Quality, other_count: u32) -> Quality {
+        if count + other_count == 0 {return self;}
+        Quality((self.0 * count + other.0 * other_count) / (count + other_count))
+    }
+
+    ///Grade shown to the player.
+    pub fn grade(self) -> &'static str {
+        match self.0 {
+            0..=29 => "Poor",
+            30..=59 => "Common",
+            60..=84 => "Fine",
+            _ => "Superb",
+        }
+    }
+
+    ///Multiplies the sale price: half price for the worst goods, one and a half for the best.
+    pub fn price_multiplier(self) -> f32 {
+        0.5 + self.0 as f32 / 100.
+    }
+}
+
+///Display data of an item.
+#[derive(Clone, Copy, Debug)]
+pub struct ItemInfo {
+    pub name: &'static str,
+    pub tags: &'static [ItemTag],
+    pub value: u32, //base sale price in gold
+    pub icon: usize, //sprite sheet index
+}
+
+use ItemTag::*;
+
+impl ResourceItemType {
+    ///Returns the display name, tags, base value and icon of the item.
+    pub fn info(self) -> ItemInfo {
+        let (name, tags, value): (&'static str, &'static [ItemTag], u32) = match self {
+            ResourceItemType::None => ("Nothing", &[], 0),
+            ResourceItemType::Water => ("Water", &[Raw, Liquid], 1),
+            ResourceItemType::Nut(nut) => match nut {
+                NutType::Walnut => ("Walnut", &[Raw, Nut], 2),
+                NutType::Chestnut => ("Chestnut", &[Raw, Nut], 2),
+                NutType::Hazelnut => ("Hazelnut", &[Raw, Nut], 2),
+                NutType::Macademia => ("Macademia", &[Raw, Nut], 3),
+                NutType::Pistachio => ("Pistachio", &[Raw, Nut], 3),
+                NutType::Cocoa => ("Cocoa", &[Raw], 3), //a bean, it can't be roasted like the other nuts
+                NutType::Almond => ("Almond", &[Raw, Nut], 2),
+                NutType::Peanut => ("Peanut", &[Raw, Nut], 1),
+            },
+            ResourceItemType::Bush(bush) => match bush {
+                BushType::Strawberry => ("Strawberry", &[Raw, Berry], 2),
+                BushType::Blueberry => ("Blueberry", &[Raw, Berry], 2),
+                BushType::Blackberry => ("Blackberry", &[Raw, Berry], 2),
+                BushType::Grapes => ("Grapes", &[Raw, Berry], 2),
+                BushType::Raspberry => ("Raspberry", &[Raw, Berry], 2),
+                BushType::Coffee => ("Coffee beans", &[Raw], 3),
+                BushType::Marshmallow => ("Marshmallow", &[Raw], 3),
+                BushType::Rhubarb => ("Rhubarb", &[Raw], 2),
+            },
+            ResourceItemType::Fruit(fruit) => match fruit {
+                FruitType::Banana => ("Banana", &[Raw, Fruit], 2),
+                FruitType::Coconut => ("Coconut", &[Raw, Fruit], 3),
+                FruitType::Cherry => ("Cherry", &[Raw, Fruit], 2),
+                FruitType::Apple => ("Apple", &[Raw, Fruit], 1),
+                FruitType::Peach => ("Peach", &[Raw, Fruit], 2),
+                FruitType::Plum => ("Plum", &[Raw, Fruit], 2),
+                FruitType::Apricot => ("Apricot", &[Raw, Fruit], 2),
+                FruitType::Pear => ("Pear", &[Raw, Fruit], 1),
+                FruitType::Elderberry => ("Elderberry", &[Raw, Fruit], 2),
+                FruitType::Goji => ("Goji", &[Raw, Fruit], 3),
+                FruitType::Lemon => ("Lemon", &[Raw, Fruit, Citrus], 2),
+                FruitType::Lime => ("Lime", &[Raw, Fruit, Citrus], 2),
+                FruitType::MapleSyrup => ("Maple syrup", &[Raw, Liquid], 3),
+                FruitType::Orange => ("Orange", &[Raw, Fruit, Citrus], 2),
+                FruitType::Pineapple => ("Pineapple", &[Raw, Fruit], 3),
+            },
+            ResourceItemType::Mine(mine) => match mine {
+                MineType::Sugar => ("Sugar", &[Raw, Mineral, Sugar], 2),
+                MineType::JellyCrystals => ("Jelly crystals", &[Raw, Mineral], 3),
+                MineType::Salt => ("Salt", &[Raw, Mineral], 1),
+                MineType::Milkgem => ("Milkgem", &[Raw, Mineral], 3),
+                MineType::Maltgem => ("Maltgem", &[Raw, Mineral], 3),
+            },
+            ResourceItemType::Spice(spice) => match spice {
+                SpiceType::Cinammon => ("Cinammon", &[Raw, Spice], 2),
+                SpiceType::Ginger => ("Ginger", &[Raw, Spice], 2),
+                SpiceType::EssenceOfBear => ("Essence of bear", &[Raw, Spice], 4),
+                SpiceType::EssenceOfWorm => ("Essence of worm", &[Raw, Spice], 4),
+                SpiceType::Peppermint => ("Peppermint", &[Raw, Spice, Mint], 2),
+                SpiceType::Icemint => ("Icemint", &[Raw, Spice, Mint], 2),
+                SpiceType::Seamint => ("Seamint", &[Raw, Spice, Mint], 3),
+                SpiceType::Spearmint => ("Spearmint", &[Raw, Spice, Mint], 2),
+                SpiceType::GumgumBush => ("Gum-gum", &[Raw, Spice], 3),
+                SpiceType::Rose => ("Rose", &[Raw, Spice], 2),
+                SpiceType::LicoriceRoot => ("Licorice root", &[Raw, Spice], 2),
+                SpiceType::Violet => ("Violet", &[Raw, Spice], 2),
+                SpiceType::Vanilla => ("Vanilla", &[Raw, Spice], 3),
+            },
+            ResourceItemType::Bee(bee) => match bee {
+                BeeType::Honey => ("Honey", &[Raw, Beeple, Liquid], 4),
+                BeeType::ButterbeeButter => ("Butterbee butter", &[Raw, Beeple], 4),
+                BeeType::Molasses => ("Molasses", &[Raw, Beeple, Liquid], 4),
+                BeeType::Wafer => ("Wafer", &[Raw, Beeple], 4),
+                BeeType::Honeycomb => ("Honeycomb", &[Raw, Beeple], 5),
+            },
+            ResourceItemType::Processed(processed) => processed.info(),
+        };
+        ItemInfo { name, tags, value, icon: self.icon() }
+    }
+
+    ///Returns true if the item has the given tag.
+    pub fn has_tag(self, tag: ItemTag) -> bool {
+        self.info().tags.contains(&tag)
+    }
+
+    ///Sprite sheet index of the item, the same sprite harvestable tiles of that item show on the map.
+    fn icon(self) -> usize {
+        match self {
+            ResourceItemType::Water => 28,
+            ResourceItemType::Nut(_) => 120,
+            ResourceItemType::Bush(_) => 132,
+            ResourceItemType::Fruit(_) => 108,
+            ResourceItemType::Mine(_) => 96,
+            ResourceItemType::Spice(SpiceType::Seamint) => 149,
+            ResourceItemType::Spice(_) => 144,
+            ResourceItemType::Bee(_) => 85, //beeple billage
+            ResourceItemType::None | ResourceItemType::Processed(_) => PLACEHOLDER_ICON_INDEX,
+        }
+    }
+
+    ///Returns every item in the game, raw goods first.
+    pub fn all() -> Vec<ResourceItemType> {
+        let nuts = [NutType::Walnut, NutType::Chestnut, NutType::Hazelnut, NutType::Macademia, NutType::Pistachio, NutType::Cocoa, NutType::Almond, NutType::Peanut];
+        let bushes = [BushType::Strawberry, BushType::Blueberry, BushType::Blackberry, BushType::Grapes, BushType::Raspberry, BushType::Coffee, BushType::Marshmallow, BushType::Rhubarb];
+        let fruits = [FruitType::Banana, FruitType::Coconut, FruitType::Cherry, FruitType::Apple, FruitType::Peach, FruitType::Plum, FruitType::Apricot, FruitType::Pear,
+            FruitType::Elderberry, FruitType::Goji, FruitType::Lemon, FruitType::Lime, FruitType::MapleSyrup, FruitType::Orange, FruitType::Pineapple];
+        let mines = [MineType::Sugar, MineType::JellyCrystals, MineType::Salt, MineType::Milkgem, MineType::Maltgem];
+        let spices = [SpiceType::Cinammon, SpiceType::Ginger, SpiceType::EssenceOfBear, SpiceType::EssenceOfWorm, SpiceType::Peppermint, SpiceType::Icemint, SpiceType::Seamint,
+            SpiceType::Spearmint, SpiceType::GumgumBush, SpiceType::Rose, SpiceType::LicoriceRoot, SpiceType::Violet, SpiceType::Vanilla];
+        let bees = [BeeType::Honey, BeeType::ButterbeeButter, BeeType::Molasses, BeeType::Wafer, BeeType::Honeycomb];
+        [ResourceItemType::Water].into_iter()
+            .chain(nuts.into_iter().map(ResourceItemType::Nut))
+            .chain(bushes.into_iter().map(ResourceItemType::Bush))
+            .chain(fruits.into_iter().map(ResourceItemType::Fruit))
+            .chain(mines.into_iter().map(ResourceItemType::Mine))
+            .chain(spices.into_iter().map(ResourceItemType::Spice))
+            .chain(bees.into_iter().map(ResourceItemType::Bee))
+            .chain(ProcessedType::ALL.into_iter().map(ResourceItemType::Processed))
+            .collect()
+    }
+
+    ///Finds an item by its display name, ignoring case.
+    pub fn from_name(name: &str) -> Option<ResourceItemType> {
+        ResourceItemType::all().into_iter().find(|item| item.info().name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl ProcessedType {
+    pub const ALL: [ProcessedType; 97] = [
+        ProcessedType::LiquidSugar, ProcessedType::Caramel, ProcessedType::Ice, ProcessedType::RoastedNuts, ProcessedType::RoastedAlmonds,
+        ProcessedType::RoastedHazelnuts, ProcessedType::RoastedChestnuts, ProcessedType::LiquidNutMilk, ProcessedType::LiquidMilk, ProcessedType::LiquidCream,
+        ProcessedType::CandiedFruit, ProcessedType::CandiedCoconut, ProcessedType::CandiedPlum, ProcessedType::CocoaPowder, ProcessedType::Raisins,
+        ProcessedType::LiquidJelly, ProcessedType::LiquidChocolate, ProcessedType::Fondant, ProcessedType::FruitExtract, ProcessedType::RaspberryExtract,
+        ProcessedType::PineappleExtract, ProcessedType::MintExtract, ProcessedType::PeppermintExtract, ProcessedType::GingerExtract, ProcessedType::CoffeeExtract,
+        ProcessedType::LicoriceExtract, ProcessedType::MaltExtract, ProcessedType::Sourspice,
+        ProcessedType::Toffee, ProcessedType::Marzipan, ProcessedType::Calisson, ProcessedType::CottonCandy, ProcessedType::CaramelFruit,
+        ProcessedType::Nougat, ProcessedType::Taffy, ProcessedType::SaltwaterTaffy, ProcessedType::DarkChocolate, ProcessedType::Chocolate,
+        ProcessedType::WhiteChocolate, ProcessedType::ChocolateBar, ProcessedType::ChocoNuts, ProcessedType::FruitJelly, ProcessedType::JellyCandies,
+        ProcessedType::CoffeeHardCandy, ProcessedType::FruitHardCandy, ProcessedType::CandyCanes, ProcessedType::GummyBears, ProcessedType::SourGummyBears,
+        ProcessedType::GummyWorms, ProcessedType::SourGummyWorms, ProcessedType::MintCandy, ProcessedType::RockCandy, ProcessedType::Polkagris,
+        ProcessedType::Poz, ProcessedType::SoftLicorice, ProcessedType::HardLicorice, ProcessedType::SaltedSoftLicorice, ProcessedType::SaltedHardLicorice,
+        ProcessedType::ChewingGum, ProcessedType::TurkishDelight, ProcessedType::IceCream, ProcessedType::NutBrittle, ProcessedType::VioletteDeToulouse,
+        ProcessedType::ChocolateTruffle, ProcessedType::Coucougnette, ProcessedType::MarronGlace, ProcessedType::Szaloncukor, ProcessedType::FerrariRichards,
+        ProcessedType::JordanAlmonds, ProcessedType::Raffiellis, ProcessedType::Hopjes, ProcessedType::Fudge, ProcessedType::Krowki,
+        ProcessedType::PtasieMleczko, ProcessedType::ChocolateWater, ProcessedType::Pastila, ProcessedType::DamnBar, ProcessedType::TobysOwn,
+        ProcessedType::Bentie, ProcessedType::FruitSally, ProcessedType::BlackJake, ProcessedType::Cronchie, ProcessedType::MaltBalls,
+        ProcessedType::MarksBar, ProcessedType::Skittlers, ProcessedType::MapleTaffy, ProcessedType::Saladitos, ProcessedType::ChocolateRaisins,
+        ProcessedType::HoneyBar, ProcessedType::OperaCream, ProcessedType::ReesesRounds, ProcessedType::PeppermintBark, ProcessedType::JellyTobys,
+        ProcessedType::TobysRolls, ProcessedType::Gobstoppers, ProcessedType::SourApacheBits, ProcessedType::JellyBeans,
+    ];
+
+    fn info(self) -> (&'static str, &'static [ItemTag], u32) {
+        match self {
+            ProcessedType::LiquidSugar => ("Liquid sugar", &[Intermediate, Sugar, Liquid], 4),
+            ProcessedType::Caramel => ("Caramel", &[Intermediate], 6),
+            ProcessedType::Ice => ("Ice", &[Intermediate], 2),
+            ProcessedType::RoastedNuts => ("Roasted nuts", &[Intermediate, RoastedNut], 4),
+            ProcessedType::RoastedAlmonds => ("Roasted almonds", &[Intermediate, RoastedNut], 4),
+            ProcessedType::RoastedHazelnuts => ("Roasted hazelnuts", &[Intermediate, RoastedNut], 4),
+            ProcessedType::RoastedChestnuts => ("Roasted chestnuts", &[Intermediate, RoastedNut], 4),
+            ProcessedType::LiquidNutMilk => ("Liquid nut milk", &[Intermediate, Liquid], 6),
+            ProcessedType::LiquidMilk => ("Liquid milk", &[Intermediate, Liquid], 6),
+            ProcessedType::LiquidCream => ("Liquid cream", &[Intermediate, Liquid], 8),
+            ProcessedType::CandiedFruit => ("Candied fruit", &[Intermediate], 4),
+            ProcessedType::CandiedCoconut => ("Candied coconut", &[Intermediate], 5),
+            ProcessedType::CandiedPlum => ("Candied plum", &[Intermediate], 4),
+            ProcessedType::CocoaPowder => ("Cocoa powder", &[Intermediate], 5),
+            ProcessedType::Raisins => ("Raisins", &[Intermediate], 4),
+            ProcessedType::LiquidJelly => ("Liquid jelly", &[Intermediate, Liquid], 6),
+            ProcessedType::LiquidChocolate => ("Liquid chocolate", &[Intermediate, Liquid], 16),
+            ProcessedType::Fondant => ("Fondant", &[Intermediate], 14),
+            ProcessedType::FruitExtract => ("Fruit extract", &[Intermediate, Extract], 4),
+            ProcessedType::RaspberryExtract => ("Raspberry extract", &[Intermediate, Extract], 4),
+            ProcessedType::PineappleExtract => ("Pineapple extract", &[Intermediate, Extract], 5),
+            ProcessedType::MintExtract => ("Mint extract", &[Intermediate, Extract], 4),
+            ProcessedType::PeppermintExtract => ("Peppermint extract", &[Intermediate, Extract], 4),
+            ProcessedType::GingerExtract => ("Ginger extract", &[Intermediate, Extract], 4),
+            ProcessedType::CoffeeExtract => ("Coffee extract", &[Intermediate, Extract], 5),
+            ProcessedType::LicoriceExtract => ("Licorice extract", &[Intermediate, Extract], 4),
+            ProcessedType::MaltExtract => ("Malt extract", &[Intermediate, Extract], 5),
+            ProcessedType::Sourspice => ("Sourspice", &[Intermediate, Extract], 4),
+            ProcessedType::Toffee => ("Toffee", &[Candy], 18),
+            ProcessedType::Marzipan => ("Marzipan", &[Candy], 8),
+            ProcessedType::Calisson => ("Calisson", &[Candy], 22),
+            ProcessedType::CottonCandy => ("Cotton candy", &[Candy], 6),
+            ProcessedType::CaramelFruit => ("Caramel fruit", &[Candy], 10),
+            ProcessedType::Nougat => ("Nougat", &[Candy], 16),
+            ProcessedType::Taffy => ("Taffy", &[Candy], 14),
+            ProcessedType::SaltwaterTaffy => ("Saltwater taffy", &[Candy], 16),
+            ProcessedType::DarkChocolate => ("Dark chocolate", &[Candy, Chocolate], 12),
+            ProcessedType::Chocolate => ("Chocolate", &[Candy, Chocolate], 14),
+            ProcessedType::WhiteChocolate => ("White chocolate", &[Candy, Chocolate], 16),
+            ProcessedType::ChocolateBar => ("Chocolate bar", &[Candy], 18),
+            ProcessedType::ChocoNuts => ("Choco-nuts", &[Candy], 22),
+            ProcessedType::FruitJelly => ("Fruit jelly", &[Candy], 14),
+            ProcessedType::JellyCandies => ("Jelly candies", &[Candy], 12),
+            ProcessedType::CoffeeHardCandy => ("Coffee hard candy", &[Candy], 16),
+            ProcessedType::FruitHardCandy => ("Fruit hard candy", &[Candy], 12),
+            ProcessedType::CandyCanes => ("Candy canes", &[Candy], 12),
+            ProcessedType::GummyBears => ("Gummy bears", &[Candy], 18),
+            ProcessedType::SourGummyBears => ("Sour gummy bears", &[Candy], 24),
+            ProcessedType::GummyWorms => ("Gummy worms", &[Candy], 18),
+            ProcessedType::SourGummyWorms => ("Sour gummy worms", &[Candy], 24),
+            ProcessedType::MintCandy => ("Mint candy", &[Candy], 10),
+            ProcessedType::RockCandy => ("Rock candy", &[Candy], 10),
+            ProcessedType::Polkagris => ("Polkagris", &[Candy], 10),
+            ProcessedType::Poz => ("Poz", &[Candy], 8),
+            ProcessedType::SoftLicorice => ("Soft licorice", &[Candy], 12),
+            ProcessedType::HardLicorice => ("Hard licorice", &[Candy], 14),
+            ProcessedType::SaltedSoftLicorice => ("Salted soft licorice", &[Candy], 14),
+            ProcessedType::SaltedHardLicorice => ("Salted hard licorice", &[Candy], 16),
+            ProcessedType::ChewingGum => ("Chewing gum", &[Candy], 12),
+            ProcessedType::TurkishDelight => ("Turkish delight", &[Candy], 20),
+            ProcessedType::IceCream => ("Ice cream", &[Candy], 26),
+            ProcessedType::NutBrittle => ("Nut brittle", &[Candy], 10),
+            ProcessedType::VioletteDeToulouse => ("Les violettes de Toulouse", &[Candy], 8),
+            ProcessedType::ChocolateTruffle => ("Chocolate truffle", &[Candy], 40),
+            ProcessedType::Coucougnette => ("Coucougnette", &[Candy], 40),
+            ProcessedType::MarronGlace => ("Marron glace", &[Candy], 10),
+            ProcessedType::Szaloncukor => ("Szaloncukor", &[Candy], 32),
+            ProcessedType::FerrariRichards => ("Ferrari richards", &[Candy], 40),
+            ProcessedType::JordanAlmonds => ("Jordan almonds", &[Candy], 8),
+            ProcessedType::Raffiellis => ("Raffielli's", &[Candy], 30),
+            ProcessedType::Hopjes => ("Hopjes", &[Candy], 26),
+            ProcessedType::Fudge => ("Fudge", &[Candy], 16),
+            ProcessedType::Krowki => ("Krowki", &[Candy], 40),
+            ProcessedType::PtasieMleczko => ("Ptasie mleczko", &[Candy], 20),
+            ProcessedType::ChocolateWater => ("Chocolate water", &[Candy], 20),
+            ProcessedType::Pastila => ("Pastila", &[Candy], 20),
+            ProcessedType::DamnBar => ("Damn bar", &[Candy], 28),
+            ProcessedType::TobysOwn => ("Toby's own", &[Candy], 40),
+            ProcessedType::Bentie => ("Bentie", &[Candy], 24),
+            ProcessedType::FruitSally => ("Fruit sally", &[Candy], 20),
+            ProcessedType::BlackJake => ("Black jake", &[Candy], 16),
+            ProcessedType::Cronchie => ("Cronchie", &[Candy], 22),
+            ProcessedType::MaltBalls => ("Malt balls", &[Candy], 26),
+            ProcessedType::MarksBar => ("Mark's bar", &[Candy], 40),
+            ProcessedType::Skittlers => ("Skittlers", &[Candy], 12),
+            ProcessedType::MapleTaffy => ("Maple taffy", &[Candy], 8),
+            ProcessedType::Saladitos => ("Saladitos", &[Candy], 8),
+            ProcessedType::ChocolateRaisins => ("Chocolate raisins", &[Candy], 22),
+            ProcessedType::HoneyBar => ("Honey bar", &[Candy], 24),
+            ProcessedType::OperaCream => ("Opera cream", &[Candy], 40),
+            ProcessedType::ReesesRounds => ("Reese's rounds", &[Candy], 24),
+            ProcessedType::PeppermintBark => ("Peppermint bark", &[Candy], 48),
+            ProcessedType::JellyTobys => ("Jelly toby's", &[Candy], 16),
+            ProcessedType::TobysRolls => ("Toby's rolls", &[Candy], 30),
+            ProcessedType::Gobstoppers => ("Gobstoppers", &[Candy], 18),
+            ProcessedType::SourApacheBits => ("Sour apache bits", &[Candy], 18),
+            ProcessedType::JellyBeans => ("Jelly beans", &[Candy], 20),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_names_are_unique() {
+        let items = ResourceItemType::all();
+        for item in &items {
+            assert_eq!(ResourceItemType::from_name(item.info().name), Some(*item), "{} is not a unique name", item.info().name);
+        }
+    }
+
+    #[test]
+    fn every_item_has_exactly_one_stage() {
+        for item in ResourceItemType::all() {
+            let stages = [Raw, Intermediate, Candy].iter().filter(|stage| item.has_tag(**stage)).count();
+            assert_eq!(stages, 1, "{} has {} stages", item.info().name, stages);
+            assert!(item.info().value > 0, "{} is worthless", item.info().name);
+        }
+    }
+
+    #[test]
+    fn quality_blends_and_stays_in_range() {
+        assert_eq!(Quality::new(20).blend(3, Quality::new(60), 1), Quality::new(30));
+        assert_eq!(Quality::new(95).worked(100), Quality::new(100));
+        assert_eq!(Quality::new(3).worked(25), Quality::new(0));
+        assert_eq!(Quality::STANDARD.worked(50), Quality::STANDARD);
+        assert_eq!(Quality::harvested(100, 50).grade(), "Fine");
+        assert_eq!(Quality::STANDARD.grade(), "Common");
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{default, Changed, Commands, DetectChanges, Entity, Query, Res, ResMut, Resource, Sprite, SpriteBundle, Transform, Vec2};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::items::ItemTag;
+use crate::map_grid::{grid_to_world, world_to_grid, MapGrid};
+use crate::processors::Processor;
+use crate::recipes::{Ingredient, IngredientKind, Recipe, RecipeBook};
+use crate::research::Research;
+use crate::stockpile::Stockpile;
+use crate::tile_data::*;
+use crate::walkers::{carry_capacity, route_cost, Travellers, WalkRegions, Walker, WALKER_COLOR};
+use crate::{PlayerStats, SPRITE_SCALE_FACTOR, TILE_WIDTH};
+
+///Batches' worth of ingredients processors ask to keep in stock.
+pub const REQUEST_BATCHES: u32 = 2;
+///Candy outposts ask to keep in stock for selling.
+pub const OUTPOST_REQUEST: u32 = 10;
+///Seconds between rounds of matching requests to offers.
+pub const DISPATCH_INTERVAL: f32 = 1.;
+///Priority of processors' requests for ingredients, which keep production going.
+pub const PROCESSOR_PRIORITY: u32 = 2;
+///Priority of outposts' requests for candy to sell.
+pub const OUTPOST_PRIORITY: u32 = 1;
+
+///A stockpile asking for items to be brought to it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Request {
+    pub pos: (usize, usize), //grid coordinates of the tile asking
+    pub wants: Ingredient, //what it takes, and how many are still needed
+    pub priority: u32, //higher is filled first
+}
+
+///Items a stockpile has spare for the network to take.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Offer {
+    pub pos: (usize, usize),
+    pub item: ResourceItemType,
+    pub amount: u32,
+}
+
+///Items on their way from an offer to a request.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Delivery {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub item: ResourceItemType,
+    pub amount: u32,
+}
+
+///A walk from an offer to a request, as the grid coordinates of each end.
+pub type Leg = ((usize, usize), (usize, usize));
+
+///The logistics network's latest requests and offers, and whether its view is open. L opens and closes the view.
+#[derive(Resource, Default, Debug)]
+pub struct Logistics {
+    pub open: bool,
+    pub requests: Vec<Request>, //still open after the deliveries on their way
+    pub offers: Vec<Offer>,
+    pub sites: BTreeMap<(usize, usize), Entity>, //tiles with stock, a processor or an outpost, the only ones that can offer or ask
+    pub route_costs: HashMap<Leg, Option<u32>>, //walks already searched for
+}
+
+///What a processor asks for: the ingredients of the first of its recipes that can be made from its stock and what's on offer,
+///enough for a couple of batches.
+pub fn processor_requests(pos: (usize, usize), recipes: &[&Recipe], stock: &Stockpile, offers: &[Offer]) -> Vec<Request> {
+    let available = |ingredient: &Ingredient| stock.iter().chain(offers.iter().map(|offer| (offer.item, offer.amount)))
+        .filter(|(item, _)| ingredient.accepts(*item))
+        .map(|(_, amount)| amount)
+        .sum::<u32>() >= ingredient.amount;
+    let Some(recipe) = recipes.iter().find(|recipe| recipe.ingredients.iter().all(available)) else {return vec![];};
+    recipe.ingredients.iter()
+        .filter_map(|ingredient| {
+            let stored: u32 = stock.iter().filter(|(item, _)| ingredient.accepts(*item)).map(|(_, count)| count).sum();
+            let missing = (ingredient.amount * REQUEST_BATCHES).checked_sub(stored).filter(|missing| *missing > 0)?;
+            Some(Request { pos, wants: Ingredient { kind: ingredient.kind.clone(), amount: missing }, priority: PROCESSOR_PRIORITY })
+        })
+        .collect()
+}
+
+///What an outpost asks for: candy to sell, up to a stock of `OUTPOST_REQUEST`.
+pub fn outpost_request(pos: (usize, usize), stock: &Stockpile) -> Option<Request> {
+    let wants = Ingredient { kind: IngredientKind::Tag(ItemTag::Candy), amount: OUTPOST_REQUEST };
+    let stored: u32 = stock.iter().filter(|(item, _)| wants.accepts(*item)).map(|(_, count)| count).sum();
+    let missing = OUTPOST_REQUEST.checked_sub(stored).filter(|missing| *missing > 0)?;
+    Some(Request { pos, wants: Ingredient { amount: missing, ..wants }, priority: OUTPOST_PRIORITY })
+}
+
+///Takes the deliveries already on their way off the requests they fill. Requests with nothing left to fill are dropped.
+pub fn outstanding(requests: Vec<Request>, deliveries: &[Delivery]) -> Vec<Request> {
+    requests.into_iter()
+        .filter_map(|mut request| {
+            let incoming: u32 = deliveries.iter()
+                .filter(|delivery| delivery.to == request.pos && request.wants.accepts(delivery.item))
+                .map(|delivery| delivery.amount)
+                .sum();
+            request.wants.amount = request.wants.amount.checked_sub(incoming).filter(|left| *left > 0)?;
+            Some(request)
+        })
+        .collect()
+}
+
+///Matches requests to offers for the idle couriers, given as their ids and how much each can carry.
+///Higher priority requests go first, each filled from the closest offer of something it takes, by the courier able to carry the most.
+///`route_cost` gives how hard the walk from an offer to a request is, or None if it can't be walked, in which case the offer is passed over.
+///The offers are used up as they are matched.
+pub fn dispatch(
+    requests: &[Request],
+    offers: &mut [Offer],
+    couriers: &[(usize, u32)],
+    mut route_cost: impl FnMut((usize, usize), (usize, usize)) -> Option<u32>,
+) -> Vec<(usize, Delivery)> {
+    let mut requests: Vec<&Request> = requests.iter().collect();
+    requests.sort_by_key(|request| std::cmp::Reverse(request.priority)); //stable, so equal priorities stay in order
+    let mut couriers = couriers.to_vec();
+    couriers.sort_by_key(|(_, capacity)| std::cmp::Reverse(*capacity));
+    let mut couriers = couriers.into_iter();
+
+    let mut deliveries = vec![];
+    for request in requests {
+        let mut needed = request.wants.amount;
+        let mut closest: Vec<(u32, usize)> = offers.iter().enumerate()
+            .filter(|(_, offer)| offer.amount > 0 && offer.pos != request.pos && request.wants.accepts(offer.item))
+            .filter_map(|(index, offer)| Some((route_cost(offer.pos, request.pos)?, index)))
+            .collect();
+        closest.sort();
+        for (_, index) in closest {
+            let offer = &mut offers[index];
+            while needed > 0 && offer.amount > 0 {
+                let Some((courier, capacity)) = couriers.next() else {return deliveries;};
+                let amount = needed.min(offer.amount).min(capacity);
+                offer.amount -= amount;
+                needed -= amount;
+                deliveries.push((courier, Delivery { from: offer.pos, to: request.pos, item: offer.item, amount }));
+            }
+        }
+    }
+    deliveries
+}
+
+///Recipes processors can make: those in the recipe book that research has discovered.
+#[derive(SystemParam)]
+pub struct KnownRecipes<'w> {
+    recipe_book: Res<'w, RecipeBook>,
+    research: Res<'w, Research>,
+}
+
+impl KnownRecipes<'_> {
+    ///Returns the discovered recipes a processor could run on its current line.
+    fn for_processor(&self, processor: &Processor) -> Vec<&Recipe> {
+        processor.candidate_recipes(&self.recipe_book, self.research.discovered(), &processor.line).into_iter()
+            .map(|index| &self.recipe_book.recipes()[index])
+            .collect()
+    }
+}
+
+///The map's tiles and the processors on them, as logistics reads them.
+#[derive(SystemParam)]
+pub struct SiteTiles<'w, 's> {
+    map_grid: Res<'w, MapGrid>,
+    tile_q: Query<'w, 's, &'static Tile>,
+    changed_tile_q: Query<'w, 's, (Entity, &'static Tile), Changed<Tile>>,
+    processor_q: Query<'w, 's, &'static Processor>,
+}
+
+impl SiteTiles<'_, '_> {
+    ///Cost of walking onto a tile, or None if it can't be walked on.
+    fn walk_cost(&self, pos: (usize, usize)) -> Option<u32> {
+        self.map_grid.get(pos.0, pos.1)
+            .and_then(|entity| self.tile_q.get(entity).ok())
+            .and_then(|tile| tile.base_type.move_cost())
+    }
+}
+
+///Every second, gathers what processors and outposts ask for and what harvest stockpiles have spare,
+///and sends idle travellers to carry goods from offers to the requests that need them most and that they can walk to.
+pub fn run_logistics(
+    mut commands: Commands,
+    known_recipes: KnownRecipes,
+    walk_regions: Res<WalkRegions>,
+    site_tiles: SiteTiles,
+    travellers: Travellers,
+    player_stats_q: Query<&PlayerStats>,
+    mut logistics: ResMut<Logistics>,
+) {
+    let Ok(player_stats) = player_stats_q.get_single() else {return;};
+    let logistics = &mut *logistics;
+
+    //only tiles that changed since last round can have started or stopped offering or asking
+    for (entity, tile) in site_tiles.changed_tile_q.iter() {
+        let Some(pos) = world_to_grid(tile.location.0, tile.location.1) else {continue;};
+        let is_site = tile.stock.total() > 0 || tile.processor.is_some() || tile.outpost.outpost_type != OutpostType::None;
+        if is_site && tile.base_type.move_cost().is_some() { //out of reach on foot otherwise
+            logistics.sites.insert(pos, entity);
+        } else {
+            logistics.sites.remove(&pos);
+        }
+    }
+    if walk_regions.is_changed() {logistics.route_costs.clear();} //a new map
+
+    //harvest stockpiles offer everything in them, processors only what they don't use
+    let mut offers = vec![];
+    let mut wanted_by_processors = vec![];
+    let mut requests = vec![];
+    for (pos, entity) in &logistics.sites {
+        let Ok(tile) = site_tiles.tile_q.get(*entity) else {continue;};
+        match tile.processor.and_then(|entity| site_tiles.processor_q.get(entity).ok()) {
+            Some(processor) => {
+                let recipes = known_recipes.for_processor(processor);
+                let uses = |item| recipes.iter().any(|recipe| recipe.ingredients.iter().any(|ingredient| ingredient.accepts(item)));
+                offers.extend(tile.stock.iter().filter(|(item, _)| !uses(*item)).map(|(item, amount)| Offer { pos: *pos, item, amount }));
+                wanted_by_processors.push((*pos, recipes, &tile.stock));
+            },
+            None if tile.outpost.outpost_type == OutpostType::None => {
+                offers.extend(tile.stock.iter().map(|(item, amount)| Offer { pos: *pos, item, amount }));
+            },
+            None => requests.extend(outpost_request(*pos, &tile.stock)),
+        }
+    }
+    requests.extend(wanted_by_processors.iter()
+        .flat_map(|(pos, recipes, stock)| processor_requests(*pos, recipes, stock, &offers)));
+
+    let delivering: Vec<Delivery> = travellers.walker_q.iter().filter_map(|walker| walker.order).collect();
+    logistics.requests = outstanding(requests, &delivering);
+    logistics.offers = offers;
+
+    let travelling = travellers.loonkas();
+    let couriers: Vec<(usize, u32)> = player_stats.loonkas.iter()
+        .filter(|loonka| loonka.current_job == LoonkaJob::Traveller && loonka.tile.is_none() && !travelling.contains(&loonka.id))
+        .map(|loonka| (loonka.id, carry_capacity(loonka.vigor)))
+        .collect();
+    let walk_cost = |pos| site_tiles.walk_cost(pos);
+    let route_costs = &mut logistics.route_costs;
+    let walk = |from, to| {
+        if !walk_regions.connected(from, to) {return None;}
+        *route_costs.entry((from, to)).or_insert_with(|| route_cost(from, to, walk_cost))
+    };
+    for (courier, delivery) in dispatch(&logistics.requests, &mut logistics.offers, &couriers, walk) {
+        let Some(loonka) = player_stats.loonkas.iter().find(|loonka| loonka.id == courier) else {continue;};
+        let (x, y) = grid_to_world(delivery.from.0 as f32, delivery.from.1 as f32);
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite { color: WALKER_COLOR, custom_size: Some(Vec2::splat((TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32 * 0.3)), ..default() },
+                transform: Transform::from_xyz(x, y, 3.),
+                ..default()
+            },
+            Walker::for_delivery(loonka.id, loonka.vigor, delivery),
+        ));
+    }
+}
+
+///Draws the logistics view while it is open: the requests still waiting for goods and the deliveries on their way.
+pub fn logistics_window(
+    mut contexts: EguiContexts,
+    mut logistics: ResMut<Logistics>,
+    walker_q: Query<&Walker>,
+    player_stats_q: Query<&PlayerStats>,
+) {
+    if !logistics.open {return;}
+    let Ok(player_stats) = player_stats_q.get_single() else {return;};
+    let mut open = logistics.open;
+    egui::Window::new("Logistics").open(&mut open).default_width(380.).show(contexts.ctx_mut(), |ui| {
+        ui.label("Idle travellers carry goods from harvest stockpiles to processors and outposts that ask for them.");
+        ui.heading(format!("Open requests ({})", logistics.requests.len()));
+        egui::ScrollArea::vertical().id_source("logistics_requests").max_height(200.).show(ui, |ui| {
+            for request in &logistics.requests {
+                let wants = match &request.wants.kind {
+                    IngredientKind::Item(item) => item.info().name.to_string(),
+                    IngredientKind::Tag(tag) => format!("any {}", tag.name()),
+                    IngredientKind::AnyOf(items) => items.iter().map(|item| item.info().name).collect::<Vec<_>>().join(" or "),
+                };
+                let asker = if request.priority == PROCESSOR_PRIORITY {"processor"} else {"outpost"};
+                ui.label(format!("({}, {}) {}: {} x{}", request.pos.0, request.pos.1, asker, wants, request.wants.amount));
+            }
+        });
+        let on_offer: u32 = logistics.offers.iter().map(|offer| offer.amount).sum();
+        ui.label(format!("Spare goods on offer: {}", on_offer));
+        ui.separator();
+
+        let deliveries: Vec<(&Walker, Delivery)> = walker_q.iter().filter_map(|walker| Some((walker, walker.order?))).collect();
+        ui.heading(format!("Deliveries ({})", deliveries.len()));
+        egui::ScrollArea::vertical().id_source("logistics_deliveries").max_height(200.).show(ui, |ui| {
+            for (walker, delivery) in deliveries {
+                let name = player_stats.loonkas.iter().find(|loonka| loonka.id == walker.loonka).map_or("?", |loonka| loonka.name.as_str());
+                ui.label(format!("{}: {} x{} from ({}, {}) to ({}, {}), {}",
+                    name, delivery.item.info().name, delivery.amount,
+                    delivery.from.0, delivery.from.1, delivery.to.0, delivery.to.1,
+                    if walker.delivering {"delivering"} else {"collecting"}));
+            }
+        });
+    });
+    logistics.open = open;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::ProcessedType;
+    use crate::recipes::RecipeBook;
+
+    const SUGAR: ResourceItemType = ResourceItemType::Mine(MineType::Sugar);
+    const TOFFEE: ResourceItemType = ResourceItemType::Processed(ProcessedType::Toffee);
+
+    fn offer(pos: (usize, usize), item: ResourceItemType, amount: u32) -> Offer {
+        Offer { pos, item, amount }
+    }
+
+    ///Route costs for open plains, where every step costs 1.
+    fn grid_distance(from: (usize, usize), to: (usize, usize)) -> Option<u32> {
+        Some((from.0.abs_diff(to.0) + from.1.abs_diff(to.1)) as u32)
+    }
+
+    #[test]
+    fn processors_ask_for_what_their_recipes_need() {
+        let (book, _) = RecipeBook::parse(r#"[(output: "Liquid sugar", ingredients: [("Sugar", 3)], processors: ["heater"])]"#);
+        let recipes: Vec<&Recipe> = book.recipes().iter().collect();
+        let mut stock = Stockpile::new(100);
+        assert_eq!(processor_requests((0, 0), &recipes, &stock, &[]), vec![], "nothing to ask for if none is on offer");
+        stock.add(SUGAR, 2);
+        let requests = processor_requests((0, 0), &recipes, &stock, &[offer((5, 5), SUGAR, 1)]);
+        assert_eq!(requests, vec![Request { pos: (0, 0), wants: Ingredient { kind: IngredientKind::Item(SUGAR), amount: 4 }, priority: PROCESSOR_PRIORITY }]);
+        stock.add(SUGAR, 4);
+        assert_eq!(processor_requests((0, 0), &recipes, &stock, &[]), vec![], "two batches in stock is enough");
+
+        let mut outpost = Stockpile::new(100);
+        outpost.add(TOFFEE, 4);
+        assert_eq!(outpost_request((1, 1), &outpost).map(|request| request.wants.amount), Some(OUTPOST_REQUEST - 4));
+    }
+
+    #[test]
+    fn deliveries_fill_the_most_important_requests_from_the_nearest_offers() {
+        let candy = Request { pos: (10, 0), wants: Ingredient { kind: IngredientKind::Tag(ItemTag::Candy), amount: 5 }, priority: OUTPOST_PRIORITY };
+        let sugar = Request { pos: (0, 0), wants: Ingredient { kind: IngredientKind::Item(SUGAR), amount: 6 }, priority: PROCESSOR_PRIORITY };
+        let mut offers = [offer((9, 0), SUGAR, 10), offer((3, 0), SUGAR, 4), offer((20, 0), TOFFEE, 10)];
+        let deliveries = dispatch(&[candy.clone(), sugar.clone()], &mut offers, &[(7, 3), (8, 5), (9, 5)], grid_distance);
+        assert_eq!(deliveries, vec![
+            (8, Delivery { from: (3, 0), to: (0, 0), item: SUGAR, amount: 4 }),
+            (9, Delivery { from: (9, 0), to: (0, 0), item: SUGAR, amount: 2 }),
+            (7, Delivery { from: (20, 0), to: (10, 0), item: TOFFEE, amount: 3 }),
+        ]);
+        assert_eq!(offers[0].amount, 8);
+
+        //the deliveries on their way leave only the rest of the candy to find
+        let delivering: Vec<Delivery> = deliveries.into_iter().map(|(_, delivery)| delivery).collect();
+        let left = outstanding(vec![candy, sugar], &delivering);
+        assert_eq!(left.len(), 1);
+        assert_eq!((left[0].pos, left[0].wants.amount), ((10, 0), 2));
+    }
+
+    #[test]
+    fn offers_are_ranked_by_the_walk_and_skipped_if_out_of_reach() {
+        let sugar = Request { pos: (0, 0), wants: Ingredient { kind: IngredientKind::Item(SUGAR), amount: 4 }, priority: PROCESSOR_PRIORITY };
+        let mut offers = [offer((1, 0), SUGAR, 4), offer((2, 0), SUGAR, 4), offer((6, 0), SUGAR, 4)];
+        //the nearest offer is across the sea, the next is over a mountain range, the farthest is an easy walk
+        let route_cost = |from: (usize, usize), _| match from {
+            (1, 0) => None,
+            (2, 0) => Some(30),
+            _ => Some(6),
+        };
+        let deliveries = dispatch(std::slice::from_ref(&sugar), &mut offers, &[(1, 2), (2, 2)], route_cost);
+        assert_eq!(deliveries, vec![
+            (1, Delivery { from: (6, 0), to: (0, 0), item: SUGAR, amount: 2 }),
+            (2, Delivery { from: (6, 0), to: (0, 0), item: SUGAR, amount: 2 }),
+        ]);
+
+        let mut offers = [offer((1, 0), SUGAR, 4)];
+        assert_eq!(dispatch(&[sugar], &mut offers, &[(1, 2)], route_cost), vec![], "no courier is sent where it can't walk");
+        assert_eq!(offers[0].amount, 4);
+    }
+}
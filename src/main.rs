@@ -1,31 +1,54 @@
 //          IMPORTS
 
-use std::collections::HashMap;
-use std::process::Command;
-use bevy::ecs::world;
-use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::input::mouse::MouseWheel;
+use bevy::ecs::system::SystemParam;
 use bevy::{
-    color::palettes::css::*,
-    input::mouse::MouseButtonInput, math::vec3, prelude::*, utils::HashSet,
-    color::palettes::basic::*,
-    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    diagnostic::FrameTimeDiagnosticsPlugin,
+    math::vec3,
     prelude::*,
-    window::PresentMode,
 };
-use noise::{NoiseFn, Perlin};
 use rand::seq::SliceRandom;
 use rand::Rng;
 use bevy::window::PrimaryWindow;
 use bevy_pancam::{PanCamPlugin, PanCam};
-use rand_distr::{Normal, Distribution};
 use bevy_simple_scroll_view::*;
+use std::collections::HashMap;
+use std::time::Duration;
+use bevy::time::common_conditions::on_timer;
 
-//bevy ecs tilemap stuff
-use bevy::{color::palettes, math::Vec4Swizzles};
-use bevy::{ecs::system::Resource};
+//bevy ecs tilemap
+use bevy_ecs_tilemap::prelude::*;
 
 mod tile_data;
 use tile_data::*;
+mod world_gen;
+use world_gen::*;
+mod map_grid;
+use map_grid::*;
+use map_grid::Direction; //not the ui layout one from the bevy prelude
+mod mapgen;
+mod stockpile;
+use stockpile::*;
+mod items;
+mod recipes;
+use recipes::*;
+mod processors;
+use processors::*;
+mod research;
+use research::*;
+mod codex;
+use codex::*;
+mod planner;
+mod rails;
+use rails::*;
+mod trains;
+use trains::*;
+mod walkers;
+use walkers::*;
+mod ships;
+use ships::*;
+mod logistics;
+use logistics::*;
 
 //bevy egui
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
@@ -40,19 +63,24 @@ const SPRITE_SHEET_PATH: &str = "map.png";
 const TILE_WIDTH: usize = 8;
 ///Pixel height of a tile in the spritesheet.
 const TILE_HEIGHT: usize = 8;
+///Columns of tiles in the spritesheet.
+const SPRITE_SHEET_COLUMNS: u32 = 12;
+///Rows of tiles in the spritesheet.
+const SPRITE_SHEET_ROWS: u32 = 24;
 ///Sprite scale factor for a tile in the spritesheet, converted to the worldmap.
 const SPRITE_SCALE_FACTOR: usize = 6;
-///Number of columns on the spritesheet.
-const MAP_COLS: u32 = 12;
-///Number of rows on the spritesheet.
-const MAP_ROWS: u32 = 24;
 
 ///Map grid number of columns.
 pub const GRID_COLS:usize = 500;
 ///Map grid number of rows.
 pub const GRID_ROWS:usize = 500;
-///Map grid perlin noise scale.
-const PERLIN_NOISE_SCALE: f64 = 65.; //was 10
+///Map tiles per side of a tilemap render chunk.
+const MAP_CHUNK_SIZE: u32 = 64;
+
+///Gold the player starts with.
+const STARTING_GOLD: i32 = 100;
+///Entities placed on the map by the player, and the loonkas carrying goods over it, cleared when a new map is made.
+type BuildingFilter = Or<(With<Processor>, With<Train>, With<Walker>, With<Ship>)>;
 
 ///Camera lerp smoothing factor.
 const CAM_LERP_FACTOR: f32 = 4.0;
@@ -77,9 +105,16 @@ const BG_COLOR:Color = Color::srgba(0.02, 0.06, 0.23, 0.8);
 const BG_BORDER_COLOR:Color = Color::srgba(0.52, 0.56, 0.73, 0.5);
 
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("mapgen") { //headless map export, see mapgen.rs
+        mapgen::run_cli();
+        return;
+    }
+
+    let recipe_book = RecipeBook::load();
     App::new()
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest())) // prevents blurry sprites
         .add_plugins(ScrollViewPlugin)
+        .add_plugins(TilemapPlugin)
         .add_plugins((FrameTimeDiagnosticsPlugin,))
         .add_systems(Startup, init_msg_ui)
         .add_systems(Startup,init_build_ui)
@@ -88,7 +123,37 @@ fn main() {
         .add_systems(FixedUpdate, mouse_input_handler) //mouse input handler
         .add_systems(FixedUpdate,keyboard_handler)
         .add_systems(FixedUpdate, gui_buttons)
+        .add_systems(FixedUpdate, context_menu_buttons)
+        .add_systems(FixedUpdate, build_mode_handler)
+        .add_systems(FixedUpdate, track_mode_handler)
+        .add_systems(FixedUpdate, run_processors)
+        .add_systems(FixedUpdate, run_research)
+        .add_systems(FixedUpdate, run_trains)
+        .add_systems(FixedUpdate, run_walkers)
+        .add_systems(FixedUpdate, run_ships)
+        .add_systems(FixedUpdate, run_logistics.run_if(on_timer(Duration::from_secs_f32(DISPATCH_INTERVAL))))
+        .add_systems(Update, new_message)
+        .add_systems(Update, codex_window)
+        .add_systems(Update, update_egui_hovered)
+        .add_systems(Update, train_panel_window)
+        .add_systems(Update, logistics_window)
+        .add_systems(Update, loonka_picker_window)
         .init_resource::<CursorWorldCoords>()
+        .init_resource::<MapGrid>()
+        .init_resource::<CodexState>()
+        .init_resource::<EguiHovered>()
+        .init_resource::<ItemIcons>()
+        .init_resource::<RailGraph>()
+        .init_resource::<TrainPanel>()
+        .init_resource::<HaulSource>()
+        .init_resource::<LoonkaPicker>()
+        .init_resource::<Logistics>()
+        .init_resource::<WalkRegions>()
+        .insert_resource(WorldSeed::from_args())
+        .insert_resource(load_world_gen_config())
+        .insert_resource(Research::new(&recipe_book))
+        .insert_resource(recipe_book)
+        .add_event::<MessageEvent>()
         .add_plugins(PanCamPlugin) //Adds zoom and mouse-pan
         .insert_resource(Msaa::Off) //Removes lines between assets
 
@@ -99,10 +164,80 @@ fn main() {
 
 //          COMPONENTS
 
+///Whether the cursor is over an egui window, so map clicks are ignored.
+#[derive(Resource, Default)]
+struct EguiHovered(bool);
+
+///Spritesheet cut into tiles, to draw item icons in the UI.
+#[derive(Resource)]
+struct ItemIcons {
+    texture: Handle<Image>,
+    layout: Handle<TextureAtlasLayout>,
+}
+
+impl FromWorld for ItemIcons {
+    fn from_world(world: &mut World) -> Self {
+        let texture = world.resource::<AssetServer>().load(SPRITE_SHEET_PATH);
+        let grid = TextureAtlasLayout::from_grid(UVec2::new(TILE_WIDTH as u32, TILE_HEIGHT as u32), SPRITE_SHEET_COLUMNS, SPRITE_SHEET_ROWS, None, None);
+        let layout = world.resource_mut::<Assets<TextureAtlasLayout>>().add(grid);
+        ItemIcons {texture, layout}
+    }
+}
+
+impl ItemIcons {
+    ///UI image of the item's icon, scaled up like the map.
+    fn image(&self, item: ResourceItemType) -> (ImageBundle, TextureAtlas) {
+        let size = Val::Px((TILE_WIDTH * SPRITE_SCALE_FACTOR / 2) as f32);
+        (
+            ImageBundle {
+                style: Style {width: size, height: size, margin: UiRect::right(Val::Px(6.)), ..default()},
+                image: UiImage::new(self.texture.clone()),
+                ..default()
+            },
+            TextureAtlas {layout: self.layout.clone(), index: item.info().icon},
+        )
+    }
+}
+
 /// World coordinates of the mouse position
 #[derive(Resource, Default)]
 struct CursorWorldCoords(Vec2);
 
+/// Seed the current map was generated from. The same seed always generates the same map.
+#[derive(Resource, Clone, Copy, Debug)]
+struct WorldSeed(u64);
+
+impl WorldSeed {
+    ///Reads the seed from a `--seed <number>` launch argument, or picks a random one if none is given.
+    fn from_args() -> WorldSeed {
+        match launch_arg("--seed") {
+            Some(seed) => match seed.parse::<u64>() {
+                Ok(seed) => WorldSeed(seed),
+                Err(_) => {
+                    println!("Invalid seed '{}', using a random one instead.", seed);
+                    WorldSeed(rand::random())
+                }
+            },
+            None => WorldSeed(rand::random()),
+        }
+    }
+}
+
+///Returns the value following the given flag in the launch arguments, e.g. `--seed 42`.
+fn launch_arg(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1)).cloned()
+}
+
+///Loads the world generation preset named by the `--preset <name>` launch argument, or the default preset.
+fn load_world_gen_config() -> WorldGenConfig {
+    WorldGenConfig::load(&launch_arg("--preset").unwrap_or("default".to_string()))
+}
+
+/// A line of text to add to the player message log.
+#[derive(Event)]
+struct MessageEvent(String);
+
 /// Current speed of the camera.
 #[derive(Component)]
 struct CameraSpeed {
@@ -114,6 +249,33 @@ struct PlayerStats {
     gold: i32, //currency traded for with outposts
     knowledge:i32, //research unlocked by hiring researchers to research ingredient tiles.
     loonkas: Vec<Loonka>,
+    next_loonka_id: usize, //ids aren't reused, so lost loonkas can't be mistaken for new ones
+}
+
+impl PlayerStats {
+    fn new() -> PlayerStats {
+        PlayerStats {gold:STARTING_GOLD,knowledge:0,loonkas:vec![],next_loonka_id:1}
+    }
+
+    ///Hires a loonka with random stats. Returns its id.
+    fn hire_loonka(&mut self, name: String, rng: &mut impl Rng) -> usize {
+        let id = self.next_loonka_id;
+        self.next_loonka_id += 1;
+        self.loonkas.push(Loonka{
+            name,
+            id,
+            current_job: LoonkaJob::None,
+            vigor: rng.gen_range(25..=100),
+            speed: rng.gen_range(25..=100),
+            dexterity: rng.gen_range(25..=100),
+            strength: rng.gen_range(25..=100),
+            charisma: rng.gen_range(25..=100),
+            intellect: rng.gen_range(25..=100),
+            icon_num: rng.gen_range(0..=11),
+            tile: None,
+        });
+        id
+    }
 }
 #[derive(Component)]
 struct BuildMenuState {
@@ -125,6 +287,25 @@ struct BuildMenuState {
     track: bool,
     press: bool,
 }
+
+impl BuildMenuState {
+    ///Returns the selected processor and whether it is being placed (true) or removed (false).
+    fn selected_processor(&self) -> Option<(ProcessorType, bool)> {
+        match self.selected.as_ref()?.0.as_str() {
+            "build_menu_cooler_button" => Some((ProcessorType::Cooler, self.cooler)),
+            "build_menu_heater_button" => Some((ProcessorType::Heater, self.heater)),
+            "build_menu_mixer_button" => Some((ProcessorType::Mixer, self.mixer)),
+            "build_menu_spinner_button" => Some((ProcessorType::Spinner, self.spinner)),
+            "build_menu_press_button" => Some((ProcessorType::Press, self.press)),
+            _ => None,
+        }
+    }
+
+    ///Returns whether track is being laid (true) or lifted (false), if the track tool is selected.
+    fn selected_track(&self) -> Option<bool> {
+        if self.selected.as_ref()?.0 == "build_menu_track_button" {Some(self.track)} else {None}
+    }
+}
 #[derive(Component)]
 ///UI Node storage for comparisons.
 struct UINode {
@@ -136,292 +317,148 @@ struct UINode {
 struct Name(String);
 #[derive(Component)]
 struct BuildMenuBtn;
+///Stockpile picked with the Haul button, waiting for a second one to carry goods to.
+#[derive(Resource, Default)]
+struct HaulSource(Option<(usize, usize)>);
+///Tile picked with the Assign Loonka button, waiting for the player to choose who works there.
+#[derive(Resource, Default)]
+struct LoonkaPicker(Option<Entity>);
+///Picks started from the tile context menu that finish later: where a haul starts, and the tile a loonka is being chosen for.
+#[derive(SystemParam)]
+struct TilePicks<'w> {
+    haul_source: ResMut<'w, HaulSource>,
+    loonka_picker: ResMut<'w, LoonkaPicker>,
+}
+///Tile a context menu button acts on.
+#[derive(Component)]
+struct TileButton(Entity);
 
 ///Sets up the game and map.
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+    world_seed: Res<WorldSeed>,
+    world_gen_config: Res<WorldGenConfig>,
+    mut messages: EventWriter<MessageEvent>,
 ) {
     //create the map size, scale, and grid.
     let texture: Handle<Image> = asset_server.load(SPRITE_SHEET_PATH);
-    let layout = TextureAtlasLayout::from_grid(
-        UVec2::new(TILE_WIDTH as u32, TILE_HEIGHT as u32), MAP_COLS, MAP_ROWS, None, None);
-    let texture_atlas_layout = texture_atlas_layouts.add(layout);
 
     //Spawns and instantiates camera and player information variables.
     commands.spawn(CameraSpeed {speed: CAM_SPEED_MIN});
-    commands.spawn(PlayerStats {gold:0,knowledge:0,loonkas:vec![]});
+    commands.spawn(PlayerStats::new());
     commands.spawn(BuildMenuState { selected: None, cooler: true, heater: true, mixer: true, spinner: true, track: true, press: true });
     commands.spawn(Camera2dBundle::default()).insert(PanCam::default());
 
     //Generate the map
-    generate_new_map(commands, texture, texture_atlas_layout,None);
+    generate_new_map(commands, texture, asset_server.load(RAIL_SPRITE_SHEET_PATH), None, world_seed.0, &world_gen_config);
+    messages.send(MessageEvent(format!("World seed: {}", world_seed.0)));
 }
 
-///Generates the map tiles, data, and resources.
+///Generates the map and spawns it as a base terrain tilemap layer and a resource overlay tilemap layer,
+///with an empty rail layer above them for track.
 fn generate_new_map(
     mut commands: Commands, 
     texture: Handle<Image>, 
-    texture_atlas_layout: Handle<TextureAtlasLayout>, 
-    tiles_q: Option<Query<Entity, With<TileBaseType>>>)
+    rail_texture: Handle<Image>,
+    tilemap_q: Option<Query<(Entity, &TileStorage)>>,
+    seed: u64,
+    config: &WorldGenConfig)
 {
-    //despawn all tiles if they exist
-    if let Some(query) = tiles_q {
-        for entity in query.iter() {
+    //despawn the old tilemaps and their tiles if they exist
+    if let Some(query) = tilemap_q {
+        for (entity, storage) in query.iter() {
+            for tile_entity in storage.iter().flatten() {
+                commands.entity(*tile_entity).despawn();
+            }
             commands.entity(entity).despawn();
         }
     }
 
-    
-    //Generates set of perlin noises for the map
-    let mut perlin_rand = rand::thread_rng();
-    let base_perlin = Perlin::new(perlin_rand.gen());
-    let forest_perlin = Perlin::new(perlin_rand.gen());
-    let dark_forest_perlin = Perlin::new(perlin_rand.gen());
-    let jungle_perlin = Perlin::new(perlin_rand.gen());
-    let dirt_perlin = Perlin::new(perlin_rand.gen());
-    let mud_perlin = Perlin::new(perlin_rand.gen());
-    let pond_perlin = Perlin::new(perlin_rand.gen());
-    let islands_perlin = Perlin::new(perlin_rand.gen());
-
-
-    //Create base tiles and use perlin noise to fill in the tilebasetype.
-    let mut tiles = HashSet::new();
-    for x in 0..GRID_COLS {
-        for y in 0..GRID_ROWS {
-            let mut value = base_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let tile_base_type = if value <= 0.10 {
-                let normalized_rand = Normal::new(0.0,0.02).unwrap();
-                let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-                value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &0.14);
-
-                    match value {
-                        v if v < 0.01 => TileBaseType::DeepWater,
-                        _ => TileBaseType::Water,
-                    }
-            } else {
-                let normalized_rand = Normal::new(0.0,0.05).unwrap();
-                let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-                value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-    
-                match value {
-                    v if v < 0.25 => TileBaseType::Sand,
-                    v if v < 0.65 => TileBaseType::Plains,
-                    v if v < 0.85 => TileBaseType::DarkMountain,
-                    _ => TileBaseType::Mountain,
-                }
-            };
-
-            //Generate forests
-            let mut value = forest_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let normalized_rand = Normal::new(0.0,0.05).unwrap();
-            let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-            value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-            let tile_base_type = match value {
-                v if (v > 0.9 && tile_base_type == TileBaseType::Plains) => TileBaseType::Forest,
-                _ => {tile_base_type}
-            };
-            
-            //Generate dark forests
-            let mut value = dark_forest_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let normalized_rand = Normal::new(0.0,0.05).unwrap();
-            let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-            value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-            let tile_base_type = match value {
-                v if (v > 0.9 && tile_base_type == TileBaseType::Plains) => TileBaseType::DarkForest,
-                _ => {tile_base_type}
-            };
-            
-            //Generate jungles
-            let mut value = jungle_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let normalized_rand = Normal::new(0.0,0.05).unwrap();
-            let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-            value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-            let tile_base_type = match value {
-                v if (v > 0.9 && (tile_base_type == TileBaseType::Plains || tile_base_type == TileBaseType::DarkMountain)) => TileBaseType::Jungle,
-                _ => {tile_base_type}
-            };
-            
-            //Generate dirt
-            let mut value = dirt_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let normalized_rand = Normal::new(0.0,0.05).unwrap();
-            let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-            value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-            let tile_base_type = match value {
-                v if (v > 0.9 && tile_base_type == TileBaseType::Plains) => TileBaseType::DarkDirt,
-                v if (v > 0.8 && tile_base_type == TileBaseType::Plains) => TileBaseType::Dirt,
-                _ => {tile_base_type}
-            };
-            
-            //Generate mud
-            let mut value = mud_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let normalized_rand = Normal::new(0.0,0.05).unwrap();
-            let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-            value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-            let tile_base_type = match value {
-                v if (v > 0.9 && tile_base_type == TileBaseType::Sand) => TileBaseType::Mud,
-                _ => {tile_base_type}
-            };
-
-            //Generate ponds
-            let mut value = pond_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let normalized_rand = Normal::new(0.0,0.05).unwrap();
-            let normal_rng_val = normalized_rand.sample(&mut rand::thread_rng());
-            value = *clamp(&(value + (normal_rng_val) as f64), &0.00, &1.00);
-            let tile_base_type = match value {
-                v if (v > 0.9 && (tile_base_type == TileBaseType::Plains || tile_base_type == TileBaseType::DarkMountain || tile_base_type == TileBaseType::Mountain)) => TileBaseType::Pond,
-                v if (v > 0.9 && tile_base_type == TileBaseType::Sand) => TileBaseType::Mud,
-                v if (v > 0.8 && (tile_base_type == TileBaseType::Plains || tile_base_type == TileBaseType::DarkMountain || tile_base_type == TileBaseType::Mountain)) => TileBaseType::Mud,
-                _ => {tile_base_type}
-            };
-
-            //Generate islands
-            let value = islands_perlin.get([(x as f64 /PERLIN_NOISE_SCALE),(y as f64 / PERLIN_NOISE_SCALE)]);
-            let tile_base_type = match value {
-                v if (v > 0.95 && (tile_base_type == TileBaseType::DeepWater || tile_base_type == TileBaseType::Water)) => TileBaseType::Sand,
-                v if (v > 0.85 && (tile_base_type == TileBaseType::DeepWater || tile_base_type == TileBaseType::Water)) => TileBaseType::Water,
-                _ => {tile_base_type}
-            };
-
-
-            //Generate resources
-            let resource_value: f64 = perlin_rand.gen();
-            let resource_type = match resource_value {
-                v if v < 0.993 => "none",
-                v if v < 0.995 => "outpost",
-                v if v < 0.997 => "enemy",
-                _ => "harvest",
-            };
-
-            let mut resource_data = (
-                OutpostTile{ outpost_type: OutpostType::None, sales_mod: 0 },
-                HarvestableTile{yields: ResourceItemType::None, amount_mod: 0},
-                EnemyTile { enemy_type: EnemyTileType::None, yields: [ResourceItemType::None,ResourceItemType::None,ResourceItemType::None,ResourceItemType::None,ResourceItemType::None], amount_mod: 0, health: 0, damage: 0 });
-
-
-            if resource_type == "outpost" {
-                let sales_mod_rand = rand::thread_rng().gen_range(0..=100);
-                resource_data.0 = OutpostTile{ outpost_type: OutpostType::get_rand(tile_base_type), sales_mod: sales_mod_rand };
-            } else if  resource_type == "enemy" {
-                let amount_mod_rand = rand::thread_rng().gen_range(0..=100);
-                let health_mod_rand = rand::thread_rng().gen_range(0..=100);
-                let damage_mod_rand = rand::thread_rng().gen_range(0..=100);
-                resource_data.2 = EnemyTile { enemy_type: EnemyTileType::get_rand(tile_base_type), yields: ([(ResourceItemType::get_rand(TileBaseType::All)),ResourceItemType::get_rand(TileBaseType::All),ResourceItemType::get_rand(TileBaseType::All),ResourceItemType::get_rand(TileBaseType::All),ResourceItemType::get_rand(TileBaseType::All)]), amount_mod: amount_mod_rand, health: health_mod_rand, damage: damage_mod_rand }
-                
-            } else if resource_type == "harvest" {
-                let rand_item: ResourceItemType = ResourceItemType::get_rand(tile_base_type);
-                let rand_item_type = match rand_item {
-                    ResourceItemType::Nut(_) => ResourceItemType::Nut(NutType::get_rand(tile_base_type)),
-                    ResourceItemType::Fruit(_) => ResourceItemType::Fruit(FruitType::get_rand(tile_base_type)),
-                    ResourceItemType::Bush(_) => ResourceItemType::Bush(BushType::get_rand(tile_base_type)),
-                    ResourceItemType::Spice(_) => ResourceItemType::Spice(SpiceType::get_rand(tile_base_type)),
-                    _ => ResourceItemType::Mine(MineType::get_rand(tile_base_type)),
-                };
-                let rand_amount = rand::random();
-                resource_data.1 = HarvestableTile {yields: rand_item_type, amount_mod: rand_amount};
-                
-            };
-            // Insert into the tiles collection as a point and a tile type enum
-            tiles.insert(((x, y), tile_base_type, resource_data.0, resource_data.1, resource_data.2));
-        }
-    }
-
-    //Go through each tile, scale the grid locations to the world location using tile width and scale factor, then get the sprite based on the tilebasetype enum and draw it.
-    for ((x,y), tile_base_type, outpost_data, harvest_data, enemy_data) in tiles.iter() {
-        let (x, y) = grid_to_world(*x as f32, *y as f32);
-
-        let tile_randomizer = rand::thread_rng().gen_range(0..=3); //4 possible max states, most tiles use less hence the clamp.
-
-        let texture_index = match tile_base_type {
-            TileBaseType::Plains => {0 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::Forest => {3 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::DarkForest => {6 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::Jungle => {9 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::Mountain => {12 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::DarkMountain => {15 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::Sand => {18 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::Dirt => {21 + *clamp(&tile_randomizer, &0, &2) as usize},
-            TileBaseType::DarkDirt => {24 + *clamp(&tile_randomizer, &0, &1) as usize},
-            TileBaseType::Mud => {26 + *clamp(&tile_randomizer, &0, &1) as usize},
-            TileBaseType::Water => {28 + *clamp(&tile_randomizer, &0, &1) as usize},
-            TileBaseType::DeepWater => {30 + *clamp(&tile_randomizer, &0, &1) as usize},
-            TileBaseType::River => {32 + *clamp(&tile_randomizer, &0, &1) as usize},
-            TileBaseType::Pond => {34 + *clamp(&tile_randomizer, &0, &1) as usize},
-            _ => {210}, //shouldn't happen ever since all is only used internally
-        };
-
-        //Gets the random tile type or color.
-        let resource_index = if outpost_data.outpost_type != OutpostType::None {
-            let tile_resource_type = outpost_data.outpost_type;
-            let rand_colorval = rand::thread_rng().gen_range(0..=11); //0 to 11 inclusive
-            let resource_index = match tile_resource_type {
-                OutpostType::ForestOutpost => {48 + rand_colorval},
-                OutpostType::MountainOutpost => {60 + rand_colorval},
-                OutpostType::ShipOutpost => {72 + rand_colorval},
-                _ => {36 + rand_colorval},
-            };
-            resource_index
-        } else if harvest_data.yields != ResourceItemType::None {
-            let tile_resource_type = &harvest_data.yields;
-            //let rand_colorval = rand::thread_rng().gen_range(0..=11); //0 to 11 inclusive
-            let resource_index = match tile_resource_type {
-                ResourceItemType::Nut(_) => {120 + rand::thread_rng().gen_range(0..=2)},
-                ResourceItemType::Bush(_) => {132 + rand::thread_rng().gen_range(0..=8)},
-                ResourceItemType::Fruit(_) => {108 + rand::thread_rng().gen_range(0..=11)},
-                ResourceItemType::Spice(SpiceType::Seamint) => 149, //seamint has a unique icon
-                ResourceItemType::Spice(_) => {144 + rand::thread_rng().gen_range(0..=4)},
-                _ => {96 + rand::thread_rng().gen_range(0..=4)}, //Mine
-            };
-            resource_index
-        } else if enemy_data.enemy_type != EnemyTileType::None {
-            let tile_resource_type = &enemy_data.enemy_type;
-            let resource_index = match tile_resource_type {
-                EnemyTileType::BeepleBillage => {85},
-                _ => {84}, //pirate ship
-            };
-            resource_index
-        } else {
-            192 //default = none
-        };
-
-        
-        
-        commands.spawn(( //Spawns the texture with the given texture index calculated previously
-            SpriteBundle {
-                transform: Transform::from_scale(Vec3::splat(SPRITE_SCALE_FACTOR as f32)).with_translation(vec3(x as f32, y as f32, 0.0)),
-                texture: texture.clone(),
+    let map_size = TilemapSize { x: GRID_COLS as u32, y: GRID_ROWS as u32 };
+    let base_tilemap = commands.spawn_empty().id();
+    let resource_tilemap = commands.spawn_empty().id();
+    let rail_tilemap = commands.spawn(RailLayer).id();
+    let mut base_storage = TileStorage::empty(map_size);
+    let mut resource_storage = TileStorage::empty(map_size);
+    let mut map_grid = MapGrid::default();
+    let mut walkable = vec![false; GRID_COLS * GRID_ROWS];
+
+    for generated in generate_tiles(seed, config) {
+        let (grid_x, grid_y) = generated.grid_pos;
+        walkable[grid_x * GRID_ROWS + grid_y] = generated.base_type.move_cost().is_some();
+        let tile_pos = TilePos { x: grid_x as u32, y: grid_y as u32 };
+        let (x, y) = grid_to_world(grid_x as f32, grid_y as f32);
+
+        let tile_entity = commands.spawn((
+            TileBundle {
+                position: tile_pos,
+                tilemap_id: TilemapId(base_tilemap),
+                texture_index: TileTextureIndex(generated.texture_index as u32),
                 ..default()
             },
-            TextureAtlas {
-                layout: texture_atlas_layout.clone(),
-                index: texture_index,
-            },
             Tile {
                 location :(x,y), 
-                base_type: *tile_base_type, 
-                outpost: *outpost_data,
-                harvest: *harvest_data,
-                enemy: *enemy_data,
+                base_type: generated.base_type, 
+                outpost: generated.outpost,
+                harvest: generated.harvest,
+                enemy: generated.enemy,
                 loonkas: vec![],
+                stock: Stockpile::new(if generated.outpost.outpost_type != OutpostType::None {OUTPOST_STOCK_CAPACITY} else {TILE_STOCK_CAPACITY}),
+                last_harvest: None,
+                processor: None,
             },
-        ));
-
-        if resource_index < 191 { //if it has a valid resource
-
-            commands.spawn(( //Spawns the resource texture with the given resource index calculated previously
-                SpriteBundle {
-                    transform: Transform::from_scale(Vec3::splat(SPRITE_SCALE_FACTOR as f32)).with_translation(vec3(x as f32, y as f32, 0.0)),
-                    texture: texture.clone(),
-                    ..default()
-                },
-                TextureAtlas {
-                    layout: texture_atlas_layout.clone(),
-                    index: resource_index,
-                },
-            ));
+        )).id();
+        base_storage.set(&tile_pos, tile_entity);
+        map_grid.set(grid_x, grid_y, tile_entity);
+
+        if let Some(resource_index) = generated.resource_index { //if it has a valid resource
+            let resource_entity = commands.spawn(TileBundle {
+                position: tile_pos,
+                tilemap_id: TilemapId(resource_tilemap),
+                texture_index: TileTextureIndex(resource_index as u32),
+                ..default()
+            }).id();
+            resource_storage.set(&tile_pos, resource_entity);
         }
     }
+
+    //Both layers share the spritesheet, the resource layer is drawn just above the terrain.
+    let tile_size = TilemapTileSize { x: TILE_WIDTH as f32, y: TILE_HEIGHT as f32 };
+    let render_settings = TilemapRenderSettings { render_chunk_size: UVec2::splat(MAP_CHUNK_SIZE), y_sort: false };
+    commands.entity(base_tilemap).insert(TilemapBundle {
+        grid_size: tile_size.into(),
+        size: map_size,
+        storage: base_storage,
+        texture: TilemapTexture::Single(texture.clone()),
+        tile_size,
+        transform: Transform::from_scale(Vec3::splat(SPRITE_SCALE_FACTOR as f32)),
+        render_settings,
+        ..default()
+    });
+    commands.entity(resource_tilemap).insert(TilemapBundle {
+        grid_size: tile_size.into(),
+        size: map_size,
+        storage: resource_storage,
+        texture: TilemapTexture::Single(texture),
+        tile_size,
+        transform: Transform::from_scale(Vec3::splat(SPRITE_SCALE_FACTOR as f32)).with_translation(vec3(0.0, 0.0, 1.0)),
+        render_settings,
+        ..default()
+    });
+    commands.entity(rail_tilemap).insert(TilemapBundle { //filled in as track is laid
+        grid_size: tile_size.into(),
+        size: map_size,
+        storage: TileStorage::empty(map_size),
+        texture: TilemapTexture::Single(rail_texture),
+        tile_size,
+        transform: Transform::from_scale(Vec3::splat(SPRITE_SCALE_FACTOR as f32)).with_translation(vec3(0.0, 0.0, 1.5)),
+        render_settings,
+        ..default()
+    });
+    commands.insert_resource(map_grid);
+    commands.insert_resource(WalkRegions::new(|(x, y)| walkable[x * GRID_ROWS + y]));
+    commands.insert_resource(RailGraph::default());
 }
 
 ///Creates a new loonka for the player.
@@ -435,87 +472,84 @@ fn create_loonka(
     "Lisa".to_string(),"Tara".to_string(),"Primrose".to_string(),"Xantu".to_string(), "Patrick".to_string(), "Cody".to_string(), "Dani".to_string(), "Quinn".to_string(),
     "Lainey".to_string(), "Chloe".to_string()];
     
-    let currentid = player_stats.loonkas.len();
     let name = loonka_names.choose(&mut rand::thread_rng()).unwrap().clone();
-    player_stats.loonkas.push(Loonka{
-        name: name,
-        id: currentid+1,
-        current_job: LoonkaJob::None,
-        vigor: rand::thread_rng().gen_range(25..=100),
-        speed: rand::thread_rng().gen_range(25..=100),
-        dexterity: rand::thread_rng().gen_range(25..=100),
-        strength: rand::thread_rng().gen_range(25..=100),
-        charisma: rand::thread_rng().gen_range(25..=100),
-        intellect: rand::thread_rng().gen_range(25..=100),
-        icon_num: rand::thread_rng().gen_range(0..=11),
-    });
+    player_stats.hire_loonka(name, &mut rand::thread_rng());
     // println!("loonkas: {:?}",player_stats.loonkas);
 }
 
-///Place a loonka (WIP)
-fn place_loonka(
-    mut commands: Commands, 
-    texture: Handle<Image>, 
-    texture_atlas_layout: Handle<TextureAtlasLayout>,
-    tile_q: Query<&Tile>, 
-    mut player_stat_q: Query<&mut PlayerStats>, 
-    mut clicked_tile: Tile) {
-
-    // let Ok(mut player_stats) = player_stats.get_single_mut() else {return;};
-
-
-
-    // //get texture index
-    
-    // commands.spawn(( //Spawns the texture with the given texture index calculated previously
-    //     SpriteBundle {
-    //         transform: Transform::from_scale(Vec3::splat(SPRITE_SCALE_FACTOR as f32)).with_translation(vec3(x as f32, y as f32, 0.0)),
-    //         texture: texture.clone(), //cloning is performance hit -- necessary?
-    //         ..default()
-    //     },
-    //     TextureAtlas {
-    //         layout: texture_atlas_layout.clone(), //cloning is performance hit -- necessary?
-    //         index: texture_index,
-    //     },
-    //     Tile {
-    //         location :(x,y), 
-    //         base_type: *tile_base_type, 
-    //         outpost: *outpost_data,
-    //         harvest: *harvest_data,
-    //         enemy: *enemy_data ,
-    //     },
-    //     // animation_indices,
-    //     // AnimationTimer(Timer::from_seconds(0.4, TimerMode::Repeating)),
-    // ));
+///What regenerating the map with R replaces, and the seed and presets it is made from.
+#[derive(SystemParam)]
+struct MapReload<'w, 's> {
+    tilemap_q: Query<'w, 's, (Entity, &'static TileStorage)>,
+    building_q: Query<'w, 's, Entity, BuildingFilter>,
+    world_seed: ResMut<'w, WorldSeed>,
+    world_gen_config: ResMut<'w, WorldGenConfig>,
+}
 
+///The egui windows opened and closed from the keyboard.
+#[derive(SystemParam)]
+struct Panels<'w> {
+    codex: ResMut<'w, CodexState>,
+    train_panel: ResMut<'w, TrainPanel>,
+    logistics: ResMut<'w, Logistics>,
 }
 
 ///Handles keyboard input
+#[allow(clippy::too_many_arguments)]
 fn keyboard_handler(
     commands:Commands,
     keys: Res<ButtonInput<KeyCode>>, 
-    player_stat_q: Query<&mut PlayerStats>,
+    mut player_stat_q: Query<&mut PlayerStats>,
     asset_server: Res<AssetServer>,
-    texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
-    tilebasetype_q: Query<Entity, With<TileBaseType>>,
     uinode_q: Query<&mut UINode>,
-    scrolls_q: Query<&mut ScrollableContent>,
     style_q: Query<&mut Style>,
-    contexts: EguiContexts,
+    mut map_reload: MapReload,
+    mut research: ResMut<Research>,
+    mut panels: Panels,
+    mut messages: EventWriter<MessageEvent>,
 ) {
     if keys.just_pressed(KeyCode::KeyB) {
         create_loonka(player_stat_q);
     } else if keys.just_pressed(KeyCode::KeyR) {
-        reload_on_r(commands, asset_server, texture_atlas_layouts, tilebasetype_q)
+        map_reload.world_seed.0 = rand::random();
+        *map_reload.world_gen_config = load_world_gen_config(); //re-read so preset edits show up without restarting
+        reload_on_r(commands, asset_server, map_reload.tilemap_q, map_reload.building_q, map_reload.world_seed.0, &map_reload.world_gen_config);
+        if let Ok(mut player_stats) = player_stat_q.get_single_mut() {
+            for loonka in player_stats.loonkas.iter_mut() { //their tiles are gone
+                loonka.tile = None;
+            }
+        }
+        messages.send(MessageEvent(format!("World seed: {}", map_reload.world_seed.0)));
+    } else if keys.just_pressed(KeyCode::BracketRight) { //move gold into the research budget
+        if let Ok(mut player_stats) = player_stat_q.get_single_mut() {
+            let amount = BUDGET_STEP.min(player_stats.gold.max(0));
+            player_stats.gold -= amount;
+            research.budget += amount;
+            messages.send(MessageEvent(format!("Research budget: {}. Gold: {}", research.budget, player_stats.gold)));
+        }
+    } else if keys.just_pressed(KeyCode::BracketLeft) { //and back out of it
+        if let Ok(mut player_stats) = player_stat_q.get_single_mut() {
+            let amount = BUDGET_STEP.min(research.budget);
+            research.budget -= amount;
+            player_stats.gold += amount;
+            messages.send(MessageEvent(format!("Research budget: {}. Gold: {}", research.budget, player_stats.gold)));
+        }
+    } else if keys.just_pressed(KeyCode::KeyC) {
+        panels.codex.open = !panels.codex.open;
+    } else if keys.just_pressed(KeyCode::KeyT) {
+        panels.train_panel.open = !panels.train_panel.open;
+    } else if keys.just_pressed(KeyCode::KeyL) {
+        panels.logistics.open = !panels.logistics.open;
     } else if keys.just_pressed(KeyCode::KeyF) {
-        new_message(scrolls_q, commands, asset_server, uinode_q)
+        messages.send(MessageEvent("New message!".to_string()));
     } else if keys.just_pressed(KeyCode::Tab) {
         load_inventory(commands,asset_server,uinode_q,player_stat_q,style_q,None)
     }
 }
 
-//Sends player a new message (WIP)
+///Adds any new messages to the player message log.
 fn new_message(
+    mut messages: EventReader<MessageEvent>,
     mut scrolls_q: Query<&mut ScrollableContent>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -524,14 +558,23 @@ fn new_message(
     let font = asset_server.load(TEXT_FONT);
     let font_size = TEXT_SIZE_STANDARD;
     let font_color = TEXT_COLOR_STANDARD;
-    
+
+    let text_area = {
+        let mut res = None;
+        for ui_node in uinode_q.iter() {
+             if ui_node.name == "text_area" {res = Some(ui_node.id)};
+        }
+        res
+    };
+    let Some(text_area) = text_area else {return;};
+
+    for message in messages.read() {
         let new_msg = commands.spawn((TextBundle::from_section(
-            "New message!",
+            message.0.clone(),
             TextStyle {
                 font: font.clone(),
-                font_size: font_size,
+                font_size,
                 color: font_color,
-                ..default()
             },
         ),
         )).id();
@@ -543,25 +586,16 @@ fn new_message(
                 ..default()
             }).id();
 
-    let text_area = {
-        let mut res = None;
-        for ui_node in uinode_q.iter() {
-             if ui_node.name == "text_area".to_string() {res = Some(ui_node.id)};
-        }
-        res
-    };
-    let text_area = text_area.unwrap();
         commands
             .entity(text_area)
             .push_children(&[new_msg])
             .push_children(&[msg_gap]);
 
-
-    for mut scroll in scrolls_q.iter_mut() { //scroll down in textbox
-            scroll.pos_y -= 20.;
-            //println!("scroll pos: {}",scroll.pos_y);
+        for mut scroll in scrolls_q.iter_mut() { //scroll down in textbox
+                scroll.pos_y -= 20.;
+                //println!("scroll pos: {}",scroll.pos_y);
+        }
     }
-    
 }
 
 ///Loads the inventory, or reloads if reload is set to true.
@@ -573,16 +607,15 @@ fn load_inventory(
     mut style_q: Query<&mut Style>,
     reload: Option<bool>,
 ) {
-    let reload_bool = match reload {Some(val) => {val},None => {false}};
+    let reload_bool = reload.unwrap_or_default();
 
     let inv_list_container = {
         let mut res = None;
         for ui_node in uinode_q.iter() {
-            if ui_node.name == "inv_list_container".to_string() {res = Some(ui_node.id)};
+            if ui_node.name == "inv_list_container" {res = Some(ui_node.id)};
         }
         res
     };
-    let inv_list_container = inv_list_container;
         match inv_list_container {
             Some(x) => {
                 if let Ok(style) = style_q.get_mut(inv_list_container.unwrap()) {
@@ -616,6 +649,7 @@ fn load_inventory(
 }
 
 ///Handles GUI button interactions.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn gui_buttons(
     commands: Commands,
     asset_server: Res<AssetServer>,
@@ -674,57 +708,57 @@ fn gui_buttons(
             build_menu_state.selected = Some(Name(name.0.clone()));
             match name.0.as_str() {
                 "build_menu_cooler_button" => {
-                    if build_menu_state.cooler == true {
+                    if build_menu_state.cooler {
                         build_menu_state.cooler = false;
-                        *img = UiImage::new(asset_server.load(format!("ui/remove_cooler.png")));
+                        *img = UiImage::new(asset_server.load("ui/remove_cooler.png"));
                     } else {
                         build_menu_state.cooler = true;
-                        *img = UiImage::new(asset_server.load(format!("ui/place_cooler.png")));
+                        *img = UiImage::new(asset_server.load("ui/place_cooler.png"));
                     };
                 },
                 "build_menu_heater_button" => {
-                    if build_menu_state.heater == true {
+                    if build_menu_state.heater {
                         build_menu_state.heater = false;
-                        *img = UiImage::new(asset_server.load(format!("ui/remove_heater.png")));
+                        *img = UiImage::new(asset_server.load("ui/remove_heater.png"));
                     } else {
                         build_menu_state.heater = true;
-                        *img = UiImage::new(asset_server.load(format!("ui/place_heater.png")));
+                        *img = UiImage::new(asset_server.load("ui/place_heater.png"));
                     };
                 },
                 "build_menu_mixer_button" => {
-                    if build_menu_state.mixer == true {
+                    if build_menu_state.mixer {
                         build_menu_state.mixer = false;
-                        *img = UiImage::new(asset_server.load(format!("ui/remove_mixer.png")));
+                        *img = UiImage::new(asset_server.load("ui/remove_mixer.png"));
                     } else {
                         build_menu_state.mixer = true;
-                        *img = UiImage::new(asset_server.load(format!("ui/place_mixer.png")));
+                        *img = UiImage::new(asset_server.load("ui/place_mixer.png"));
                     };
                 },
                 "build_menu_spinner_button" => {
-                    if build_menu_state.spinner == true {
+                    if build_menu_state.spinner {
                         build_menu_state.spinner = false;
-                        *img = UiImage::new(asset_server.load(format!("ui/remove_spinner.png")));
+                        *img = UiImage::new(asset_server.load("ui/remove_spinner.png"));
                     } else {
                         build_menu_state.spinner = true;
-                        *img = UiImage::new(asset_server.load(format!("ui/place_spinner.png")));
+                        *img = UiImage::new(asset_server.load("ui/place_spinner.png"));
                     };
                 },
                 "build_menu_track_button" => {
-                    if build_menu_state.track == true {
+                    if build_menu_state.track {
                         build_menu_state.track = false;
-                        *img = UiImage::new(asset_server.load(format!("ui/remove_track.png")));
+                        *img = UiImage::new(asset_server.load("ui/remove_track.png"));
                     } else {
                         build_menu_state.track = true;
-                        *img = UiImage::new(asset_server.load(format!("ui/place_track.png")));
+                        *img = UiImage::new(asset_server.load("ui/place_track.png"));
                     };
                 },
                 "build_menu_press_button" => {
-                    if build_menu_state.press == true {
+                    if build_menu_state.press {
                         build_menu_state.press = false;
-                        *img = UiImage::new(asset_server.load(format!("ui/remove_press.png")));
+                        *img = UiImage::new(asset_server.load("ui/remove_press.png"));
                     } else {
                         build_menu_state.press = true;
-                        *img = UiImage::new(asset_server.load(format!("ui/place_press.png")));
+                        *img = UiImage::new(asset_server.load("ui/place_press.png"));
                     };
                 },
                 _ => {}
@@ -798,7 +832,7 @@ fn refresh_inventory(
                 padding: UiRect::axes(Val::Px(5.), Val::Px(5.)),
                 ..default()
             },
-            transform: Transform::from_translation(vec3(0.0 as f32, 0.0 as f32, 0.0)),
+            transform: Transform::from_translation(vec3(0.0, 0.0, 0.0)),
             ..default()
         },
         ScrollableContent::default(),
@@ -819,7 +853,7 @@ fn refresh_inventory(
                         padding: UiRect::axes(Val::Px(5.), Val::Px(5.)),
                         ..default()
                     },
-                    transform: Transform::from_translation(vec3(0.0 as f32, 0.0 as f32, 0.0)),
+                    transform: Transform::from_translation(vec3(0.0, 0.0, 0.0)),
                     ..default()
                 },
                 ScrollableContent::default(),
@@ -834,7 +868,7 @@ fn refresh_inventory(
                         padding: UiRect::axes(Val::Px(5.), Val::Px(5.)),
                         ..default()
                     },
-                    transform: Transform::from_translation(vec3(0.0 as f32, 0.0 as f32, 0.0)),
+                    transform: Transform::from_translation(vec3(0.0, 0.0, 0.0)),
                     ..default()
                 },
                 ScrollableContent::default(),
@@ -845,7 +879,6 @@ fn refresh_inventory(
                             font: font.clone(),
                             font_size: highlight_font_size,
                             color: highlight_font_color,
-                            ..default()
                         },
                     ),
                 ));
@@ -865,9 +898,8 @@ fn refresh_inventory(
                         loonka.strength,loonka.dexterity,loonka.intellect,loonka.vigor),
                         TextStyle {
                             font: font.clone(),
-                            font_size: font_size,
+                            font_size,
                             color: font_color,
-                            ..default()
                         },
                     ),
                 ));  
@@ -913,7 +945,7 @@ fn refresh_inventory(
     let root_uinode = {
         let mut res = None;
         for ui_node in uinode_q.iter() {
-            if ui_node.name == "root_uinode".to_string() {res = Some(ui_node.id)};
+            if ui_node.name == "root_uinode" {res = Some(ui_node.id)};
         }
         res
     };
@@ -979,9 +1011,8 @@ fn init_msg_ui(
                 "You awaken on a strange island...",
                 TextStyle {
                     font: font.clone(),
-                    font_size: font_size,
+                    font_size,
                     color: font_color,
-                    ..default()
                 },
             ),
         ));
@@ -996,9 +1027,8 @@ fn init_msg_ui(
             "You don't know where you are.",
                 TextStyle {
                     font: font.clone(),
-                    font_size: font_size,
+                    font_size,
                     color: font_color,
-                    ..default()
                 },
             ),
         ));
@@ -1013,9 +1043,8 @@ fn init_msg_ui(
             "You don't know where you came from.",
                 TextStyle {
                     font: font.clone(),
-                    font_size: font_size,
+                    font_size,
                     color: font_color,
-                    ..default()
                 },
             ),
         ));
@@ -1030,9 +1059,8 @@ fn init_msg_ui(
             "You know just one thing. You are thirsty!",
                 TextStyle {
                     font: font.clone(),
-                    font_size: font_size,
+                    font_size,
                     color: font_color,
-                    ..default()
                 },
             ),
         ));
@@ -1047,9 +1075,8 @@ fn init_msg_ui(
             "Try clicking some water to harvest it!",
                 TextStyle {
                     font: font.clone(),
-                    font_size: font_size,
+                    font_size,
                     color: font_color,
-                    ..default()
                 },
             ),
         ));
@@ -1071,26 +1098,103 @@ commands.spawn(UINode {name: "text_area".to_string(), id:text_area}); //instanti
         .push_children(&[text_box]);
 }
 
+///Returns the best dexterity among the harvesters assigned to a tile, if any work there.
+fn harvester_dexterity(loonkas: &[Loonka], tile: Entity) -> Option<i32> {
+    loonkas.iter()
+        .filter(|loonka| loonka.tile == Some(tile) && loonka.current_job == LoonkaJob::Harvester)
+        .map(|loonka| loonka.dexterity)
+        .max()
+}
+
+///Returns the combined strength of the warriors assigned to a tile.
+fn warrior_strength(loonkas: &[Loonka], tile: Entity) -> i32 {
+    loonkas.iter()
+        .filter(|loonka| loonka.tile == Some(tile) && loonka.current_job == LoonkaJob::Warrior)
+        .map(|loonka| loonka.strength)
+        .sum()
+}
+
+///The enemy's damage is taken from the vigor of every warrior on a tile; those left with none are lost and returned.
+fn wound_warriors(loonkas: &mut Vec<Loonka>, tile: Entity, damage: i32) -> Vec<Loonka> {
+    let mut fallen = vec![];
+    let mut index = 0;
+    while index < loonkas.len() {
+        let loonka = &mut loonkas[index];
+        if loonka.tile == Some(tile) && loonka.current_job == LoonkaJob::Warrior {
+            loonka.vigor -= damage;
+            if loonka.vigor <= 0 {
+                fallen.push(loonkas.remove(index));
+                continue;
+            }
+        }
+        index += 1;
+    }
+    fallen
+}
+
+///The window and camera the map is seen through, and the UI that takes clicks before the map does.
+#[derive(SystemParam)]
+struct MapCursor<'w, 's> {
+    windows_q: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    camera_q: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    button_q: Query<'w, 's, &'static Interaction, With<Button>>,
+    egui_hovered: Res<'w, EguiHovered>,
+}
+
+impl MapCursor<'_, '_> {
+    fn window(&self) -> &Window {
+        self.windows_q.single()
+    }
+
+    ///World coordinates under the cursor, if it is in the window.
+    fn world_position(&self) -> Option<Vec2> {
+        let (camera, camera_transform) = self.camera_q.single();
+        self.window().cursor_position()
+            .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+            .map(|ray| ray.origin.truncate())
+    }
+
+    ///Returns true if the cursor is over a button or an egui window.
+    fn over_ui(&self) -> bool {
+        self.button_q.iter().any(|interaction| *interaction != Interaction::None) || self.egui_hovered.0
+    }
+}
+
+///The map grid and the tiles on it.
+#[derive(SystemParam)]
+struct MapTiles<'w, 's> {
+    map_grid: Res<'w, MapGrid>,
+    tile_q: Query<'w, 's, &'static mut Tile>,
+}
+
+///The player's stats, and the message log that reports what happens to them.
+#[derive(SystemParam)]
+struct Player<'w, 's> {
+    stats_q: Query<'w, 's, &'static mut PlayerStats>,
+    messages: EventWriter<'w, MessageEvent>,
+}
+
 ///Handles mouse input.
+#[allow(clippy::too_many_arguments)]
 fn mouse_input_handler(
     mut commands: Commands,
     mut cursor_coords: ResMut<CursorWorldCoords>,
-    tile_q: Query<&Tile>, 
+    map_grid: Res<MapGrid>,
+    mut tile_q: Query<&mut Tile>,
+    time: Res<Time>,
+    mut messages: EventWriter<MessageEvent>,
     mouse_input: Res<ButtonInput<MouseButton>>,
     mut evr_scroll: EventReader<MouseWheel>,
-    windows_q: Query<&Window, With<PrimaryWindow>>,
-    camera_q: Query<(&Camera, &GlobalTransform)>,
+    map_cursor: MapCursor,
     mut named_entities_q: Query<(Entity, &Style, &Name), With<Name>>,
     mut pancam_q: Query<&mut PanCam>,
+    build_menu_q: Query<&BuildMenuState>, //open tools take clicks off the map
+    mut player_stats_q: Query<&mut PlayerStats>,
     asset_server: Res<AssetServer>,
 ) {
 
-    // get the camera info and transform
-    // assuming there is exactly one main camera entity, so Query::single() is OK
-    let (camera, camera_transform) = camera_q.single();
-
-    // There is only one primary window, so we can similarly get it from the query:
-    let window = windows_q.single();
+    // There is only one primary window, so Query::single() is OK
+    let window = map_cursor.window();
 
     for _evt in evr_scroll.read() { //Check for scroll events inside the chat box
         
@@ -1192,30 +1296,66 @@ fn mouse_input_handler(
             }
         }
         
+        //Clicks on buttons are handled by the buttons themselves, not the map underneath.
+        if map_cursor.over_ui() {return;}
+
         // check if the cursor is inside the window and get its position
         // then, ask bevy to convert into world coordinates, and truncate to discard Z
-        if let Some(world_position) = window.cursor_position()
-        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
-        .map(|ray| ray.origin.truncate())
-        {
+        if let Some(world_position) = map_cursor.world_position() {
             cursor_coords.0 = world_position;
-            for tile in tile_q.iter() {
-                    //Shift value x by -24, -24 and value 2 (outer value) by +24, +24
-                if world_position.x > tile.location.0 - 24. && world_position.x < (tile.location.0 + TILE_WIDTH as f32 * 3.) && 
-                    world_position.y > tile.location.1 - 24. && world_position.y < (tile.location.1 + TILE_HEIGHT as f32 * 3.) {
-                    
-                    //println!("\n->cursor coords: {}/{}", world_position.x, world_position.y);
-                    //println!("Tile data: {:#?}", tile); //pretty print
-                    if tile.harvest.yields != ResourceItemType::None {
-                        println!("Harvestable!");
-
+            if let Some((tile_entity, mut tile)) = map_grid.get_at_world(world_position.x, world_position.y).and_then(|entity| tile_q.get_mut(entity).ok().map(|tile| (entity, tile))) {
+                //println!("\n->cursor coords: {}/{}", world_position.x, world_position.y);
+                //println!("Tile data: {:#?}", tile); //pretty print
+                let building = build_menu_q.get_single().is_ok_and(|state| state.selected_processor().is_some() || state.selected_track().is_some());
+                if !building { //clicks place buildings in build mode instead
+                    let dexterity = player_stats_q.get_single().ok()
+                        .and_then(|player_stats| harvester_dexterity(&player_stats.loonkas, tile_entity))
+                        .unwrap_or(UNSKILLED_DEXTERITY);
+                    if tile.enemy.enemy_type != EnemyTileType::None {
+                        let strength = player_stats_q.get_single().map_or(0, |player_stats| warrior_strength(&player_stats.loonkas, tile_entity));
+                        match tile.raid(time.elapsed_seconds(), strength, dexterity) {
+                            Ok(Raid::Cleared(looted)) => {
+                                let items: Vec<String> = looted.iter().map(|(item, amount, quality)| format!("{} {} {}", amount, quality.grade(), item.info().name)).collect();
+                                messages.send(MessageEvent(format!("The enemy was driven off! Looted {}.", items.join(", "))));
+                            },
+                            Ok(Raid::Repelled(damage)) => {
+                                messages.send(MessageEvent(format!("The enemy held with {} health left.", tile.enemy.health)));
+                                if let Ok(mut player_stats) = player_stats_q.get_single_mut() {
+                                    for fallen in wound_warriors(&mut player_stats.loonkas, tile_entity, damage) {
+                                        tile.loonkas.retain(|id| *id != fallen.id);
+                                        messages.send(MessageEvent(format!("{} fell in the raid.", fallen.name)));
+                                    }
+                                }
+                            },
+                            Err(HarvestError::NoWarriors) => {
+                                messages.send(MessageEvent("Assign warriors to this tile to raid it.".to_string()));
+                            },
+                            Err(HarvestError::Cooldown(remaining)) => {
+                                messages.send(MessageEvent(format!("The warriors need {:.1}s to regroup.", remaining)));
+                            },
+                            Err(_) => {},
+                        }
+                    } else {
+                        match tile.harvest(time.elapsed_seconds(), dexterity) {
+                            Ok(harvested) => {
+                                let items: Vec<String> = harvested.iter().map(|(item, amount, quality)| format!("{} {} {}", amount, quality.grade(), item.info().name)).collect();
+                                messages.send(MessageEvent(format!("Harvested {}.", items.join(", "))));
+                            },
+                            Err(HarvestError::Cooldown(remaining)) => {
+                                messages.send(MessageEvent(format!("This tile needs {:.1}s to regrow.", remaining)));
+                            },
+                            Err(HarvestError::StockFull) => {
+                                messages.send(MessageEvent("This tile's stock is full!".to_string()));
+                            },
+                            Err(_) => {},
+                        }
                     }
                 }
             }
         };
 
         for (entity, _style,name) in named_entities_q.iter_mut() {
-            if name.0 == "context_menu" {
+            if name.0 == "context_menu" || name.0 == "tile_info" {
                 commands.entity(entity).despawn_recursive();
             }
         }
@@ -1225,28 +1365,19 @@ fn mouse_input_handler(
     if mouse_input.just_pressed(MouseButton::Right) {
         // check if the cursor is inside the window and get its position
         // then, ask bevy to convert into world coordinates, and truncate to discard Z
-        let mut selected_tile: Option<&Tile> = None;
-        if let Some(world_position) = window.cursor_position()
-        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
-        .map(|ray| ray.origin.truncate())
-        {
+        let mut selected_tile: Option<Entity> = None;
+        if let Some(world_position) = map_cursor.world_position() {
             cursor_coords.0 = world_position;
-            for tile in tile_q.iter() {
-                    //Shift value x by -24, -24 and value 2 (outer value) by +24, +24
-                if world_position.x > tile.location.0 - 24. && world_position.x < (tile.location.0 + TILE_WIDTH as f32 * 3.) && 
-                    world_position.y > tile.location.1 - 24. && world_position.y < (tile.location.1 + TILE_HEIGHT as f32 * 3.) {
-                    selected_tile = Some(tile);
-                }
-            }
+            selected_tile = map_grid.get_at_world(world_position.x, world_position.y);
         };
 
         for (entity, _style,name) in named_entities_q.iter_mut() {
-            if name.0 == "context_menu" {
+            if name.0 == "context_menu" || name.0 == "tile_info" {
                 commands.entity(entity).despawn_recursive();
             }
         }
-        if let Some(tile) = selected_tile {
-            get_context_menu(commands,tile, window,asset_server);
+        if let Some(tile_entity) = selected_tile {
+            get_context_menu(commands,tile_entity, window,asset_server);
         };
 
     }
@@ -1259,14 +1390,14 @@ fn init_build_ui(
     windows_q: Query<&Window, With<PrimaryWindow>>,
 ) {
     //let font = asset_server.load(TEXT_FONT);
-    let font_size = TEXT_SIZE_STANDARD;
-    let font_color = TEXT_COLOR_STANDARD;
+    let _font_size = TEXT_SIZE_STANDARD;
+    let _font_color = TEXT_COLOR_STANDARD;
 
-    let mut buttontext = Entity::PLACEHOLDER;
-    let mut buttonbundle = Entity::PLACEHOLDER;
+    let _buttontext = Entity::PLACEHOLDER;
+    let _buttonbundle = Entity::PLACEHOLDER;
 
     // There is only one primary window, so we can similarly get it from the query:
-    let window = windows_q.single();
+    let _window = windows_q.single();
     let root = commands.spawn((
         NodeBundle {
             style: Style {
@@ -1296,7 +1427,7 @@ fn init_build_ui(
                 display:Display::Flex,
                 ..default()
             },
-            image: UiImage::new(asset_server.load(format!("ui/place_track.png"))),
+            image: UiImage::new(asset_server.load("ui/place_track.png")),
             ..default()
         },
         BuildMenuBtn,
@@ -1311,7 +1442,7 @@ fn init_build_ui(
                 display:Display::Flex,
                 ..default()
             },
-            image: UiImage::new(asset_server.load(format!("ui/place_cooler.png"))),
+            image: UiImage::new(asset_server.load("ui/place_cooler.png")),
             //border_radius: BorderRadius { top_left: (Val::Px(15.)), top_right: (Val::Px(15.)), bottom_left: (Val::Px(15.)), bottom_right: (Val::Px(15.))},
             ..default()
         },
@@ -1328,7 +1459,7 @@ fn init_build_ui(
                 display:Display::Flex,
                 ..default()
             },
-            image: UiImage::new(asset_server.load(format!("ui/place_heater.png"))),
+            image: UiImage::new(asset_server.load("ui/place_heater.png")),
             //border_radius: BorderRadius { top_left: (Val::Px(15.)), top_right: (Val::Px(15.)), bottom_left: (Val::Px(15.)), bottom_right: (Val::Px(15.))},
             ..default()
         },
@@ -1345,7 +1476,7 @@ fn init_build_ui(
                 display:Display::Flex,
                 ..default()
             },
-            image: UiImage::new(asset_server.load(format!("ui/place_mixer.png"))),
+            image: UiImage::new(asset_server.load("ui/place_mixer.png")),
             //border_radius: BorderRadius { top_left: (Val::Px(15.)), top_right: (Val::Px(15.)), bottom_left: (Val::Px(15.)), bottom_right: (Val::Px(15.))},
             ..default()
         },
@@ -1362,7 +1493,7 @@ fn init_build_ui(
                 display:Display::Flex,
                 ..default()
             },
-            image: UiImage::new(asset_server.load(format!("ui/place_press.png"))),
+            image: UiImage::new(asset_server.load("ui/place_press.png")),
             //border_radius: BorderRadius { top_left: (Val::Px(15.)), top_right: (Val::Px(15.)), bottom_left: (Val::Px(15.)), bottom_right: (Val::Px(15.))},
             ..default()
         },
@@ -1379,7 +1510,7 @@ fn init_build_ui(
                 display:Display::Flex,
                 ..default()
             },
-            image: UiImage::new(asset_server.load(format!("ui/place_spinner.png"))),
+            image: UiImage::new(asset_server.load("ui/place_spinner.png")),
             //border_radius: BorderRadius { top_left: (Val::Px(15.)), top_right: (Val::Px(15.)), bottom_left: (Val::Px(15.)), bottom_right: (Val::Px(15.))},
             ..default()
         },
@@ -1408,7 +1539,7 @@ fn init_build_ui(
             height: Val::Percent(100.),
             ..default()
         },
-        image: UiImage::new(asset_server.load(format!("ui/selected_button.png"))),
+        image: UiImage::new(asset_server.load("ui/selected_button.png")),
         ..default()
     }).id();
     commands.entity(build_menu_track_button).push_children(&[selector]);
@@ -1418,7 +1549,7 @@ fn init_build_ui(
 
 fn get_context_menu(
     mut commands: Commands,
-    tile: &Tile,
+    tile_entity: Entity,
     window: &Window,
     asset_server: Res<AssetServer>,
 ) {
@@ -1443,7 +1574,7 @@ fn get_context_menu(
                     top: Val::Percent(relative_position.0.abs() * 100.),
                     left: Val::Percent(relative_position.1.abs() * 100.),
                     width: Val::Percent(15.0),
-                    height: Val::Percent(15.0),
+                    height: Val::Percent(18.0),
                     flex_direction: FlexDirection::Column,
                     ..default()
                 },
@@ -1458,7 +1589,7 @@ fn get_context_menu(
                 ButtonBundle {
                     style: Style {
                         width: Val::Percent(100.0),
-                        height: Val::Percent(20.0),
+                        height: Val::Percent(100. / 6.),
                         justify_content: JustifyContent::Center,
                         border: UiRect { left: (Val::Px(2.)), right: (Val::Px(2.)), top: (Val::Px(2.)), bottom: (Val::Px(2.))},
                         align_items: AlignItems::Center,
@@ -1469,15 +1600,15 @@ fn get_context_menu(
                     ..default()
                 },
                 Name("show_tile_info".to_string()),
+                TileButton(tile_entity),
             )).with_children(|button| {
                 buttontext = button.spawn((
                     TextBundle::from_section(
-                        format!("Inspect"),
+                        "Inspect".to_string(),
                         TextStyle {
                             font: font.clone(),
-                            font_size: font_size,
+                            font_size,
                             color: font_color,
-                            ..default()
                         },
                     ),
                 )).id();
@@ -1489,7 +1620,7 @@ fn get_context_menu(
                 ButtonBundle {
                     style: Style {
                         width: Val::Percent(100.0),
-                        height: Val::Percent(20.0),
+                        height: Val::Percent(100. / 6.),
                         justify_content: JustifyContent::Center,
                         align_items: AlignItems::Center,
                         border: UiRect { left: (Val::Px(2.)), right: (Val::Px(2.)), top: (Val::Px(0.)), bottom: (Val::Px(2.))},
@@ -1498,16 +1629,16 @@ fn get_context_menu(
                     border_color: BG_BORDER_COLOR.into(),
                     ..default()
                 },
-                Name("show_tile_info".to_string()),
+                Name("assign_loonka".to_string()),
+                TileButton(tile_entity),
             )).with_children(|button| {
                 buttontext = button.spawn((
                     TextBundle::from_section(
-                        format!("Assign Loonka"),
+                        "Assign Loonka".to_string(),
                         TextStyle {
                             font: font.clone(),
-                            font_size: font_size,
+                            font_size,
                             color: font_color,
-                            ..default()
                         },
                     ),
                 )).id();
@@ -1519,7 +1650,7 @@ fn get_context_menu(
                 ButtonBundle {
                     style: Style {
                         width: Val::Percent(100.0),
-                        height: Val::Percent(20.0),
+                        height: Val::Percent(100. / 6.),
                         justify_content: JustifyContent::Center,
                         align_items: AlignItems::Center,
                         border: UiRect { left: (Val::Px(2.)), right: (Val::Px(2.)), top: (Val::Px(0.)), bottom: (Val::Px(2.))},
@@ -1528,16 +1659,107 @@ fn get_context_menu(
                     border_color: BG_BORDER_COLOR.into(),
                     ..default()
                 },
-                Name("show_tile_info".to_string()),
+                Name("unassign_loonka".to_string()),
+                TileButton(tile_entity),
             )).with_children(|button| {
                 buttontext = button.spawn((
                     TextBundle::from_section(
-                        format!("Unassign Loonka"),
+                        "Unassign Loonka".to_string(),
                         TextStyle {
                             font: font.clone(),
-                            font_size: font_size,
+                            font_size,
+                            color: font_color,
+                        },
+                    ),
+                )).id();
+            }).id();
+        })
+
+        .with_children(|menu| {
+            buttonbundle = menu.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100. / 6.),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        border: UiRect { left: (Val::Px(2.)), right: (Val::Px(2.)), top: (Val::Px(0.)), bottom: (Val::Px(2.))},
+                        ..default()
+                    },
+                    border_color: BG_BORDER_COLOR.into(),
+                    ..default()
+                },
+                Name("sell_candy".to_string()),
+                TileButton(tile_entity),
+            )).with_children(|button| {
+                buttontext = button.spawn((
+                    TextBundle::from_section(
+                        "Sell Candy".to_string(),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size,
+                            color: font_color,
+                        },
+                    ),
+                )).id();
+            }).id();
+        })
+
+        .with_children(|menu| {
+            buttonbundle = menu.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100. / 6.),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        border: UiRect { left: (Val::Px(2.)), right: (Val::Px(2.)), top: (Val::Px(0.)), bottom: (Val::Px(2.))},
+                        ..default()
+                    },
+                    border_color: BG_BORDER_COLOR.into(),
+                    ..default()
+                },
+                Name("add_stop".to_string()),
+                TileButton(tile_entity),
+            )).with_children(|button| {
+                buttontext = button.spawn((
+                    TextBundle::from_section(
+                        "Add Stop".to_string(),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size,
+                            color: font_color,
+                        },
+                    ),
+                )).id();
+            }).id();
+        })
+
+        .with_children(|menu| {
+            buttonbundle = menu.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100. / 6.),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        border: UiRect { left: (Val::Px(2.)), right: (Val::Px(2.)), top: (Val::Px(0.)), bottom: (Val::Px(2.))},
+                        ..default()
+                    },
+                    border_color: BG_BORDER_COLOR.into(),
+                    border_radius: BorderRadius { top_left: (Val::Px(0.)), top_right: (Val::Px(0.)), bottom_left: (Val::Px(15.)), bottom_right: (Val::Px(15.))},
+                    ..default()
+                },
+                Name("haul".to_string()),
+                TileButton(tile_entity),
+            )).with_children(|button| {
+                buttontext = button.spawn((
+                    TextBundle::from_section(
+                        "Haul".to_string(),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size,
                             color: font_color,
-                            ..default()
                         },
                     ),
                 )).id();
@@ -1548,24 +1770,712 @@ fn get_context_menu(
         ;}
 }
     
-fn reload_on_r( //Reload map textures on 'r' press
+///The build bar's tool, and the mouse, keys and cursor that work it on the map.
+#[derive(SystemParam)]
+struct BuildTool<'w, 's> {
+    mouse_input: Res<'w, ButtonInput<MouseButton>>,
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    map_cursor: MapCursor<'w, 's>,
+    build_menu_q: Query<'w, 's, &'static mut BuildMenuState>,
+}
+
+///Built processors, and the arrows showing where their output goes.
+#[derive(SystemParam)]
+struct ProcessorSprites<'w, 's> {
+    processor_q: Query<'w, 's, (Entity, &'static mut Processor, &'static Children)>,
+    arrow_q: Query<'w, 's, (&'static mut Text, &'static mut Transform), With<ProcessorArrow>>,
+}
+
+///Places and removes processors on clicked tiles while a processor is selected in the build bar.
+///Clicking a built processor turns its output clockwise. Shift-click removes the processor on a tile, ctrl-click removes every processor of the selected type. Escape leaves build mode.
+fn build_mode_handler(
     mut commands: Commands,
+    build_tool: BuildTool,
+    map_tiles: MapTiles,
+    processor_sprites: ProcessorSprites,
+    rail_graph: Res<RailGraph>,
+    player: Player,
     asset_server: Res<AssetServer>,
-    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
-    tilebasetype_q: Query<Entity, With<TileBaseType>>,
 ) {
-    for entities in &tilebasetype_q {
-        commands.entity(entities).despawn(); //despawn all entities
-    };
-    let texture: Handle<Image> = asset_server.load(SPRITE_SHEET_PATH);
+    let BuildTool { mouse_input, keys, map_cursor, mut build_menu_q } = build_tool;
+    let MapTiles { map_grid, mut tile_q } = map_tiles;
+    let ProcessorSprites { mut processor_q, mut arrow_q } = processor_sprites;
+    let Player { mut stats_q, mut messages } = player;
+    let Ok(mut build_menu_state) = build_menu_q.get_single_mut() else {return;};
+    let Ok(mut player_stats) = stats_q.get_single_mut() else {return;};
+    if keys.just_pressed(KeyCode::Escape) {
+        build_menu_state.selected = None;
+        return;
+    }
+    let Some((processor_type, placing)) = build_menu_state.selected_processor() else {return;};
+    if !mouse_input.just_pressed(MouseButton::Left) {return;}
+    if map_cursor.over_ui() {return;} //clicking the build bar or an egui window itself
+
+    let Some(world_position) = map_cursor.world_position() else {return;};
+    let Some(tile_entity) = map_grid.get_at_world(world_position.x, world_position.y) else {return;};
+    let processor_name = processor_type.name();
+
+    if keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        let (mut removed, mut kept) = (0, 0);
+        for (entity, mut processor, _) in processor_q.iter_mut().filter(|(_, processor, _)| processor.processor_type == processor_type) {
+            let Ok(mut tile) = tile_q.get_mut(processor.tile) else {continue;};
+            if !processor.scrap_batch(&mut tile.stock) {
+                kept += 1;
+                continue;
+            }
+            tile.processor = None;
+            commands.entity(entity).despawn_recursive();
+            player_stats.gold += processor_type.cost();
+            removed += 1;
+        }
+        messages.send(MessageEvent(format!("Removed {} {}s. Gold: {}", removed, processor_name, player_stats.gold)));
+        if kept > 0 {
+            messages.send(MessageEvent(format!("Kept {} {}s: their batches' ingredients don't fit back in the stockpile.", kept, processor_name)));
+        }
+        return;
+    }
+
+    let Ok(mut tile) = tile_q.get_mut(tile_entity) else {return;};
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) || !placing {
+        let Some((entity, mut processor, _)) = tile.processor.and_then(|entity| processor_q.get_mut(entity).ok()) else {return;};
+        if !processor.scrap_batch(&mut tile.stock) {
+            messages.send(MessageEvent(format!("The {}'s batch won't fit back in the stockpile. Make room before removing it.", processor.processor_type.name())));
+            return;
+        }
+        commands.entity(entity).despawn_recursive();
+        tile.processor = None;
+        player_stats.gold += processor.processor_type.cost();
+        messages.send(MessageEvent(format!("Removed a {}. Gold: {}", processor.processor_type.name(), player_stats.gold)));
+        return;
+    }
+    if let Some((_, mut processor, children)) = tile.processor.and_then(|entity| processor_q.get_mut(entity).ok()) {
+        processor.output = processor.output.rotate_clockwise();
+        for child in children.iter() {
+            if let Ok((mut text, mut transform)) = arrow_q.get_mut(*child) {
+                text.sections[0].value = processor.output.arrow().to_string();
+                *transform = arrow_transform(processor.output);
+            }
+        }
+        messages.send(MessageEvent(format!("The {} now outputs {:?}.", processor.processor_type.name(), processor.output)));
+        return;
+    }
+
+    let on_track = world_to_grid(tile.location.0, tile.location.1).is_some_and(|pos| rail_graph.has_track(pos));
+    let result = if on_track {Err(BuildError::Occupied)} else {check_build(processor_type, &tile, player_stats.gold)};
+    match result {
+        Ok(()) => {
+            let size = (TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32;
+            let new_processor = Processor::new(processor_type, tile_entity);
+            let output = new_processor.output;
+            let processor = commands.spawn((
+                SpriteBundle {
+                    texture: asset_server.load(processor_type.sprite_path()),
+                    sprite: Sprite { custom_size: Some(Vec2::splat(size)), ..default() },
+                    transform: Transform::from_xyz(tile.location.0, tile.location.1, 2.), //above both tilemap layers
+                    ..default()
+                },
+                new_processor,
+            )).with_children(|processor| {
+                processor.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(StallReason::NoWorkers.describe(), TextStyle {
+                            font: asset_server.load(TEXT_FONT),
+                            font_size: TEXT_SIZE_STANDARD,
+                            color: TEXT_COLOR_HIGHLIGHT,
+                        }),
+                        transform: Transform::from_xyz(0., size * 0.75, 1.),
+                        ..default()
+                    },
+                    ProcessorLabel,
+                ));
+                processor.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(output.arrow(), TextStyle {
+                            font: asset_server.load(TEXT_FONT),
+                            font_size: TEXT_SIZE_STANDARD,
+                            color: TEXT_COLOR_HIGHLIGHT,
+                        }),
+                        transform: arrow_transform(output),
+                        ..default()
+                    },
+                    ProcessorArrow,
+                ));
+            }).id();
+            tile.processor = Some(processor);
+            player_stats.gold -= processor_type.cost();
+            messages.send(MessageEvent(format!("Built a {} for {} gold. Gold: {}", processor_name, processor_type.cost(), player_stats.gold)));
+        },
+        Err(BuildError::Occupied) => {
+            messages.send(MessageEvent("There's no room to build here.".to_string()));
+        },
+        Err(BuildError::BadTerrain) => {
+            messages.send(MessageEvent(format!("A {} can't be built on {:?}.", processor_name, tile.base_type)));
+        },
+        Err(BuildError::NotEnoughGold) => {
+            messages.send(MessageEvent(format!("A {} costs {} gold, you have {}.", processor_name, processor_type.cost(), player_stats.gold)));
+        },
+    }
+}
 
-    let layout = TextureAtlasLayout::from_grid(
-        UVec2::new(TILE_WIDTH as u32, TILE_HEIGHT as u32), MAP_COLS, MAP_ROWS, None, None);
+///The tilemap layer track is drawn on, and its sprites.
+#[derive(SystemParam)]
+struct RailSprites<'w, 's> {
+    rail_layer_q: Query<'w, 's, (Entity, &'static mut TileStorage), With<RailLayer>>,
+    texture_q: Query<'w, 's, (&'static mut TileTextureIndex, &'static mut TileColor)>,
+}
 
-    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+///Lays and lifts track while the track tool is selected in the build bar. Each piece joins the track beside it, and dragging lays a line.
+///Holding X while dragging joins each piece only to the one before, to run track side by side.
+///Shift lifts track instead, ctrl-click lifts all of it, and alt-click puts a signal on a piece of track or takes it off.
+fn track_mode_handler(
+    mut commands: Commands,
+    build_tool: BuildTool,
+    map_tiles: MapTiles,
+    mut rail_graph: ResMut<RailGraph>,
+    rail_sprites: RailSprites,
+    player: Player,
+    mut dragged_from: Local<Option<(usize, usize)>>, //tile the cursor was last over while laying track
+) {
+    let BuildTool { mouse_input, keys, map_cursor, build_menu_q } = build_tool;
+    let MapTiles { map_grid, tile_q } = map_tiles;
+    let Player { mut stats_q, mut messages } = player;
+    if !mouse_input.pressed(MouseButton::Left) {
+        *dragged_from = None;
+        return;
+    }
+    let Some(laying) = build_menu_q.get_single().ok().and_then(|state| state.selected_track()) else {return;};
+    if map_cursor.over_ui() {return;}
+    let Ok(mut player_stats) = stats_q.get_single_mut() else {return;};
+    let RailSprites { mut rail_layer_q, mut texture_q } = rail_sprites;
+    let Ok((rail_layer, mut storage)) = rail_layer_q.get_single_mut() else {return;};
+
+    if keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        if !mouse_input.just_pressed(MouseButton::Left) {return;}
+        let lifted = rail_graph.tracks().count() as i32;
+        for entity in storage.iter().flatten() {
+            commands.entity(*entity).despawn();
+        }
+        *storage = TileStorage::empty(storage.size);
+        rail_graph.clear();
+        player_stats.gold += lifted * TRACK_COST;
+        messages.send(MessageEvent(format!("Lifted {} track. Gold: {}", lifted, player_stats.gold)));
+        return;
+    }
 
-    generate_new_map(commands, texture, texture_atlas_layout, Some(tilebasetype_q));
-    
+    let Some(world_position) = map_cursor.world_position() else {return;};
+    let Some(pos) = world_to_grid(world_position.x, world_position.y) else {return;};
+    let Some(tile) = map_grid.get(pos.0, pos.1).and_then(|entity| tile_q.get(entity).ok()) else {return;};
+
+    if keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) {
+        if !mouse_input.just_pressed(MouseButton::Left) || !rail_graph.has_track(pos) {return;}
+        let signal = rail_graph.toggle_signal(pos);
+        messages.send(MessageEvent(if signal {"Signal placed.".to_string()} else {"Signal removed.".to_string()}));
+    } else if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) || !laying {
+        if !rail_graph.remove(pos) {return;}
+        player_stats.gold += TRACK_COST;
+    } else {
+        //track joins the piece the drag came from, including track that was already there
+        let from = dragged_from.replace(pos).filter(|from| *from != pos);
+        let side_by_side = keys.pressed(KeyCode::KeyX);
+        if rail_graph.has_track(pos) {
+            if !from.is_some_and(|from| rail_graph.connect(from, pos)) {return;}
+        } else if let Err(err) = check_track(tile, player_stats.gold) {
+            if mouse_input.just_pressed(MouseButton::Left) { //not every frame of a drag
+                messages.send(MessageEvent(match err {
+                    BuildError::Occupied => "There's no room for track here.".to_string(),
+                    BuildError::BadTerrain => format!("Track can't be laid on {:?}.", tile.base_type),
+                    BuildError::NotEnoughGold => format!("Track costs {} gold, you have {}.", TRACK_COST, player_stats.gold),
+                }));
+            }
+            return;
+        } else {
+            if side_by_side {
+                rail_graph.place(pos);
+                if let Some(from) = from {rail_graph.connect(from, pos);}
+            } else {
+                rail_graph.place_joined(pos);
+            }
+            player_stats.gold -= TRACK_COST;
+        }
+    }
+
+    //the piece and its neighbours may all have changed shape
+    for pos in std::iter::once(pos).chain(MapGrid::neighbours(pos.0, pos.1)) {
+        let tile_pos = TilePos { x: pos.0 as u32, y: pos.1 as u32 };
+        let sprite = TileTextureIndex(rail_graph.sprite_index(pos));
+        let color = TileColor(if rail_graph.has_signal(pos) {SIGNAL_COLOR} else {Color::WHITE}); //signals tint their track
+        match (rail_graph.has_track(pos), storage.get(&tile_pos)) {
+            (true, Some(entity)) => {
+                if let Ok((mut texture, mut tile_color)) = texture_q.get_mut(entity) {
+                    *texture = sprite;
+                    *tile_color = color;
+                }
+            },
+            (true, None) => {
+                let entity = commands.spawn(TileBundle {
+                    position: tile_pos,
+                    tilemap_id: TilemapId(rail_layer),
+                    texture_index: sprite,
+                    color,
+                    ..default()
+                }).id();
+                storage.set(&tile_pos, entity);
+            },
+            (false, Some(entity)) => {
+                commands.entity(entity).despawn();
+                storage.remove(&tile_pos);
+            },
+            (false, None) => {},
+        }
+    }
+}
+
+///Places a processor's output arrow on the edge of its tile facing the output.
+fn arrow_transform(output: Direction) -> Transform {
+    let (dx, dy) = output.offset();
+    let distance = (TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32 * 0.4;
+    Transform::from_xyz(dx as f32 * distance, dy as f32 * distance, 1.)
+}
+
+///Runs every processor on its tile's stock, at the speed of the factory workers assigned to the tile.
+///Processors pass chained batches on to the processor their output points at, forming production lines.
+fn run_processors(
+    time: Res<Time>,
+    recipe_book: Res<RecipeBook>,
+    mut research: ResMut<Research>,
+    map_tiles: MapTiles,
+    mut processor_q: Query<(Entity, &mut Processor, &Children)>,
+    player_stats_q: Query<&PlayerStats>,
+    mut label_q: Query<&mut Text, With<ProcessorLabel>>,
+) {
+    let MapTiles { map_grid, mut tile_q } = map_tiles;
+    let Ok(player_stats) = player_stats_q.get_single() else {return;};
+
+    //the processor each processor's output points at, worked out before any of them change
+    let types: HashMap<Entity, ProcessorType> = processor_q.iter().map(|(entity, processor, _)| (entity, processor.processor_type)).collect();
+    let downstream: HashMap<Entity, Entity> = processor_q.iter().filter_map(|(entity, processor, _)| {
+        let location = tile_q.get(processor.tile).ok()?.location;
+        let (x, y) = world_to_grid(location.0, location.1)?;
+        let (next_x, next_y) = MapGrid::step(x, y, processor.output)?;
+        let next = tile_q.get(map_grid.get(next_x, next_y)?).ok()?.processor?;
+        Some((entity, next))
+    }).collect();
+
+    for (entity, mut processor, children) in processor_q.iter_mut() {
+        let mut line = vec![processor.processor_type];
+        let mut current = entity;
+        while let Some(next) = downstream.get(&current).filter(|_| line.len() < MAX_CHAIN_LENGTH) {
+            line.push(types[next]);
+            current = *next;
+        }
+        if processor.line != line {processor.line = line.clone();} //for the logistics network
+        let Ok(mut tile) = tile_q.get_mut(processor.tile) else {continue;};
+        let workers = player_stats.loonkas.iter().filter(|loonka| loonka.tile == Some(processor.tile));
+        let crew = factory_crew(workers);
+        let old_status = processor.status;
+        if let Some((item, amount)) = processor.tick(&mut tile.stock, &recipe_book, research.discovered(), &line, crew, time.delta_seconds()) {
+            research.record_produced(item, amount);
+        }
+
+        if processor.status != old_status {
+            for child in children.iter() {
+                if let Ok(mut label) = label_q.get_mut(*child) {
+                    label.sections[0].value = match processor.status {
+                        ProcessorStatus::Working => String::new(),
+                        ProcessorStatus::Stalled(reason) => reason.describe(),
+                    };
+                }
+            }
+        }
+    }
+
+    for (from, to) in downstream {
+        if let Ok([(_, mut from, _), (_, mut to, _)]) = processor_q.get_many_mut([from, to]) {
+            from.hand_off(&mut to, &recipe_book);
+        }
+    }
+}
+
+///Notes whether the cursor is over any egui window.
+fn update_egui_hovered(mut contexts: EguiContexts, mut egui_hovered: ResMut<EguiHovered>) {
+    egui_hovered.0 = contexts.ctx_mut().is_pointer_over_area();
+}
+
+///Draws the candy codex while it is open. C opens and closes it.
+fn codex_window(
+    mut contexts: EguiContexts,
+    mut codex: ResMut<CodexState>,
+    recipe_book: Res<RecipeBook>,
+    research: Res<Research>,
+) {
+    if !codex.open {return;}
+    show_codex(contexts.ctx_mut(), &mut codex, &recipe_book, &research);
+}
+
+///Lists the loonkas free to work on the tile picked with Assign Loonka, so the player can choose who goes there.
+fn loonka_picker_window(
+    mut contexts: EguiContexts,
+    mut picker: ResMut<LoonkaPicker>,
+    mut tile_q: Query<&mut Tile>,
+    travellers: Travellers,
+    mut player_stats_q: Query<&mut PlayerStats>,
+    mut messages: EventWriter<MessageEvent>,
+) {
+    let Some(tile_entity) = picker.0 else {return;};
+    if !tile_q.contains(tile_entity) { //the map was made anew
+        picker.0 = None;
+        return;
+    }
+    let Ok(mut player_stats) = player_stats_q.get_single_mut() else {return;};
+    let travelling = travellers.loonkas();
+    let mut open = true;
+    let mut chosen = None;
+    egui::Window::new("Assign a loonka").open(&mut open).default_width(320.).show(contexts.ctx_mut(), |ui| {
+        let free: Vec<&Loonka> = player_stats.loonkas.iter().filter(|loonka| loonka.tile.is_none() && !travelling.contains(&loonka.id)).collect();
+        if free.is_empty() {
+            ui.label("Every loonka already has a tile.");
+        }
+        for loonka in free {
+            ui.horizontal(|ui| {
+                if ui.button("Assign").clicked() {
+                    chosen = Some(loonka.id);
+                }
+                ui.label(format!("{}, {:?}", loonka.name, loonka.current_job))
+                    .on_hover_text(format!("Speed: {}, Charisma: {}, Strength: {}, Dexterity: {}, Intellect: {}, Vigor: {}",
+                        loonka.speed, loonka.charisma, loonka.strength, loonka.dexterity, loonka.intellect, loonka.vigor));
+            });
+        }
+    });
+    if let Some(id) = chosen {
+        let loonka = player_stats.loonkas.iter_mut().find(|loonka| loonka.id == id);
+        if let (Some(loonka), Ok(mut tile)) = (loonka, tile_q.get_mut(tile_entity)) {
+            loonka.tile = Some(tile_entity);
+            tile.loonkas.push(loonka.id);
+            messages.send(MessageEvent(format!("{} now works here as a {:?}.", loonka.name, loonka.current_job)));
+        }
+        open = false;
+    }
+    if !open {picker.0 = None;}
+}
+
+///Researchers turn the research budget into knowledge at a rate set by their intellect, and knowledge discovers recipes.
+fn run_research(
+    time: Res<Time>,
+    recipe_book: Res<RecipeBook>,
+    mut research: ResMut<Research>,
+    mut player_stats_q: Query<&mut PlayerStats>,
+    mut messages: EventWriter<MessageEvent>,
+) {
+    let Ok(mut player_stats) = player_stats_q.get_single_mut() else {return;};
+    let intellect = player_stats.loonkas.iter()
+        .filter(|loonka| loonka.current_job == LoonkaJob::Researcher)
+        .map(|loonka| loonka.intellect)
+        .sum();
+    player_stats.knowledge += research.study(intellect, time.delta_seconds());
+
+    for recipe in research.discover(&recipe_book, &mut player_stats.knowledge) {
+        let recipe = &recipe_book.recipes()[recipe];
+        let processors: Vec<&str> = recipe.processors.iter().map(|processor| processor.name()).collect();
+        messages.send(MessageEvent(format!("Discovered {} ({}).", recipe.output.info().name, processors.join(", "))));
+    }
+}
+
+///The tile context menu's buttons, and the choices they leave open.
+#[derive(SystemParam)]
+struct ContextMenu<'w, 's> {
+    interaction_q: Query<'w, 's, (&'static Interaction, &'static Name, &'static TileButton), Changed<Interaction>>,
+    named_entities_q: Query<'w, 's, (Entity, &'static Name)>,
+    picks: TilePicks<'w>,
+}
+
+///What the tile info panel shows beside the tile itself, and where and how it is drawn.
+#[derive(SystemParam)]
+struct TileInfoPanel<'w, 's> {
+    processor_q: Query<'w, 's, &'static Processor>,
+    windows_q: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    asset_server: Res<'w, AssetServer>,
+    item_icons: Res<'w, ItemIcons>,
+}
+
+///Handles presses on the tile context menu buttons.
+fn context_menu_buttons(
+    mut commands: Commands,
+    context_menu: ContextMenu,
+    map_tiles: MapTiles,
+    rail_graph: Res<RailGraph>,
+    mut fleet: Fleet,
+    player: Player,
+    tile_info_panel: TileInfoPanel,
+) {
+    let ContextMenu { interaction_q, named_entities_q, mut picks } = context_menu;
+    let MapTiles { map_grid, mut tile_q } = map_tiles;
+    let Player { mut stats_q, mut messages } = player;
+    let TileInfoPanel { processor_q, windows_q, asset_server, item_icons } = tile_info_panel;
+    let Ok(mut player_stats) = stats_q.get_single_mut() else {return;};
+    for (interaction, name, tile_button) in &interaction_q {
+        if *interaction != Interaction::Pressed {continue;}
+        for (entity, open_name) in &named_entities_q { //close the menu and any open tile info
+            if open_name.0 == "context_menu" || open_name.0 == "tile_info" {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        let Ok(mut tile) = tile_q.get_mut(tile_button.0) else {continue;};
+        match name.0.as_str() {
+            "show_tile_info" => {
+                let processor = tile.processor.and_then(|entity| processor_q.get(entity).ok());
+                let track = world_to_grid(tile.location.0, tile.location.1)
+                    .filter(|pos| rail_graph.has_track(*pos))
+                    .map(|pos| (rail_graph.piece(pos), rail_graph.has_signal(pos)));
+                let hauls: Vec<String> = fleet.walker_q.iter()
+                    .filter(|walker| world_to_grid(tile.location.0, tile.location.1).is_some_and(|pos| pos == walker.source || pos == walker.destination))
+                    .filter_map(|walker| {
+                        let loonka = player_stats.loonkas.iter().find(|loonka| loonka.id == walker.loonka)?;
+                        Some(format!("{} ({}, carrying {})", loonka.name, walker.status.describe(), walker.load.total()))
+                    })
+                    .chain(fleet.ship_q.iter()
+                        .filter(|ship| world_to_grid(tile.location.0, tile.location.1).is_some_and(|pos| ship.ports.contains(&pos)))
+                        .map(|ship| format!("Ship {} ({}, carrying {})", ship.id, ship.status.describe(), ship.cargo.total())))
+                    .collect();
+                get_tile_info(&mut commands, &tile, TileOccupants {processor, track, hauls: &hauls}, windows_q.single(), &asset_server, &item_icons);
+            },
+            "assign_loonka" => { //the player picks who moves here
+                let travelling = fleet.travelling();
+                if player_stats.loonkas.iter().any(|loonka| loonka.tile.is_none() && !travelling.contains(&loonka.id)) {
+                    picks.loonka_picker.0 = Some(tile_button.0);
+                } else {
+                    messages.send(MessageEvent("Every loonka already has a tile.".to_string()));
+                }
+            },
+            "unassign_loonka" => {
+                match tile.loonkas.pop() {
+                    Some(removed) => {
+                        if let Some(loonka) = player_stats.loonkas.iter_mut().find(|loonka| loonka.id == removed) {
+                            loonka.tile = None;
+                            messages.send(MessageEvent(format!("{} left this tile.", loonka.name)));
+                        }
+                    },
+                    None => {
+                        messages.send(MessageEvent("No loonkas work here.".to_string()));
+                    },
+                }
+            },
+            "sell_candy" => { //outposts buy every candy stored on them
+                if tile.outpost.outpost_type == OutpostType::None {
+                    messages.send(MessageEvent("Only outposts buy candy.".to_string()));
+                    continue;
+                }
+                let mut earned = 0;
+                let mut sold = 0;
+                for (item, count) in tile.stock.iter().collect::<Vec<_>>() {
+                    let Some(price) = tile.outpost.price(item, tile.stock.quality(item)) else {continue;};
+                    tile.stock.remove(item, count);
+                    earned += price * count as i32;
+                    sold += count;
+                }
+                player_stats.gold += earned;
+                messages.send(MessageEvent(format!("Sold {} candy for {} gold. Gold: {}", sold, earned, player_stats.gold)));
+            },
+            "haul" => { //pick the stockpile to haul from, then the one to haul to
+                let Some(pos) = world_to_grid(tile.location.0, tile.location.1) else {continue;};
+                let Some(source) = picks.haul_source.0.take() else {
+                    picks.haul_source.0 = Some(pos);
+                    messages.send(MessageEvent("Hauling from here. Pick Haul on the tile to carry goods to.".to_string()));
+                    continue;
+                };
+                if source == pos {
+                    messages.send(MessageEvent("Haul cancelled.".to_string()));
+                    continue;
+                }
+                let travelling = fleet.travelling();
+                let Some((loonka_id, loonka_name, vigor)) = player_stats.loonkas.iter()
+                    .find(|loonka| loonka.current_job == LoonkaJob::Traveller && loonka.tile.is_none() && !travelling.contains(&loonka.id))
+                    .map(|loonka| (loonka.id, loonka.name.clone(), loonka.vigor)) else {
+                    messages.send(MessageEvent("There's no free traveller to haul goods.".to_string()));
+                    continue;
+                };
+                let tile_at = |pos: (usize, usize)| map_grid.get(pos.0, pos.1).and_then(|entity| tile_q.get(entity).ok());
+
+                //hauls to or from a ship outpost go by sea
+                if [source, pos].into_iter().any(|end| tile_at(end).is_some_and(|tile| tile.outpost.outpost_type == OutpostType::ShipOutpost)) {
+                    let sailable = |pos| tile_at(pos).is_some_and(|tile| tile.base_type.sail_cost().is_some());
+                    let (Some(from_dock), Some(to_dock)) = (dock_for(source, sailable), dock_for(pos, sailable)) else {
+                        messages.send(MessageEvent("Ships can only trade with ports on the water or the coast.".to_string()));
+                        continue;
+                    };
+                    if player_stats.gold < SHIP_COST {
+                        messages.send(MessageEvent(format!("A ship costs {} gold, you have {}.", SHIP_COST, player_stats.gold)));
+                        continue;
+                    }
+                    player_stats.gold -= SHIP_COST;
+                    let id = fleet.ship_q.iter().map(|ship| ship.id + 1).max().unwrap_or(0);
+                    let (x, y) = grid_to_world(from_dock.0 as f32, from_dock.1 as f32);
+                    commands.spawn((
+                        SpriteBundle {
+                            sprite: Sprite { color: SHIP_COLOR, custom_size: Some(Vec2::splat((TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32 * 0.7)), ..default() },
+                            transform: Transform::from_xyz(x, y, 3.),
+                            ..default()
+                        },
+                        Ship::new(id, [source, pos], [from_dock, to_dock], loonka_id),
+                    ));
+                    messages.send(MessageEvent(format!("Built ship {} for {} gold, sailed by {}. Gold: {}", id, SHIP_COST, loonka_name, player_stats.gold)));
+                    continue;
+                }
+
+                let (x, y) = grid_to_world(source.0 as f32, source.1 as f32);
+                commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite { color: WALKER_COLOR, custom_size: Some(Vec2::splat((TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32 * 0.3)), ..default() },
+                        transform: Transform::from_xyz(x, y, 3.),
+                        ..default()
+                    },
+                    Walker::new(loonka_id, vigor, source, pos),
+                ));
+                messages.send(MessageEvent(format!("{} will carry up to {} goods at a time.", loonka_name, carry_capacity(vigor))));
+            },
+            "add_stop" => { //to the selected train, or to a new one built here
+                let Some(pos) = world_to_grid(tile.location.0, tile.location.1).filter(|pos| rail_graph.has_track(*pos)) else {
+                    messages.send(MessageEvent("Trains can only stop on track.".to_string()));
+                    continue;
+                };
+                if let Some((_, mut train)) = fleet.panel.selected.and_then(|entity| fleet.train_q.get_mut(entity).ok()) {
+                    train.stops.push(Stop::new(pos));
+                    train.path.clear();
+                    messages.send(MessageEvent(format!("Train {} now has {} stops.", train.id, train.stops.len())));
+                    continue;
+                }
+                if player_stats.gold < TRAIN_COST {
+                    messages.send(MessageEvent(format!("A train costs {} gold, you have {}.", TRAIN_COST, player_stats.gold)));
+                    continue;
+                }
+                player_stats.gold -= TRAIN_COST;
+                let id = fleet.train_q.iter().map(|(_, train)| train.id + 1).max().unwrap_or(0);
+                let mut train = Train::new(id, pos);
+                train.stops.push(Stop::new(pos));
+                let train = commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite { color: TRAIN_COLOR, custom_size: Some(Vec2::splat((TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32 * 0.6)), ..default() },
+                        transform: Transform::from_xyz(tile.location.0, tile.location.1, 3.), //above processors
+                        ..default()
+                    },
+                    train,
+                )).id();
+                fleet.panel.selected = Some(train);
+                fleet.panel.open = true;
+                messages.send(MessageEvent(format!("Built train {} for {} gold. Gold: {}", id, TRAIN_COST, player_stats.gold)));
+            },
+            _ => {},
+        }
+    }
+}
+
+///What stands on or passes through a tile, shown in its info panel.
+struct TileOccupants<'a> {
+    processor: Option<&'a Processor>,
+    track: Option<(TrackPiece, bool)>, //piece, and whether it has a signal
+    hauls: &'a [String], //loonkas hauling goods to or from the tile
+}
+
+///Spawns a panel at the cursor describing the tile and everything stored on it.
+fn get_tile_info(
+    commands: &mut Commands,
+    tile: &Tile,
+    occupants: TileOccupants,
+    window: &Window,
+    asset_server: &AssetServer,
+    item_icons: &ItemIcons,
+) {
+    let Some(cursor_translate) = window.cursor_position() else {return;};
+    let relative_position = (
+        (cursor_translate.y / window.height()).abs(),
+        (cursor_translate.x / window.width()).abs(),
+    );
+
+    let mut lines = vec![
+        format!("{:?}", tile.base_type),
+    ];
+    if tile.outpost.outpost_type != OutpostType::None {
+        lines.push(format!("{:?} (sales {})", tile.outpost.outpost_type, tile.outpost.sales_mod));
+    }
+    if tile.harvest.yields != ResourceItemType::None {
+        lines.push(format!("Yields {} x{}", tile.harvest.yields.info().name, tile.harvest.amount_mod));
+    }
+    if tile.enemy.enemy_type != EnemyTileType::None {
+        lines.push(format!("Enemy: {:?}", tile.enemy.enemy_type));
+    }
+    if let Some(processor) = occupants.processor {
+        lines.push(format!("{}: {}", processor.processor_type.name(), match processor.status {
+            ProcessorStatus::Working => "Working".to_string(),
+            ProcessorStatus::Stalled(reason) => reason.describe(),
+        }));
+        lines.push(format!("Output: {:?}", processor.output));
+    }
+    if let Some((track, signal)) = occupants.track {
+        lines.push(format!("Track: {:?}{}", track, if signal {" with signal"} else {""}));
+    }
+    lines.push(format!("Loonkas: {}", tile.loonkas.len()));
+    for haul in occupants.hauls {
+        lines.push(format!("Hauler: {}", haul));
+    }
+    lines.push(format!("Stock ({}/{})", tile.stock.total(), tile.stock.capacity()));
+    let stock: Vec<(ResourceItemType, String)> = tile.stock.iter()
+        .map(|(item, count)| (item, format!("{}: {} ({})", item.info().name, count, tile.stock.quality(item).grade())))
+        .collect();
+
+    let text_style = TextStyle {
+        font: asset_server.load(TEXT_FONT),
+        font_size: TEXT_SIZE_STANDARD,
+        color: TEXT_COLOR_STANDARD,
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Percent(relative_position.0 * 100.),
+                left: Val::Percent(relative_position.1 * 100.),
+                min_width: Val::Percent(15.0),
+                padding: UiRect::all(Val::Px(10.)),
+                border: UiRect::all(Val::Px(2.)),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            background_color: BG_COLOR.into(),
+            border_color: BG_BORDER_COLOR.into(),
+            border_radius: BorderRadius::all(Val::Px(15.)),
+            ..default()
+        },
+        Name("tile_info".to_string()),
+    ))
+    .with_children(|panel| {
+        for line in lines {
+            panel.spawn(TextBundle::from_section(line, text_style.clone()));
+        }
+        for (item, line) in stock { //each stocked item beside its icon
+            panel.spawn(NodeBundle {
+                style: Style {align_items: AlignItems::Center, ..default()},
+                ..default()
+            })
+            .with_children(|row| {
+                row.spawn(item_icons.image(item));
+                row.spawn(TextBundle::from_section(line, text_style.clone()));
+            });
+        }
+    });
+}
+
+fn reload_on_r( //Regenerate the map on 'r' press
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tilemap_q: Query<(Entity, &TileStorage)>,
+    building_q: Query<Entity, BuildingFilter>,
+    seed: u64,
+    config: &WorldGenConfig,
+) {
+    for building in building_q.iter() { //buildings and trains don't carry over to the new map
+        commands.entity(building).despawn_recursive();
+    }
+    let texture: Handle<Image> = asset_server.load(SPRITE_SHEET_PATH);
+    generate_new_map(commands, texture, asset_server.load(RAIL_SPRITE_SHEET_PATH), Some(tilemap_q), seed, config);
 }
 
 fn update_camera( //Allows for movement-key control for the camera
@@ -1610,15 +2520,28 @@ fn update_camera( //Allows for movement-key control for the camera
     }
 }
 
-fn grid_to_world(x:f32,y:f32) -> (f32,f32) { //Returns the new x,y coordinates as scaled based on sprite data
-    (x * TILE_WIDTH as f32 * SPRITE_SCALE_FACTOR as f32, y * TILE_HEIGHT as f32 * SPRITE_SCALE_FACTOR as f32)
-}
-
-fn clamp<'a, T: PartialOrd>(x: &'a T, min: &'a T, max: &'a T) -> &'a T { //Clamps a partially ordered value between a min and max value, inclusive.
-    if x >= max {
-        return max;
-    } else if x <= min {
-        return min;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn hired_loonkas_never_reuse_the_id_of_a_fallen_one() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut player_stats = PlayerStats::new();
+        let tile = Entity::from_raw(0);
+        for name in ["Toby", "Mark", "Reese"] {
+            player_stats.hire_loonka(name.to_string(), &mut rng);
+        }
+        let warrior = &mut player_stats.loonkas[1];
+        warrior.current_job = LoonkaJob::Warrior;
+        warrior.tile = Some(tile);
+        let fallen = wound_warriors(&mut player_stats.loonkas, tile, 1000);
+        assert_eq!(fallen.iter().map(|loonka| loonka.id).collect::<Vec<_>>(), vec![2]);
+
+        let hired = player_stats.hire_loonka("Quinn".to_string(), &mut rng);
+        let ids: Vec<usize> = player_stats.loonkas.iter().map(|loonka| loonka.id).collect();
+        assert_eq!(hired, 4);
+        assert_eq!(ids, vec![1, 3, 4], "every id is unique");
     }
-    x
-}
\ No newline at end of file
+}
@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+
+use crate::{GRID_COLS, GRID_ROWS, SPRITE_SCALE_FACTOR, TILE_HEIGHT, TILE_WIDTH};
+
+///Maps grid coordinates to tile entities so tiles and their neighbours can be looked up directly.
+#[derive(Resource, Clone, Debug)]
+pub struct MapGrid {
+    tiles: Vec<Entity>, //indexed by x * GRID_ROWS + y
+}
+
+impl Default for MapGrid {
+    fn default() -> Self {
+        MapGrid { tiles: vec![Entity::PLACEHOLDER; GRID_COLS * GRID_ROWS] }
+    }
+}
+
+impl MapGrid {
+    ///Stores the tile entity at the given grid coordinates.
+    pub fn set(&mut self, x: usize, y: usize, entity: Entity) {
+        if in_bounds(x as i64, y as i64) {
+            self.tiles[x * GRID_ROWS + y] = entity;
+        }
+    }
+
+    ///Returns the tile entity at the given grid coordinates, if there is one.
+    pub fn get(&self, x: usize, y: usize) -> Option<Entity> {
+        if !in_bounds(x as i64, y as i64) {return None;}
+        let entity = self.tiles[x * GRID_ROWS + y];
+        if entity == Entity::PLACEHOLDER {None} else {Some(entity)}
+    }
+
+    ///Returns the tile entity under the given world position, if there is one.
+    pub fn get_at_world(&self, x: f32, y: f32) -> Option<Entity> {
+        let (grid_x, grid_y) = world_to_grid(x, y)?;
+        self.get(grid_x, grid_y)
+    }
+
+    ///Returns the grid coordinates of the tile next to the given tile in a direction, if it is on the map.
+    pub fn step(x: usize, y: usize, direction: Direction) -> Option<(usize, usize)> {
+        let (dx, dy) = direction.offset();
+        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+        if in_bounds(nx, ny) {Some((nx as usize, ny as usize))} else {None}
+    }
+
+    ///Returns the grid coordinates of the (up to 4) tiles sharing an edge with the given tile.
+    pub fn neighbours(x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> {
+        [(0, 1), (1, 0), (0, -1), (-1, 0)].into_iter()
+            .map(move |(dx, dy)| (x as i64 + dx, y as i64 + dy))
+            .filter(|(nx, ny)| in_bounds(*nx, *ny))
+            .map(|(nx, ny)| (nx as usize, ny as usize))
+    }
+}
+
+///One of the 4 directions to a neighbouring tile. North is up the screen, towards higher y.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Direction { North, East, South, West }
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
+
+    pub fn offset(self) -> (i64, i64) {
+        match self {
+            Direction::North => (0, 1),
+            Direction::East => (1, 0),
+            Direction::South => (0, -1),
+            Direction::West => (-1, 0),
+        }
+    }
+
+    ///Returns the character drawn to point this way.
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Direction::North => "^",
+            Direction::East => ">",
+            Direction::South => "v",
+            Direction::West => "<",
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        self.rotate_clockwise().rotate_clockwise()
+    }
+
+    pub fn rotate_clockwise(self) -> Direction {
+        match self {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North,
+        }
+    }
+}
+
+///Returns true if the grid coordinates are inside the map.
+fn in_bounds(x: i64, y: i64) -> bool {
+    x >= 0 && y >= 0 && (x as usize) < GRID_COLS && (y as usize) < GRID_ROWS
+}
+
+///Returns the world coordinates of the center of a grid tile, as scaled based on sprite data.
+pub fn grid_to_world(x:f32,y:f32) -> (f32,f32) {
+    (x * TILE_WIDTH as f32 * SPRITE_SCALE_FACTOR as f32, y * TILE_HEIGHT as f32 * SPRITE_SCALE_FACTOR as f32)
+}
+
+///Returns the grid coordinates of the tile covering a world position, or None if it is off the map.
+pub fn world_to_grid(x:f32,y:f32) -> Option<(usize,usize)> {
+    let grid_x = (x / (TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32).round() as i64;
+    let grid_y = (y / (TILE_HEIGHT * SPRITE_SCALE_FACTOR) as f32).round() as i64;
+    if in_bounds(grid_x, grid_y) {Some((grid_x as usize, grid_y as usize))} else {None}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILE_SIZE: f32 = (TILE_WIDTH * SPRITE_SCALE_FACTOR) as f32;
+
+    #[test]
+    fn grid_and_world_coordinates_round_trip() {
+        for (x, y) in [(0, 0), (1, 2), (250, 17), (GRID_COLS - 1, GRID_ROWS - 1)] {
+            let (world_x, world_y) = grid_to_world(x as f32, y as f32);
+            assert_eq!(world_to_grid(world_x, world_y), Some((x, y)));
+            //anywhere on the tile, not just its center
+            assert_eq!(world_to_grid(world_x + TILE_SIZE * 0.4, world_y - TILE_SIZE * 0.4), Some((x, y)));
+        }
+    }
+
+    #[test]
+    fn world_positions_off_the_map_have_no_tile() {
+        assert_eq!(world_to_grid(-TILE_SIZE * 0.4, 0.), Some((0, 0)));
+        assert_eq!(world_to_grid(-TILE_SIZE, 0.), None);
+        assert_eq!(world_to_grid(0., -TILE_SIZE), None);
+        let (edge_x, edge_y) = grid_to_world(GRID_COLS as f32, GRID_ROWS as f32);
+        assert_eq!(world_to_grid(edge_x, 0.), None);
+        assert_eq!(world_to_grid(0., edge_y), None);
+    }
+
+    #[test]
+    fn steps_stay_on_the_map() {
+        assert_eq!(MapGrid::step(5, 5, Direction::North), Some((5, 6)));
+        assert_eq!(MapGrid::step(5, 5, Direction::East), Some((6, 5)));
+        assert_eq!(MapGrid::step(5, 5, Direction::South), Some((5, 4)));
+        assert_eq!(MapGrid::step(5, 5, Direction::West), Some((4, 5)));
+
+        assert_eq!(MapGrid::step(0, 5, Direction::West), None);
+        assert_eq!(MapGrid::step(5, 0, Direction::South), None);
+        assert_eq!(MapGrid::step(GRID_COLS - 1, 5, Direction::East), None);
+        assert_eq!(MapGrid::step(5, GRID_ROWS - 1, Direction::North), None);
+        assert_eq!(MapGrid::step(GRID_COLS, 5, Direction::North), None);
+    }
+
+    #[test]
+    fn neighbours_are_cut_off_at_the_edges() {
+        let mut middle: Vec<(usize, usize)> = MapGrid::neighbours(5, 5).collect();
+        middle.sort();
+        assert_eq!(middle, vec![(4, 5), (5, 4), (5, 6), (6, 5)]);
+
+        let mut corner: Vec<(usize, usize)> = MapGrid::neighbours(0, 0).collect();
+        corner.sort();
+        assert_eq!(corner, vec![(0, 1), (1, 0)]);
+
+        let mut far_corner: Vec<(usize, usize)> = MapGrid::neighbours(GRID_COLS - 1, GRID_ROWS - 1).collect();
+        far_corner.sort();
+        assert_eq!(far_corner, vec![(GRID_COLS - 2, GRID_ROWS - 1), (GRID_COLS - 1, GRID_ROWS - 2)]);
+
+        assert_eq!(MapGrid::neighbours(0, 5).count(), 3);
+        assert_eq!(MapGrid::neighbours(GRID_COLS + 1, 5).count(), 0);
+    }
+
+    #[test]
+    fn lookups_off_the_map_return_none() {
+        let mut grid = MapGrid::default();
+        let entity = Entity::from_raw(7);
+        grid.set(3, 4, entity);
+        grid.set(GRID_COLS, 4, entity); //ignored
+        assert_eq!(grid.get(3, 4), Some(entity));
+        assert_eq!(grid.get(4, 3), None);
+        assert_eq!(grid.get(GRID_COLS, 4), None);
+        assert_eq!(grid.get_at_world(3. * TILE_SIZE, 4. * TILE_SIZE), Some(entity));
+        assert_eq!(grid.get_at_world(-TILE_SIZE, 4. * TILE_SIZE), None);
+    }
+}
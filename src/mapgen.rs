@@ -0,0 +1,143 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use image::{Rgb, RgbImage};
+
+use crate::tile_data::*;
+use crate::world_gen::*;
+use crate::{launch_arg, load_world_gen_config, WorldSeed, GRID_COLS, GRID_ROWS};
+
+///Output folder used when no `--out <dir>` argument is given.
+const DEFAULT_OUT_DIR: &str = "mapgen";
+///Pixels per tile side in the exported png.
+const PNG_SCALE: u32 = 2;
+
+///Runs world generation without opening a window and writes the map to a png and a text grid.
+///Usage: `cocolo mapgen [--seed <number>] [--preset <name>] [--out <dir>]`
+pub fn run_cli() {
+    let seed = WorldSeed::from_args().0;
+    let preset = launch_arg("--preset").unwrap_or("default".to_string());
+    let out_dir = PathBuf::from(launch_arg("--out").unwrap_or(DEFAULT_OUT_DIR.to_string()));
+    let tiles = generate_tiles(seed, &load_world_gen_config());
+
+    let name = format!("map_{}_{}", preset, seed);
+    let png_path = out_dir.join(format!("{}.png", name));
+    let txt_path = out_dir.join(format!("{}.txt", name));
+    let result = std::fs::create_dir_all(&out_dir)
+        .map_err(|err| err.to_string())
+        .and_then(|_| map_image(&tiles).save(&png_path).map_err(|err| err.to_string()))
+        .and_then(|_| std::fs::write(&txt_path, map_text(&tiles, seed, &preset)).map_err(|err| err.to_string()));
+
+    match result {
+        Ok(_) => println!("Wrote {} and {}", png_path.display(), txt_path.display()),
+        Err(err) => {
+            println!("Couldn't export the map: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+///Draws the biome map with resource, outpost and enemy markers on top. North is up, like in game.
+fn map_image(tiles: &[GeneratedTile]) -> RgbImage {
+    let mut img = RgbImage::new(GRID_COLS as u32 * PNG_SCALE, GRID_ROWS as u32 * PNG_SCALE);
+    for tile in tiles {
+        let color = marker_color(tile).unwrap_or(biome_color(tile.base_type));
+        let (x, y) = (tile.grid_pos.0 as u32, (GRID_ROWS - 1 - tile.grid_pos.1) as u32);
+        for px in 0..PNG_SCALE {
+            for py in 0..PNG_SCALE {
+                img.put_pixel(x * PNG_SCALE + px, y * PNG_SCALE + py, color);
+            }
+        }
+    }
+    img
+}
+
+///Writes the biome grid and the overlay grid as text, one character per tile, with a legend.
+fn map_text(tiles: &[GeneratedTile], seed: u64, preset: &str) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "seed: {}\npreset: {}\n", seed, preset);
+    let _ = writeln!(text, "biomes: . plains, f forest, F dark forest, j jungle, M mountain, m dark mountain, s sand,");
+    let _ = writeln!(text, "        d dirt, D dark dirt, u mud, ~ water, = deep water, r river, o pond");
+    let _ = writeln!(text, "overlay: O outpost, T forest outpost, A mountain outpost, S ship outpost, P pirate ship, B beeple billage,");
+    let _ = writeln!(text, "         n nut, b bush, f fruit, m mine, s spice, . nothing\n");
+
+    let _ = writeln!(text, "[biomes]");
+    write_grid(&mut text, tiles, |tile| biome_char(tile.base_type));
+    let _ = writeln!(text, "\n[overlay]");
+    write_grid(&mut text, tiles, |tile| marker_char(tile).unwrap_or('.'));
+    text
+}
+
+///Writes one line per grid row, top row first.
+fn write_grid(text: &mut String, tiles: &[GeneratedTile], to_char: impl Fn(&GeneratedTile) -> char) {
+    for y in (0..GRID_ROWS).rev() {
+        let row: String = (0..GRID_COLS).map(|x| to_char(&tiles[x * GRID_ROWS + y])).collect();
+        let _ = writeln!(text, "{}", row);
+    }
+}
+
+fn biome_char(base: TileBaseType) -> char {
+    match base {
+        TileBaseType::Plains => '.',
+        TileBaseType::Forest => 'f',
+        TileBaseType::DarkForest => 'F',
+        TileBaseType::Jungle => 'j',
+        TileBaseType::Mountain => 'M',
+        TileBaseType::DarkMountain => 'm',
+        TileBaseType::Sand => 's',
+        TileBaseType::Dirt => 'd',
+        TileBaseType::DarkDirt => 'D',
+        TileBaseType::Mud => 'u',
+        TileBaseType::Water => '~',
+        TileBaseType::DeepWater => '=',
+        TileBaseType::River => 'r',
+        TileBaseType::Pond => 'o',
+    }
+}
+
+fn biome_color(base: TileBaseType) -> Rgb<u8> {
+    match base {
+        TileBaseType::Plains => Rgb([120, 190, 80]),
+        TileBaseType::Forest => Rgb([40, 130, 50]),
+        TileBaseType::DarkForest => Rgb([20, 80, 40]),
+        TileBaseType::Jungle => Rgb([60, 160, 100]),
+        TileBaseType::Mountain => Rgb([170, 170, 170]),
+        TileBaseType::DarkMountain => Rgb([110, 105, 110]),
+        TileBaseType::Sand => Rgb([230, 210, 140]),
+        TileBaseType::Dirt => Rgb([150, 110, 70]),
+        TileBaseType::DarkDirt => Rgb([100, 70, 45]),
+        TileBaseType::Mud => Rgb([110, 90, 60]),
+        TileBaseType::Water => Rgb([60, 120, 210]),
+        TileBaseType::DeepWater => Rgb([30, 60, 150]),
+        TileBaseType::River => Rgb([90, 170, 230]),
+        TileBaseType::Pond => Rgb([80, 150, 200]),
+    }
+}
+
+///Returns the overlay character of the outpost, enemy or harvestable on a tile, if it has one.
+fn marker_char(tile: &GeneratedTile) -> Option<char> {
+    match (tile.outpost.outpost_type, tile.enemy.enemy_type, tile.harvest.yields) {
+        (OutpostType::Outpost, _, _) => Some('O'),
+        (OutpostType::ForestOutpost, _, _) => Some('T'),
+        (OutpostType::MountainOutpost, _, _) => Some('A'),
+        (OutpostType::ShipOutpost, _, _) => Some('S'),
+        (_, EnemyTileType::PirateShip, _) => Some('P'),
+        (_, EnemyTileType::BeepleBillage, _) => Some('B'),
+        (_, _, ResourceItemType::Nut(_)) => Some('n'),
+        (_, _, ResourceItemType::Bush(_)) => Some('b'),
+        (_, _, ResourceItemType::Fruit(_)) => Some('f'),
+        (_, _, ResourceItemType::Mine(_)) => Some('m'),
+        (_, _, ResourceItemType::Spice(_)) => Some('s'),
+        _ => None,
+    }
+}
+
+///Outposts are white, enemies are red and harvestables are shades of yellow.
+fn marker_color(tile: &GeneratedTile) -> Option<Rgb<u8>> {
+    marker_char(tile).map(|marker| match marker {
+        'O' | 'T' | 'A' | 'S' => Rgb([255, 255, 255]),
+        'P' | 'B' => Rgb([220, 30, 30]),
+        'm' => Rgb([255, 160, 0]),
+        _ => Rgb([255, 230, 0]),
+    })
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use crate::processors::ProcessorType;
+use crate::recipes::{Ingredient, IngredientKind, RecipeBook};
+use crate::tile_data::*;
+
+///Deepest recipe tree expanded, in case a recipe ever uses its own product.
+const MAX_PLAN_DEPTH: usize = 16;
+///Items a harvester gathers per minute: one harvest per cooldown, of the average harvest amount.
+pub const HARVESTER_ITEMS_PER_MINUTE: f32 = 60. / HARVEST_COOLDOWN * 3.;
+
+///What it takes to make an item at a steady rate.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Plan {
+    pub raw: Vec<(IngredientKind, f32)>, //raw resources needed per minute
+    pub made: Vec<(ResourceItemType, f32)>, //every processed item made per minute, the target included
+    pub processors: HashMap<ProcessorType, u32>,
+    pub harvesters: u32,
+    pub factory_workers: u32, //one average worker per processor
+}
+
+///Expands the recipe tree of `target` to find the raw resources, processors and loonkas needed to make `per_minute` of it.
+///Processed ingredients use the first recipe that makes them. Wildcard ingredients stay raw if any raw item fits,
+///otherwise the first processed item that fits is made.
+pub fn plan(book: &RecipeBook, target: ResourceItemType, per_minute: f32) -> Plan {
+    let mut plan = Plan::default();
+    let mut busy: HashMap<ProcessorType, f32> = HashMap::new(); //processors' worth of work per type
+    expand(book, IngredientKind::Item(target), per_minute, 0, &mut plan, &mut busy);
+
+    plan.processors = busy.into_iter().map(|(processor, load)| (processor, load.ceil() as u32)).collect();
+    plan.factory_workers = plan.processors.values().sum();
+    plan.harvesters = plan.raw.iter().map(|(_, rate)| (rate / HARVESTER_ITEMS_PER_MINUTE).ceil() as u32).sum();
+    plan
+}
+
+fn expand(book: &RecipeBook, kind: IngredientKind, per_minute: f32, depth: usize, plan: &mut Plan, busy: &mut HashMap<ProcessorType, f32>) {
+    let item = match made_item(book, &kind) {
+        Some(item) if depth < MAX_PLAN_DEPTH => item,
+        _ => {
+            match plan.raw.iter_mut().find(|(raw, _)| *raw == kind) {
+                Some((_, rate)) => *rate += per_minute,
+                None => plan.raw.push((kind, per_minute)),
+            }
+            return;
+        },
+    };
+    let Some(recipe) = book.recipes_for(item).next() else {return;};
+    match plan.made.iter_mut().find(|(made, _)| *made == item) {
+        Some((_, rate)) => *rate += per_minute,
+        None => plan.made.push((item, per_minute)),
+    }
+
+    let batches = per_minute / recipe.amount as f32;
+    for processor in &recipe.processors {
+        *busy.entry(*processor).or_insert(0.) += batches * processor.duration() / 60.;
+    }
+    for Ingredient { kind, amount } in &recipe.ingredients {
+        expand(book, kind.clone(), batches * *amount as f32, depth + 1, plan, busy);
+    }
+}
+
+///The processed item an ingredient slot will be filled with, or None if it takes raw items.
+fn made_item(book: &RecipeBook, kind: &IngredientKind) -> Option<ResourceItemType> {
+    let fits: Vec<ResourceItemType> = match kind {
+        IngredientKind::Item(item) => vec![*item],
+        IngredientKind::AnyOf(items) => items.clone(),
+        IngredientKind::Tag(_) => ResourceItemType::all().into_iter()
+            .filter(|item| Ingredient { kind: kind.clone(), amount: 1 }.accepts(*item))
+            .collect(),
+    };
+    if fits.iter().any(|item| !matches!(item, ResourceItemType::Processed(_))) {return None;}
+    fits.into_iter().find(|item| book.recipes_for(*item).next().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::{ItemTag, ProcessedType};
+
+    const BOOK: &str = r#"[
+        (output: "Liquid sugar", ingredients: [("Sugar", 1)], processors: ["heater"]),
+        (output: "Caramel", ingredients: [("Liquid sugar", 1)], processors: ["heater"]),
+        (output: "Toffee", amount: 2, ingredients: [("Caramel", 1), ("any nut", 2)], processors: ["mixer", "press"]),
+    ]"#;
+    const TOFFEE: ResourceItemType = ResourceItemType::Processed(ProcessedType::Toffee);
+
+    #[test]
+    fn expands_the_whole_tree() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let plan = plan(&book, TOFFEE, 60.);
+        //60 toffee is 30 batches, needing 30 caramel, 30 liquid sugar, 30 sugar and 60 nuts
+        assert_eq!(plan.raw, vec![
+            (IngredientKind::Item(ResourceItemType::Mine(MineType::Sugar)), 30.),
+            (IngredientKind::Tag(ItemTag::Nut), 60.),
+        ]);
+        assert_eq!(plan.made, vec![
+            (TOFFEE, 60.),
+            (ResourceItemType::Processed(ProcessedType::Caramel), 30.),
+            (ResourceItemType::Processed(ProcessedType::LiquidSugar), 30.),
+        ]);
+        //60 heater batches a minute at 8 seconds each keep 8 heaters busy
+        assert_eq!(plan.processors[&ProcessorType::Heater], 8);
+        assert_eq!(plan.processors[&ProcessorType::Mixer], 3);
+        assert_eq!(plan.processors[&ProcessorType::Press], 2);
+        assert_eq!(plan.factory_workers, 13);
+        assert_eq!(plan.harvesters, (30. / HARVESTER_ITEMS_PER_MINUTE).ceil() as u32 + (60. / HARVESTER_ITEMS_PER_MINUTE).ceil() as u32);
+    }
+
+    #[test]
+    fn slow_rates_still_need_a_whole_processor() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let plan = plan(&book, ResourceItemType::Processed(ProcessedType::LiquidSugar), 0.5);
+        assert_eq!(plan.processors, HashMap::from([(ProcessorType::Heater, 1)]));
+        assert_eq!((plan.factory_workers, plan.harvesters), (1, 1));
+    }
+
+    #[test]
+    fn every_shipped_candy_can_be_planned() {
+        let (book, _) = RecipeBook::parse(include_str!("../assets/recipes.ron"));
+        let plan = plan(&book, ResourceItemType::Processed(ProcessedType::ChocolateBar), 10.);
+        assert!(plan.made.iter().any(|(item, _)| *item == ResourceItemType::Processed(ProcessedType::CocoaPowder)), "{:#?}", plan);
+        for candy in ProcessedType::ALL {
+            let plan = super::plan(&book, ResourceItemType::Processed(candy), 10.);
+            assert!(!plan.raw.is_empty() && plan.factory_workers > 0, "{:?}: {:#?}", candy, plan);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::items::Quality;
+use crate::map_grid::Direction;
+use crate::recipes::{Ingredient, IngredientKind, Recipe, RecipeBook};
+use crate::stockpile::Stockpile;
+use crate::tile_data::*;
+
+///Stores type of a processor building.
+#[derive(PartialEq, Hash, Clone, Copy, Debug)]
+pub enum ProcessorType { Mixer, Heater, Spinner, Press, Cooler }
+impl Eq for ProcessorType {} //partialEq is implemented
+
+impl ProcessorType {
+    pub const ALL: [ProcessorType; 5] = [ProcessorType::Mixer, ProcessorType::Heater, ProcessorType::Spinner, ProcessorType::Press, ProcessorType::Cooler];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProcessorType::Mixer => "mixer",
+            ProcessorType::Heater => "heater",
+            ProcessorType::Spinner => "spinner",
+            ProcessorType::Press => "press",
+            ProcessorType::Cooler => "cooler",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ProcessorType> {
+        ProcessorType::ALL.into_iter().find(|processor| processor.name().eq_ignore_ascii_case(name))
+    }
+
+    ///Gold it costs to build, refunded in full when removed.
+    pub fn cost(self) -> i32 {
+        match self {
+            ProcessorType::Mixer => 20,
+            ProcessorType::Heater => 15,
+            ProcessorType::Spinner => 25,
+            ProcessorType::Press => 30,
+            ProcessorType::Cooler => 20,
+        }
+    }
+
+    ///Seconds a batch takes with a single worker of average dexterity.
+    pub fn duration(self) -> f32 {
+        match self {
+            ProcessorType::Mixer => 6.,
+            ProcessorType::Heater => 8.,
+            ProcessorType::Spinner => 5.,
+            ProcessorType::Press => 4.,
+            ProcessorType::Cooler => 10.,
+        }
+    }
+
+    ///Nothing floats in deep water, only coolers work in still water, and rivers can also turn a spinner.
+    pub fn can_build_on(self, base: TileBaseType) -> bool {
+        match base {
+            TileBaseType::DeepWater => false,
+            TileBaseType::Water | TileBaseType::Pond => self == ProcessorType::Cooler,
+            TileBaseType::River => matches!(self, ProcessorType::Cooler | ProcessorType::Spinner),
+            _ => true,
+        }
+    }
+
+    ///Sprite shown on the map, the same as the build bar icon.
+    pub fn sprite_path(self) -> &'static str {
+        match self {
+            ProcessorType::Mixer => "ui/place_mixer.png",
+            ProcessorType::Heater => "ui/place_heater.png",
+            ProcessorType::Spinner => "ui/place_spinner.png",
+            ProcessorType::Press => "ui/place_press.png",
+            ProcessorType::Cooler => "ui/place_cooler.png",
+        }
+    }
+}
+
+///A processor building standing on a map tile.
+#[derive(Component, Clone, Debug)]
+pub struct Processor {
+    pub processor_type: ProcessorType,
+    pub tile: Entity,
+    pub output: Direction, //neighbouring tile that chained batches are passed to
+    pub batch: Option<Batch>,
+    pub status: ProcessorStatus,
+    pub line: Vec<ProcessorType>, //its type and the types of the processors downstream, as of its last tick
+}
+
+///Ingredients being processed into a recipe's output.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Batch {
+    pub recipe: usize, //index into the recipe book
+    pub step: usize, //index into the recipe's processors
+    pub progress: f32, //seconds of work done on this step
+    pub quality: Quality, //of the ingredients, then changed by the workers of each step
+    pub ingredients: Vec<(ResourceItemType, u32)>, //the items taken to start it
+}
+
+
+///The factory workers on a processor's tile.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Crew {
+    pub speed: f32, //1 is normal speed, 0 means nobody is working
+    pub skill: i32, //average dexterity, which improves or spoils the quality of each step
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ProcessorStatus { Working, Stalled(StallReason) }
+
+///Why a processor isn't working.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum StallReason { NoWorkers, MissingInputs, OutputFull, WaitingFor(ProcessorType) }
+
+impl StallReason {
+    pub fn describe(self) -> String {
+        match self {
+            StallReason::NoWorkers => "No workers".to_string(),
+            StallReason::MissingInputs => "Missing inputs".to_string(),
+            StallReason::OutputFull => "Output full".to_string(),
+            StallReason::WaitingFor(next) => format!("Waiting for {}", next.name()),
+        }
+    }
+}
+
+///Marks the text above a processor showing why it stalled.
+#[derive(Component)]
+pub struct ProcessorLabel;
+
+///Marks the arrow showing which way a processor passes chained batches.
+#[derive(Component)]
+pub struct ProcessorArrow;
+
+///Longest processor chain a recipe can have.
+pub const MAX_CHAIN_LENGTH: usize = 5;
+
+impl Processor {
+    pub fn new(processor_type: ProcessorType, tile: Entity) -> Processor {
+        Processor { processor_type, tile, output: Direction::East, batch: None, status: ProcessorStatus::Stalled(StallReason::NoWorkers), line: vec![processor_type] }
+    }
+
+    ///Runs the processor for `dt` seconds with the given crew, taking ingredients from and putting products in its tile's stock.
+    ///Only recipes in `known` are started. `line` is this processor's type followed by the types of the processors downstream of it, which chained recipes need.
+    ///Returns the item and amount made if a batch finished.
+    pub fn tick(&mut self, stock: &mut Stockpile, book: &RecipeBook, known: &HashSet<usize>, line: &[ProcessorType], crew: Crew, dt: f32) -> Option<(ResourceItemType, u32)> {
+        if crew.speed <= 0. {
+            self.status = ProcessorStatus::Stalled(StallReason::NoWorkers);
+            return None;
+        }
+        if self.batch.is_none() {
+            match self.next_recipe(stock, book, known, line) {
+                Some(batch) => self.batch = Some(batch),
+                None => {
+                    self.status = ProcessorStatus::Stalled(StallReason::MissingInputs);
+                    return None;
+                }
+            }
+        }
+        let batch = self.batch.as_mut()?;
+        let duration = self.processor_type.duration();
+        let was_done = batch.progress >= duration;
+        batch.progress += dt * crew.speed;
+        self.status = ProcessorStatus::Working;
+        if batch.progress < duration {return None;}
+        if !was_done { //the crew that finishes a step decides how well it went
+            batch.quality = batch.quality.worked(crew.skill);
+        }
+
+        let recipe = &book.recipes()[batch.recipe];
+        if let Some(next) = recipe.processors.get(batch.step + 1) { //the batch moves on down the line
+            self.status = ProcessorStatus::Stalled(StallReason::WaitingFor(*next));
+            return None;
+        }
+        if stock.space() < recipe.amount { //hold on to the finished batch until there's room
+            self.status = ProcessorStatus::Stalled(StallReason::OutputFull);
+            return None;
+        }
+        stock.add_with_quality(recipe.output, recipe.amount, batch.quality);
+        self.batch = None;
+        Some((recipe.output, recipe.amount))
+    }
+
+    ///Picks the first recipe this processor's line can make from the stock and takes its ingredients.
+    ///Recipes using more of the line are tried first, then recipes naming exact items before ones taking any item of a category.
+    fn next_recipe(&self, stock: &mut Stockpile, book: &RecipeBook, known: &HashSet<usize>, line: &[ProcessorType]) -> Option<Batch> {
+        self.candidate_recipes(book, known, line).into_iter()
+            .find_map(|recipe| take_ingredients(stock, &book.recipes()[recipe])
+                .map(|(quality, ingredients)| Batch { recipe, step: 0, progress: 0., quality, ingredients }))
+    }
+
+    ///The known recipes this processor's line can start, in the order they are tried.
+    pub fn candidate_recipes(&self, book: &RecipeBook, known: &HashSet<usize>, line: &[ProcessorType]) -> Vec<usize> {
+        let exact = |recipe: &Recipe| recipe.ingredients.iter().all(|ingredient| matches!(ingredient.kind, IngredientKind::Item(_)));
+        let mut candidates: Vec<(usize, &Recipe)> = book.recipes().iter().enumerate()
+            .filter(|(index, _)| known.contains(index))
+            .filter(|(_, recipe)| recipe.processors.first() == Some(&self.processor_type) && line.starts_with(&recipe.processors))
+            .collect();
+        candidates.sort_by_key(|(_, recipe)| (std::cmp::Reverse(recipe.processors.len()), !exact(recipe)));
+        candidates.into_iter().map(|(index, _)| index).collect()
+    }
+
+    ///Passes a batch finished here on to the next processor of its recipe, if that processor is free.
+    pub fn hand_off(&mut self, next: &mut Processor, book: &RecipeBook) -> bool {
+        let Some(batch) = &self.batch else {return false;};
+        let recipe = &book.recipes()[batch.recipe];
+        if batch.progress < self.processor_type.duration() || next.batch.is_some() {return false;}
+        if recipe.processors.get(batch.step + 1) != Some(&next.processor_type) {return false;}
+        next.batch = self.batch.take().map(|batch| Batch { step: batch.step + 1, progress: 0., ..batch });
+        true
+    }
+
+    ///Puts the ingredients of the batch in progress back in the stock, so nothing is lost when the processor is taken down.
+    ///Leaves the batch as it is and returns false if they wouldn't all fit.
+    pub fn scrap_batch(&mut self, stock: &mut Stockpile) -> bool {
+        let Some(batch) = &self.batch else {return true;};
+        if batch.ingredients.iter().map(|(_, count)| count).sum::<u32>() > stock.space() {return false;}
+        for (item, count) in &batch.ingredients {
+            stock.add_with_quality(*item, *count, batch.quality);
+        }
+        self.batch = None;
+        true
+    }
+}
+
+///Takes every ingredient of the recipe out of the stock, or nothing if any are missing.
+///Returns the average quality of what was taken, and how many of each item.
+pub fn take_ingredients(stock: &mut Stockpile, recipe: &Recipe) -> Option<(Quality, Vec<(ResourceItemType, u32)>)> {
+    let mut remaining = stock.clone();
+    let mut quality = (Quality::STANDARD, 0);
+    for ingredient in &recipe.ingredients {
+        take_ingredient(&mut remaining, ingredient, &mut quality)?;
+    }
+    let taken = stock.iter().map(|(item, count)| (item, count - remaining.count(item))).filter(|(_, taken)| *taken > 0).collect();
+    *stock = remaining;
+    Some((quality.0, taken))
+}
+
+///Takes the ingredient's amount from the stock, mixing items if it accepts more than one.
+///Blends the quality of the taken items into `quality`, a quality and the count it is the average of.
+fn take_ingredient(stock: &mut Stockpile, ingredient: &Ingredient, quality: &mut (Quality, u32)) -> Option<()> {
+    let mut needed = ingredient.amount;
+    let matching: Vec<ResourceItemType> = stock.iter().map(|(item, _)| item).filter(|item| ingredient.accepts(*item)).collect();
+    for item in matching {
+        let item_quality = stock.quality(item);
+        let taken = stock.remove(item, needed);
+        *quality = (quality.0.blend(quality.1, item_quality, taken), quality.1 + taken);
+        needed -= taken;
+        if needed == 0 {return Some(());}
+    }
+    None
+}
+
+///The factory workers among the loonkas on a tile. A worker of 50 dexterity works at normal speed and keeps quality as it is.
+pub fn factory_crew<'a>(workers: impl Iterator<Item = &'a Loonka>) -> Crew {
+    let dexterity: Vec<i32> = workers
+        .filter(|loonka| loonka.current_job == LoonkaJob::FactoryWorker)
+        .map(|loonka| loonka.dexterity)
+        .collect();
+    if dexterity.is_empty() {return Crew::default();}
+    Crew {
+        speed: dexterity.iter().map(|dexterity| *dexterity as f32 / 50.).sum(),
+        skill: dexterity.iter().sum::<i32>() / dexterity.len() as i32,
+    }
+}
+
+///Reasons a processor can't be built on a tile.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BuildError {
+    Occupied, //there's already a processor, an outpost or an enemy on the tile
+    BadTerrain,
+    NotEnoughGold,
+}
+
+///Checks that the processor can be built on the tile with the given gold.
+pub fn check_build(processor_type: ProcessorType, tile: &Tile, gold: i32) -> Result<(), BuildError> {
+    if tile.processor.is_some() || tile.outpost.outpost_type != OutpostType::None || tile.enemy.enemy_type != EnemyTileType::None {
+        return Err(BuildError::Occupied);
+    }
+    if !processor_type.can_build_on(tile.base_type) {
+        return Err(BuildError::BadTerrain);
+    }
+    if gold < processor_type.cost() {
+        return Err(BuildError::NotEnoughGold);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::ProcessedType;
+
+    const BOOK: &str = r#"[
+        (output: "Liquid sugar", ingredients: [("Sugar", 2)], processors: ["heater"]),
+        (output: "Roasted nuts", ingredients: [("any nut", 1)], processors: ["heater"]),
+        (output: "Roasted almonds", ingredients: [("Almond", 1)], processors: ["heater"]),
+        (output: "Toffee", ingredients: [("Sugar", 1)], processors: ["heater", "mixer"]),
+    ]"#;
+    const SUGAR: ResourceItemType = ResourceItemType::Mine(MineType::Sugar);
+    const LIQUID_SUGAR: ResourceItemType = ResourceItemType::Processed(ProcessedType::LiquidSugar);
+
+    fn heater() -> Processor {
+        Processor::new(ProcessorType::Heater, Entity::PLACEHOLDER)
+    }
+
+    ///Workers of normal skill working at the given speed.
+    fn crew(speed: f32) -> Crew {
+        Crew { speed, skill: 50 }
+    }
+
+    ///Every recipe in the book, as if all were researched.
+    fn all(book: &RecipeBook) -> HashSet<usize> {
+        (0..book.recipes().len()).collect()
+    }
+
+    #[test]
+    fn heaters_stay_on_dry_land() {
+        assert!(ProcessorType::Heater.can_build_on(TileBaseType::Plains));
+        for base in [TileBaseType::Water, TileBaseType::DeepWater, TileBaseType::River, TileBaseType::Pond] {
+            assert!(!ProcessorType::Heater.can_build_on(base), "heater on {:?}", base);
+        }
+        assert!(ProcessorType::Cooler.can_build_on(TileBaseType::Pond));
+        assert!(ProcessorType::Spinner.can_build_on(TileBaseType::River));
+        assert!(!ProcessorType::Cooler.can_build_on(TileBaseType::DeepWater));
+    }
+
+    #[test]
+    fn processes_inputs_over_its_duration() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let mut stock = Stockpile::new(10);
+        stock.add(SUGAR, 3);
+        let mut processor = heater();
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), 1.), None);
+        assert_eq!(stock.count(SUGAR), 1, "ingredients are taken when the batch starts");
+        assert_eq!(processor.status, ProcessorStatus::Working);
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(2.), ProcessorType::Heater.duration() / 2.), Some((LIQUID_SUGAR, 1)));
+        assert_eq!(stock.count(LIQUID_SUGAR), 1);
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), 1.), None);
+        assert_eq!(processor.status, ProcessorStatus::Stalled(StallReason::MissingInputs), "one sugar isn't a batch");
+    }
+
+    #[test]
+    fn stalls_without_workers_or_output_space() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let mut stock = Stockpile::new(2);
+        stock.add(SUGAR, 2);
+        let mut processor = heater();
+        processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(0.), 1.);
+        assert_eq!(processor.status, ProcessorStatus::Stalled(StallReason::NoWorkers));
+        processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), 0.);
+        stock.add(ResourceItemType::Water, 2);
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), ProcessorType::Heater.duration()), None);
+        assert_eq!(processor.status, ProcessorStatus::Stalled(StallReason::OutputFull));
+        stock.remove(ResourceItemType::Water, 1);
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), 0.), Some((LIQUID_SUGAR, 1)));
+    }
+
+    #[test]
+    fn prefers_exact_recipes_and_skips_chains() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let mut stock = Stockpile::new(10);
+        stock.add(ResourceItemType::Nut(NutType::Almond), 1);
+        stock.add(SUGAR, 1);
+        let mut processor = heater();
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), ProcessorType::Heater.duration()),
+            Some((ResourceItemType::Processed(ProcessedType::RoastedAlmonds), 1)));
+        assert_eq!(processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), 1.), None);
+        assert_eq!(processor.status, ProcessorStatus::Stalled(StallReason::MissingInputs), "toffee needs a mixer after the heater");
+    }
+
+    #[test]
+    fn chained_batches_move_down_the_line() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let line = [ProcessorType::Heater, ProcessorType::Mixer];
+        let mut heater_stock = Stockpile::new(10);
+        heater_stock.add(SUGAR, 2);
+        let mut mixer_stock = Stockpile::new(10);
+        let mut heater = heater();
+        let mut mixer = Processor::new(ProcessorType::Mixer, Entity::PLACEHOLDER);
+
+        assert_eq!(heater.tick(&mut heater_stock, &book, &all(&book), &line, crew(1.), ProcessorType::Heater.duration()), None);
+        assert_eq!(heater_stock.count(SUGAR), 1, "the longer toffee chain wins over liquid sugar");
+        assert_eq!(heater.status, ProcessorStatus::Stalled(StallReason::WaitingFor(ProcessorType::Mixer)));
+        assert!(heater.hand_off(&mut mixer, &book));
+        assert_eq!(heater.batch, None);
+        assert_eq!(mixer.tick(&mut mixer_stock, &book, &all(&book), &[ProcessorType::Mixer], crew(1.), ProcessorType::Mixer.duration()),
+            Some((ResourceItemType::Processed(ProcessedType::Toffee), 1)));
+        assert_eq!(mixer_stock.count(ResourceItemType::Processed(ProcessedType::Toffee)), 1, "the product lands on the last tile");
+    }
+
+    #[test]
+    fn quality_carries_through_and_workers_change_it() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let line = [ProcessorType::Heater, ProcessorType::Mixer];
+        let mut heater_stock = Stockpile::new(10);
+        heater_stock.add_with_quality(SUGAR, 1, Quality::new(70));
+        let mut mixer_stock = Stockpile::new(10);
+        let mut heater = heater();
+        let mut mixer = Processor::new(ProcessorType::Mixer, Entity::PLACEHOLDER);
+
+        heater.tick(&mut heater_stock, &book, &all(&book), &line, Crew { speed: 2., skill: 100 }, ProcessorType::Heater.duration());
+        heater.tick(&mut heater_stock, &book, &all(&book), &line, Crew { speed: 2., skill: 100 }, 1.);
+        assert_eq!(heater.batch.as_ref().unwrap().quality, Quality::new(80), "a skilled crew improves a step once");
+        heater.hand_off(&mut mixer, &book);
+        mixer.tick(&mut mixer_stock, &book, &all(&book), &[ProcessorType::Mixer], Crew { speed: 1., skill: 25 }, ProcessorType::Mixer.duration());
+        assert_eq!(mixer_stock.quality(ResourceItemType::Processed(ProcessedType::Toffee)), Quality::new(75));
+    }
+
+    #[test]
+    fn scrapping_a_batch_gives_its_ingredients_back() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let mut stock = Stockpile::new(3);
+        stock.add(SUGAR, 2);
+        let mut processor = heater();
+        processor.tick(&mut stock, &book, &all(&book), &[ProcessorType::Heater], crew(1.), 1.);
+        assert_eq!(stock.count(SUGAR), 0);
+        stock.add(ResourceItemType::Water, 2);
+        assert!(!processor.scrap_batch(&mut stock), "two sugar don't fit in one space");
+        assert!(processor.batch.is_some());
+        stock.remove(ResourceItemType::Water, 1);
+        assert!(processor.scrap_batch(&mut stock));
+        assert_eq!((processor.batch, stock.count(SUGAR)), (None, 2));
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::{Color, Component, Resource};
+
+use crate::map_grid::{Direction, MapGrid};
+use crate::processors::BuildError;
+use crate::tile_data::*;
+
+///Rail sprite sheet path, one 8px piece for each combination of connections.
+pub const RAIL_SPRITE_SHEET_PATH: &str = "rails.png";
+///Gold a piece of track costs to lay, refunded when it is lifted.
+pub const TRACK_COST: i32 = 2;
+///Tint of track pieces with a signal on them.
+pub const SIGNAL_COLOR: Color = Color::srgb(1., 0.35, 0.3);
+
+///Marks the tilemap layer track pieces are drawn on.
+#[derive(Component)]
+pub struct RailLayer;
+
+///Shape of a track piece, from which of its neighbours it joins.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum TrackPiece { Single, End, Straight, Curve, Junction, Crossing }
+
+///Every piece of track on the map. A new piece joins all the track beside it, unless it is laid joined only to the piece
+///before it, so lines can run side by side.
+///Signals on the track split it into blocks, and only one train may be in a block at a time.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct RailGraph {
+    tracks: HashMap<(usize, usize), Vec<Direction>>, //grid coordinates, and the directions each piece joins
+    signals: HashSet<(usize, usize)>, //track pieces with a signal on them
+}
+
+impl RailGraph {
+    pub fn has_track(&self, pos: (usize, usize)) -> bool {
+        self.tracks.contains_key(&pos)
+    }
+
+    ///Lays a piece of track on a tile, joined to nothing yet. Returns false if there already was some.
+    pub fn place(&mut self, pos: (usize, usize)) -> bool {
+        if self.has_track(pos) {return false;}
+        self.tracks.insert(pos, vec![]);
+        true
+    }
+
+    ///Lays a piece of track on a tile, joined to every piece beside it into a straight, curve, junction or crossing.
+    ///Returns false if there already was some.
+    pub fn place_joined(&mut self, pos: (usize, usize)) -> bool {
+        if !self.place(pos) {return false;}
+        for next in MapGrid::neighbours(pos.0, pos.1) {
+            self.connect(pos, next);
+        }
+        true
+    }
+
+    ///Joins two neighbouring track pieces. Returns false if they aren't neighbours, one has no track, or they were already joined.
+    pub fn connect(&mut self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let Some(direction) = Direction::ALL.into_iter().find(|direction| MapGrid::step(from.0, from.1, *direction) == Some(to)) else {return false;};
+        if !self.has_track(to) {return false;}
+        let Some(joins) = self.tracks.get_mut(&from) else {return false;};
+        if joins.contains(&direction) {return false;}
+        joins.push(direction);
+        self.tracks.get_mut(&to).unwrap().push(direction.opposite());
+        true
+    }
+
+    ///Lays a run of track through each position in turn, each piece joined only to the one before, the way dragging the
+    ///cursor along it with the side-by-side key held does.
+    #[cfg(test)]
+    pub fn lay(&mut self, path: &[(usize, usize)]) {
+        for (index, pos) in path.iter().enumerate() {
+            self.place(*pos);
+            if index > 0 {self.connect(path[index - 1], *pos);}
+        }
+    }
+
+    ///Lifts the track on a tile, and its signal, cutting it off from its neighbours. Returns false if there was none.
+    pub fn remove(&mut self, pos: (usize, usize)) -> bool {
+        self.signals.remove(&pos);
+        let Some(joins) = self.tracks.remove(&pos) else {return false;};
+        for direction in joins {
+            let Some(next) = MapGrid::step(pos.0, pos.1, direction) else {continue;};
+            if let Some(next_joins) = self.tracks.get_mut(&next) {
+                next_joins.retain(|join| *join != direction.opposite());
+            }
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.signals.clear();
+    }
+
+    pub fn has_signal(&self, pos: (usize, usize)) -> bool {
+        self.signals.contains(&pos)
+    }
+
+    ///Puts a signal on a track piece, or takes it off if there is one. Returns whether there is a signal now.
+    pub fn toggle_signal(&mut self, pos: (usize, usize)) -> bool {
+        if !self.has_track(pos) {return false;}
+        if self.signals.remove(&pos) {return false;}
+        self.signals.insert(pos)
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.tracks.keys().copied()
+    }
+
+    ///Returns the directions a track piece leads in.
+    pub fn connections(&self, pos: (usize, usize)) -> Vec<Direction> {
+        let Some(joins) = self.tracks.get(&pos) else {return vec![];};
+        Direction::ALL.into_iter().filter(|direction| joins.contains(direction)).collect()
+    }
+
+    ///Returns the track pieces joined to a track piece.
+    pub fn neighbours(&self, pos: (usize, usize)) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.connections(pos).into_iter().filter_map(move |direction| MapGrid::step(pos.0, pos.1, direction))
+    }
+
+    ///Index of the sprite for a track piece: a bit for each connection, north 1, east 2, south 4 and west 8.
+    pub fn sprite_index(&self, pos: (usize, usize)) -> u32 {
+        self.connections(pos).into_iter()
+            .map(|direction| match direction {
+                Direction::North => 1,
+                Direction::East => 2,
+                Direction::South => 4,
+                Direction::West => 8,
+            })
+            .sum()
+    }
+
+    pub fn piece(&self, pos: (usize, usize)) -> TrackPiece {
+        let connections = self.connections(pos);
+        match connections.len() {
+            0 => TrackPiece::Single,
+            1 => TrackPiece::End,
+            2 if connections[0] == connections[1].opposite() => TrackPiece::Straight,
+            2 => TrackPiece::Curve,
+            3 => TrackPiece::Junction,
+            _ => TrackPiece::Crossing,
+        }
+    }
+
+    ///Returns true if a train could get from one track piece to the other.
+    #[cfg(test)]
+    pub fn is_connected(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        self.find_path(from, to).is_some()
+    }
+
+    ///Finds the shortest run of track from one piece to another, not counting the piece it starts on.
+    pub fn find_path(&self, from: (usize, usize), to: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        self.find_path_avoiding(from, to, |_| false)
+    }
+
+    ///Finds the shortest run of track from one piece to another that doesn't pass through any piece `avoid` returns true for.
+    pub fn find_path_avoiding(&self, from: (usize, usize), to: (usize, usize), avoid: impl Fn((usize, usize)) -> bool) -> Option<Vec<(usize, usize)>> {
+        if !self.has_track(from) || !self.has_track(to) || (from != to && avoid(to)) {return None;}
+        let mut came_from = HashMap::from([(from, from)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(pos) = queue.pop_front() {
+            if pos == to {
+                let mut path = vec![];
+                let mut step = to;
+                while step != from {
+                    path.push(step);
+                    step = came_from[&step];
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in self.neighbours(pos).filter(|next| !avoid(*next)) {
+                if let std::collections::hash_map::Entry::Vacant(entry) = came_from.entry(next) {
+                    entry.insert(pos);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    ///Splits the track into signal blocks. Each signal is a block of its own, and so is each stretch of track between signals.
+    pub fn blocks(&self) -> Blocks {
+        let mut block_of = HashMap::new();
+        let mut next_block = 0;
+        for start in self.tracks() {
+            if block_of.contains_key(&start) {continue;}
+            block_of.insert(start, next_block);
+            if !self.has_signal(start) {
+                let mut queue = VecDeque::from([start]);
+                while let Some(pos) = queue.pop_front() {
+                    for next in self.neighbours(pos).filter(|next| !self.has_signal(*next)) {
+                        if let std::collections::hash_map::Entry::Vacant(entry) = block_of.entry(next) {
+                            entry.insert(next_block);
+                            queue.push_back(next);
+                        }
+                    }
+                }
+            }
+            next_block += 1;
+        }
+        Blocks { block_of }
+    }
+}
+
+///Which signal block each track piece is in. Worked out from a `RailGraph`, and out of date once its track or signals change.
+#[derive(Clone, Debug, Default)]
+pub struct Blocks {
+    block_of: HashMap<(usize, usize), usize>,
+}
+
+impl Blocks {
+    pub fn block(&self, pos: (usize, usize)) -> Option<usize> {
+        self.block_of.get(&pos).copied()
+    }
+}
+
+///Checks that track can be laid on the tile with the given gold.
+pub fn check_track(tile: &Tile, gold: i32) -> Result<(), BuildError> {
+    if tile.processor.is_some() || tile.enemy.enemy_type != EnemyTileType::None {
+        return Err(BuildError::Occupied);
+    }
+    if tile.base_type.is_water() {
+        return Err(BuildError::BadTerrain);
+    }
+    if gold < TRACK_COST {
+        return Err(BuildError::NotEnoughGold);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(path: &[(usize, usize)]) -> RailGraph {
+        let mut graph = RailGraph::default();
+        graph.lay(path);
+        graph
+    }
+
+    #[test]
+    fn pieces_follow_their_connections() {
+        let mut graph = graph(&[(5, 5)]);
+        assert_eq!(graph.piece((5, 5)), TrackPiece::Single);
+        graph.lay(&[(5, 5), (5, 6)]);
+        assert_eq!(graph.piece((5, 5)), TrackPiece::End);
+        graph.lay(&[(5, 4), (5, 5)]);
+        assert_eq!(graph.piece((5, 5)), TrackPiece::Straight);
+        assert_eq!(graph.sprite_index((5, 5)), 1 + 4);
+        graph.lay(&[(5, 5), (6, 5)]);
+        assert_eq!(graph.piece((5, 5)), TrackPiece::Junction);
+        graph.lay(&[(4, 5), (5, 5)]);
+        assert_eq!(graph.piece((5, 5)), TrackPiece::Crossing);
+        assert_eq!(graph.sprite_index((5, 5)), 15);
+        graph.remove((5, 4));
+        graph.remove((4, 5));
+        assert_eq!(graph.piece((5, 5)), TrackPiece::Curve);
+        assert_eq!(graph.sprite_index((5, 5)), 1 + 2);
+    }
+
+    #[test]
+    fn placed_track_joins_the_track_beside_it() {
+        let mut graph = RailGraph::default();
+        for pos in [(0, 0), (1, 0), (2, 0)] {
+            assert!(graph.place_joined(pos));
+        }
+        assert_eq!(graph.piece((1, 0)), TrackPiece::Straight);
+        graph.place_joined((2, 1));
+        assert_eq!(graph.piece((2, 0)), TrackPiece::Curve);
+        graph.place_joined((1, 1));
+        assert_eq!(graph.piece((1, 0)), TrackPiece::Junction);
+        assert_eq!(graph.piece((1, 1)), TrackPiece::Curve);
+        graph.place_joined((0, 1));
+        graph.place_joined((1, 2));
+        assert_eq!(graph.piece((1, 1)), TrackPiece::Crossing);
+        assert!(!graph.place_joined((1, 1)), "already track there");
+    }
+
+    #[test]
+    fn track_laid_side_by_side_stays_apart() {
+        let mut graph = graph(&[(0, 0), (1, 0), (2, 0)]);
+        graph.lay(&[(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(graph.piece((1, 0)), TrackPiece::Straight);
+        assert!(!graph.is_connected((0, 0), (0, 1)));
+        assert!(graph.connect((1, 0), (1, 1)));
+        assert_eq!(graph.piece((1, 1)), TrackPiece::Junction);
+        assert!(graph.is_connected((0, 0), (0, 1)));
+        assert!(!graph.connect((1, 1), (1, 0)), "already joined");
+        assert!(!graph.connect((0, 0), (2, 0)), "not neighbours");
+        assert!(!graph.connect((2, 0), (3, 0)), "no track to join");
+    }
+
+    #[test]
+    fn lifting_track_splits_the_line() {
+        let mut graph = graph(&[(0, 0), (1, 0), (2, 0), (2, 1)]);
+        assert!(graph.is_connected((0, 0), (2, 1)));
+        graph.remove((1, 0));
+        assert!(!graph.is_connected((0, 0), (2, 1)));
+        assert_eq!(graph.piece((2, 0)), TrackPiece::End);
+    }
+
+    #[test]
+    fn paths_take_the_shortest_way_round() {
+        //a loop with a short and a long side
+        let graph = graph(&[(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (2, 3), (1, 3), (0, 3), (0, 2), (0, 1), (0, 0)]);
+        assert_eq!(graph.find_path((0, 0), (2, 1)), Some(vec![(1, 0), (2, 0), (2, 1)]));
+        assert_eq!(graph.find_path((1, 0), (1, 0)), Some(vec![]));
+        assert_eq!(graph.find_path((1, 0), (5, 5)), None);
+        assert_eq!(graph.find_path_avoiding((0, 0), (2, 1), |pos| pos == (1, 0)).map(|path| path.len()), Some(7), "the long way round");
+    }
+
+    #[test]
+    fn signals_split_the_track_into_blocks() {
+        let mut graph = graph(&[(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)]);
+        let blocks = graph.blocks();
+        assert_eq!(blocks.block((0, 0)), blocks.block((4, 0)));
+        assert!(graph.toggle_signal((2, 0)));
+        let blocks = graph.blocks();
+        assert_eq!(blocks.block((0, 0)), blocks.block((1, 0)));
+        assert_ne!(blocks.block((1, 0)), blocks.block((2, 0)));
+        assert_ne!(blocks.block((2, 0)), blocks.block((3, 0)));
+        assert_ne!(blocks.block((0, 0)), blocks.block((4, 0)));
+        assert!(!graph.toggle_signal((2, 0)));
+        assert!(!graph.toggle_signal((9, 9)), "signals need track");
+        graph.toggle_signal((2, 0));
+        graph.remove((2, 0));
+        assert!(!graph.has_signal((2, 0)), "lifting track takes its signal");
+    }
+}
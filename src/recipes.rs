@@ -0,0 +1,183 @@
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::Resource;
+use serde::Deserialize;
+
+use crate::items::ItemTag;
+use crate::processors::ProcessorType;
+use crate::tile_data::ResourceItemType;
+
+///Recipe database file, relative to the assets folder.
+const RECIPES_PATH: &str = "recipes.ron";
+
+///What a recipe ingredient slot accepts.
+#[derive(PartialEq, Clone, Debug)]
+pub enum IngredientKind {
+    Item(ResourceItemType),
+    Tag(ItemTag), //"any nut"
+    AnyOf(Vec<ResourceItemType>), //"sugar or liquid sugar"
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Ingredient {
+    pub kind: IngredientKind,
+    pub amount: u32,
+}
+
+impl Ingredient {
+    ///Returns true if the item can fill this ingredient slot.
+    pub fn accepts(&self, item: ResourceItemType) -> bool {
+        match &self.kind {
+            IngredientKind::Item(wanted) => *wanted == item,
+            IngredientKind::Tag(tag) => item.has_tag(*tag),
+            IngredientKind::AnyOf(options) => options.contains(&item),
+        }
+    }
+}
+
+///A way to make an item: ingredients that go in, and the processors they pass through in order.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Recipe {
+    pub output: ResourceItemType,
+    pub amount: u32, //items made per batch
+    pub ingredients: Vec<Ingredient>,
+    pub processors: Vec<ProcessorType>,
+}
+
+///A recipe as written in the recipe file, before its names are checked.
+#[derive(Deserialize)]
+struct RecipeData {
+    output: String,
+    #[serde(default = "one")]
+    amount: u32,
+    ingredients: Vec<(String, u32)>,
+    processors: Vec<String>,
+}
+
+fn one() -> u32 {1}
+
+///Every recipe in the game. The only place crafting looks up what can be made and how.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct RecipeBook {
+    recipes: Vec<Recipe>,
+}
+
+impl RecipeBook {
+    ///Loads the recipe file, printing any recipes that fail validation and leaving them out.
+    pub fn load() -> RecipeBook {
+        let path = FileAssetReader::get_base_path().join("assets").join(RECIPES_PATH);
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let (book, errors) = RecipeBook::parse(&text);
+                for err in errors {
+                    println!("{}: {}", path.display(), err);
+                }
+                book
+            },
+            Err(err) => {
+                println!("Couldn't load {}: {}. No recipes are available.", path.display(), err);
+                RecipeBook::default()
+            }
+        }
+    }
+
+    ///Parses and validates recipe file text. Returns the valid recipes and an error for each invalid one.
+    pub fn parse(text: &str) -> (RecipeBook, Vec<String>) {
+        let data = match ron::from_str::<Vec<RecipeData>>(text) {
+            Ok(data) => data,
+            Err(err) => return (RecipeBook::default(), vec![err.to_string()]),
+        };
+        let mut recipes = vec![];
+        let mut errors = vec![];
+        for recipe in data {
+            match Recipe::from_data(&recipe) {
+                Ok(recipe) => recipes.push(recipe),
+                Err(err) => errors.push(format!("recipe for '{}': {}", recipe.output, err)),
+            }
+        }
+        (RecipeBook { recipes }, errors)
+    }
+
+    pub fn recipes(&self) -> &[Recipe] {
+        &self.recipes
+    }
+
+    ///Returns every recipe that makes the item.
+    pub fn recipes_for(&self, item: ResourceItemType) -> impl Iterator<Item = &Recipe> {
+        self.recipes.iter().filter(move |recipe| recipe.output == item)
+    }
+}
+
+impl Recipe {
+    fn from_data(data: &RecipeData) -> Result<Recipe, String> {
+        let output = item_by_name(&data.output)?;
+        if data.amount == 0 {return Err("makes nothing".to_string());}
+        if data.ingredients.is_empty() {return Err("has no ingredients".to_string());}
+        if data.processors.is_empty() {return Err("has no processors".to_string());}
+
+        let mut ingredients = vec![];
+        for (name, amount) in &data.ingredients {
+            if *amount == 0 {return Err(format!("needs 0 of '{}'", name));}
+            ingredients.push(Ingredient { kind: ingredient_kind(name)?, amount: *amount });
+        }
+        let mut processors = vec![];
+        for name in &data.processors {
+            processors.push(ProcessorType::from_name(name).ok_or(format!("unknown processor '{}'", name))?);
+        }
+        Ok(Recipe { output, amount: data.amount, ingredients, processors })
+    }
+}
+
+fn item_by_name(name: &str) -> Result<ResourceItemType, String> {
+    ResourceItemType::from_name(name.trim()).ok_or(format!("unknown item '{}'", name.trim()))
+}
+
+///Reads an ingredient name: "any <category>", "<item> or <item>", or a single item.
+fn ingredient_kind(name: &str) -> Result<IngredientKind, String> {
+    if let Some(tag) = name.strip_prefix("any ") {
+        return ItemTag::from_name(tag).map(IngredientKind::Tag).ok_or(format!("unknown category '{}'", tag));
+    }
+    if name.contains(" or ") {
+        return name.split(" or ").map(item_by_name).collect::<Result<Vec<_>, _>>().map(IngredientKind::AnyOf);
+    }
+    item_by_name(name).map(IngredientKind::Item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::ProcessedType;
+
+    #[test]
+    fn shipped_recipes_are_valid() {
+        let (book, errors) = RecipeBook::parse(include_str!("../assets/recipes.ron"));
+        assert!(errors.is_empty(), "{:#?}", errors);
+        for processed in ProcessedType::ALL {
+            let item = ResourceItemType::Processed(processed);
+            assert!(book.recipes_for(item).next().is_some(), "nothing makes {}", item.info().name);
+        }
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        let text = r#"[
+            (output: "Caramel", ingredients: [("Liquid sugar", 1)], processors: ["oven"]),
+            (output: "Caramel", ingredients: [("Liquid suger", 1)], processors: ["heater"]),
+            (output: "Caramel", ingredients: [("any veggie", 1)], processors: ["heater"]),
+            (output: "Fudgecake", ingredients: [("Sugar", 1)], processors: ["heater"]),
+            (output: "Caramel", ingredients: [("Sugar or Liquid sugar", 1)], processors: ["heater"]),
+        ]"#;
+        let (book, errors) = RecipeBook::parse(text);
+        assert_eq!(errors.len(), 4, "{:#?}", errors);
+        assert_eq!(book.recipes().len(), 1);
+    }
+
+    #[test]
+    fn wildcards_accept_their_items() {
+        let any_nut = Ingredient { kind: ingredient_kind("any nut").unwrap(), amount: 1 };
+        assert!(any_nut.accepts(ResourceItemType::Nut(crate::tile_data::NutType::Almond)));
+        assert!(!any_nut.accepts(ResourceItemType::Water));
+        let sugar = Ingredient { kind: ingredient_kind("Sugar or Liquid sugar").unwrap(), amount: 1 };
+        assert!(sugar.accepts(ResourceItemType::Processed(ProcessedType::LiquidSugar)));
+        assert!(!sugar.accepts(ResourceItemType::Processed(ProcessedType::Caramel)));
+    }
+}
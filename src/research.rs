@@ -0,0 +1,132 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::Resource;
+
+use crate::items::ItemTag;
+use crate::recipes::{Recipe, RecipeBook};
+use crate::tile_data::*;
+
+///Gold moved in or out of the research budget per key press.
+pub const BUDGET_STEP: i32 = 10;
+///Knowledge a researcher with 100 intellect makes per second.
+const KNOWLEDGE_RATE: f32 = 1.;
+///Knowledge needed to discover a recipe, per gold its product is worth.
+const KNOWLEDGE_PER_VALUE: i32 = 5;
+
+///What the player has discovered, and the gold set aside to discover more.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Research {
+    pub budget: i32, //gold researchers can spend, one per knowledge
+    progress: f32, //knowledge made but not yet a whole point
+    discovered: HashSet<usize>, //indices into the recipe book
+    produced: HashMap<ResourceItemType, u32>, //how many of each item processors have made
+}
+
+impl Research {
+    ///Starts out knowing every intermediate that is made straight from raw items.
+    pub fn new(book: &RecipeBook) -> Research {
+        let discovered = book.recipes().iter().enumerate()
+            .filter(|(_, recipe)| recipe.output.has_tag(ItemTag::Intermediate) && recipe_is_raw(recipe))
+            .map(|(index, _)| index)
+            .collect();
+        Research { discovered, ..Research::default() }
+    }
+
+    ///Recipes processors are allowed to make.
+    pub fn discovered(&self) -> &HashSet<usize> {
+        &self.discovered
+    }
+
+    ///Remembers that items have been made, which lets recipes using them be researched.
+    pub fn record_produced(&mut self, item: ResourceItemType, amount: u32) {
+        *self.produced.entry(item).or_insert(0) += amount;
+    }
+
+    ///Returns how many of the item processors have made.
+    pub fn produced(&self, item: ResourceItemType) -> u32 {
+        self.produced.get(&item).copied().unwrap_or(0)
+    }
+
+    ///Returns true if any recipe making the item has been discovered.
+    pub fn knows(&self, book: &RecipeBook, item: ResourceItemType) -> bool {
+        book.recipes().iter().enumerate().any(|(index, recipe)| recipe.output == item && self.discovered.contains(&index))
+    }
+
+    ///Turns budget into knowledge for `dt` seconds of work by researchers with the given total intellect.
+    ///Returns the whole points of knowledge made.
+    pub fn study(&mut self, intellect: i32, dt: f32) -> i32 {
+        if self.budget <= 0 {return 0;}
+        self.progress += intellect as f32 / 100. * KNOWLEDGE_RATE * dt;
+        let gained = (self.progress.floor() as i32).min(self.budget);
+        self.progress -= gained as f32;
+        self.budget -= gained;
+        gained
+    }
+
+    ///Returns true if every ingredient of the recipe is a raw item or something already produced.
+    pub fn can_research(&self, recipe: &Recipe) -> bool {
+        recipe.ingredients.iter().all(|ingredient| ResourceItemType::all().into_iter()
+            .any(|item| ingredient.accepts(item) && (!matches!(item, ResourceItemType::Processed(_)) || self.produced.contains_key(&item))))
+    }
+
+    ///Spends knowledge on the cheapest recipes that can be researched, for as long as it lasts.
+    ///Returns the indices of the recipes discovered.
+    pub fn discover(&mut self, book: &RecipeBook, knowledge: &mut i32) -> Vec<usize> {
+        let mut found = vec![];
+        loop {
+            let next = book.recipes().iter().enumerate()
+                .filter(|(index, recipe)| !self.discovered.contains(index) && self.can_research(recipe))
+                .min_by_key(|(_, recipe)| research_cost(recipe));
+            let Some((index, recipe)) = next else {break;};
+            if research_cost(recipe) > *knowledge {break;}
+            *knowledge -= research_cost(recipe);
+            self.discovered.insert(index);
+            found.push(index);
+        }
+        found
+    }
+}
+
+///Knowledge needed to discover a recipe.
+pub fn research_cost(recipe: &Recipe) -> i32 {
+    recipe.output.info().value as i32 * KNOWLEDGE_PER_VALUE
+}
+
+///Returns true if the recipe only needs raw items.
+fn recipe_is_raw(recipe: &Recipe) -> bool {
+    Research::default().can_research(recipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::ProcessedType;
+
+    const BOOK: &str = r#"[
+        (output: "Liquid sugar", ingredients: [("Sugar", 1)], processors: ["heater"]),
+        (output: "Caramel", ingredients: [("Liquid sugar", 1)], processors: ["heater"]),
+        (output: "Toffee", ingredients: [("Sugar", 1)], processors: ["heater", "mixer"]),
+    ]"#;
+
+    #[test]
+    fn caramel_needs_liquid_sugar_first() {
+        let (book, _) = RecipeBook::parse(BOOK);
+        let mut research = Research::new(&book);
+        assert_eq!(research.discovered(), &HashSet::from([0]), "liquid sugar is known from the start");
+
+        let mut knowledge = 1000;
+        assert_eq!(research.discover(&book, &mut knowledge), vec![2], "caramel needs liquid sugar to have been made");
+        research.record_produced(ResourceItemType::Processed(ProcessedType::LiquidSugar), 1);
+        assert_eq!(research.discover(&book, &mut knowledge), vec![1]);
+        assert_eq!(knowledge, 1000 - research_cost(&book.recipes()[1]) - research_cost(&book.recipes()[2]));
+    }
+
+    #[test]
+    fn studying_spends_the_budget() {
+        let mut research = Research { budget: 3, ..Research::default() };
+        assert_eq!(research.study(100, 2.5), 2);
+        assert_eq!(research.study(100, 10.), 1, "only 3 gold was set aside");
+        assert_eq!(research.budget, 0);
+        assert_eq!(research.study(100, 10.), 0);
+    }
+}
@@ -0,0 +1,294 @@
+use bevy::prelude::{Color, Component, EventWriter, Query, Res, Time, Transform};
+
+use crate::map_grid::{grid_to_world, MapGrid};
+use crate::stockpile::Stockpile;
+use crate::tile_data::*;
+use crate::walkers::{drop_off, find_route, pick_up, REROUTE_DELAY};
+use crate::{MessageEvent, PlayerStats};
+
+///Items a ship can carry.
+pub const SHIP_CAPACITY: u32 = 200;
+///Tiles a second a ship sails over open water with a traveller of 50 speed.
+pub const SHIP_TILES_PER_SECOND: f32 = 3.;
+///Gold a new ship costs.
+pub const SHIP_COST: i32 = 80;
+///Tiles from a pirate ship within which it attacks passing ships.
+pub const PIRATE_RANGE: usize = 2;
+///Extra cost of sailing through pirate waters, so routes give pirates a wide berth when there's room.
+pub const PIRATE_DANGER: u32 = 5;
+///Color of ship sprites.
+pub const SHIP_COLOR: Color = Color::srgb(0.55, 0.35, 0.2);
+
+///How hard a tile is to sail across, counting the danger of pirates nearby, or None if ships can't go there.
+pub fn sailing_cost(base: TileBaseType, near_pirates: bool) -> Option<u32> {
+    Some(base.sail_cost()? + if near_pirates {PIRATE_DANGER} else {0})
+}
+
+///Returns the grid coordinates of the tiles within `range` steps of a tile, itself included.
+pub fn within(pos: (usize, usize), range: usize) -> impl Iterator<Item = (usize, usize)> {
+    let (x, y) = (pos.0 as i64, pos.1 as i64);
+    let range = range as i64;
+    (x - range..=x + range)
+        .flat_map(move |nx| (y - range..=y + range).map(move |ny| (nx, ny)))
+        .filter(move |(nx, ny)| *nx >= 0 && *ny >= 0 && (nx - x).abs() + (ny - y).abs() <= range)
+        .map(|(nx, ny)| (nx as usize, ny as usize))
+}
+
+///The water a ship ties up at to trade with a stockpile: the tile itself if it's on water, like a ship outpost,
+///otherwise water next to it on the coast.
+pub fn dock_for(port: (usize, usize), sailable: impl Fn((usize, usize)) -> bool) -> Option<(usize, usize)> {
+    if sailable(port) {return Some(port);}
+    MapGrid::neighbours(port.0, port.1).find(|pos| sailable(*pos))
+}
+
+///What a ship is doing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ShipStatus { NoCrew, Sailing, Waiting, NoRoute }
+
+impl ShipStatus {
+    pub fn describe(self) -> &'static str {
+        match self {
+            ShipStatus::NoCrew => "No crew",
+            ShipStatus::Sailing => "Sailing",
+            ShipStatus::Waiting => "Waiting to load or unload",
+            ShipStatus::NoRoute => "No way there by water",
+        }
+    }
+}
+
+///A ship crewed by a traveller, carrying goods across the water from one port to another and sailing back for more.
+#[derive(Component, Clone, Debug)]
+pub struct Ship {
+    pub id: usize,
+    pub ports: [(usize, usize); 2], //stockpiles it loads at and unloads at
+    pub docks: [(usize, usize); 2], //the water it ties up at for each port
+    pub pos: (usize, usize),
+    pub path: Vec<(usize, usize)>, //water still to sail to the end of this leg
+    pub progress: f32, //how far towards the first tile of the path, from 0 to 1
+    pub cargo: Stockpile,
+    pub crew: Option<usize>, //id of the traveller sailing it
+    pub delivering: bool, //heading for the second port rather than the first
+    pub raided_by: Vec<(usize, usize)>, //pirates that have already attacked it this leg
+    pub retry_in: f32, //seconds until it looks for a route again after finding none
+    pub status: ShipStatus,
+}
+
+impl Ship {
+    ///A ship tied up at the first port.
+    pub fn new(id: usize, ports: [(usize, usize); 2], docks: [(usize, usize); 2], crew: usize) -> Ship {
+        Ship {
+            id,
+            ports,
+            docks,
+            pos: docks[0],
+            path: vec![],
+            progress: 0.,
+            cargo: Stockpile::new(SHIP_CAPACITY),
+            crew: Some(crew),
+            delivering: false,
+            raided_by: vec![],
+            retry_in: 0.,
+            status: ShipStatus::Waiting,
+        }
+    }
+
+    ///The port at the end of the leg being sailed.
+    pub fn port(&self) -> (usize, usize) {
+        self.ports[self.delivering as usize]
+    }
+
+    ///Sails for `dt` seconds with a crew of the given speed, along the cheapest route by `cost`.
+    ///Returns true once it is docked at the end of its leg. The caller loads or unloads, then calls `turn_back`.
+    pub fn advance(&mut self, cost: impl Fn((usize, usize)) -> Option<u32>, crew_speed: Option<i32>, dt: f32) -> bool {
+        let Some(crew_speed) = crew_speed else {
+            self.status = ShipStatus::NoCrew;
+            return false;
+        };
+        let dock = self.docks[self.delivering as usize];
+        if self.pos == dock {return true;}
+        if self.path.is_empty() {
+            if self.retry_in > 0. { //no way there last time, so don't search the whole sea again every frame
+                self.retry_in -= dt;
+                return false;
+            }
+            match find_route(self.pos, dock, &cost) {
+                Some(path) => self.path = path,
+                None => {
+                    self.status = ShipStatus::NoRoute;
+                    self.retry_in = REROUTE_DELAY;
+                    return false;
+                }
+            }
+        }
+        self.status = ShipStatus::Sailing;
+        let mut time = dt;
+        while let Some(next) = self.path.first().copied() {
+            if cost(next).is_none() { //the water it was heading for can't be sailed any more
+                self.path.clear();
+                self.progress = 0.;
+                return false;
+            }
+            let tiles_per_second = SHIP_TILES_PER_SECOND * crew_speed.max(1) as f32 / 50.;
+            let needed = (1. - self.progress) / tiles_per_second;
+            if time < needed {
+                self.progress += time * tiles_per_second;
+                break;
+            }
+            time -= needed;
+            self.pos = self.path.remove(0);
+            self.progress = 0.;
+        }
+        self.pos == dock
+    }
+
+    ///Heads off on the other leg of the route.
+    pub fn turn_back(&mut self) {
+        self.delivering = !self.delivering;
+        self.path.clear();
+        self.progress = 0.;
+        self.raided_by.clear();
+        self.retry_in = 0.;
+    }
+}
+
+///Pirates board a passing ship and take part of each cargo: half their damage as a percentage, less half the crew's vigor.
+///Returns how many items they took.
+pub fn plunder(cargo: &mut Stockpile, pirate: &EnemyTile, vigor: i32) -> u32 {
+    let share = (pirate.damage - vigor / 2).clamp(0, 100) as u32 / 2;
+    let mut taken = 0;
+    for (item, count) in cargo.iter().collect::<Vec<_>>() {
+        let lost = (count * share).div_ceil(100);
+        cargo.remove(item, lost);
+        taken += lost;
+    }
+    taken
+}
+
+///Sails every ship between its ports, loading at one and unloading at the other.
+///Pirates attack ships that pass close by, once a leg each.
+pub fn run_ships(
+    time: Res<Time>,
+    map_grid: Res<MapGrid>,
+    mut ship_q: Query<(&mut Ship, &mut Transform)>,
+    mut tile_q: Query<&mut Tile>,
+    player_stats_q: Query<&PlayerStats>,
+    mut messages: EventWriter<MessageEvent>,
+) {
+    let Ok(player_stats) = player_stats_q.get_single() else {return;};
+    for (mut ship, mut transform) in ship_q.iter_mut() {
+        let crew = player_stats.loonkas.iter().find(|loonka| Some(loonka.id) == ship.crew && loonka.current_job == LoonkaJob::Traveller);
+        let tile_at = |pos: (usize, usize)| map_grid.get(pos.0, pos.1).and_then(|entity| tile_q.get(entity).ok());
+        let cost = |pos: (usize, usize)| {
+            let base = tile_at(pos)?.base_type;
+            let near_pirates = within(pos, PIRATE_RANGE).any(|near| tile_at(near).is_some_and(|tile| tile.enemy.enemy_type == EnemyTileType::PirateShip));
+            sailing_cost(base, near_pirates)
+        };
+        if ship.advance(cost, crew.map(|loonka| loonka.speed), time.delta_seconds()) {
+            let port = ship.port();
+            if let Some(mut tile) = map_grid.get(port.0, port.1).and_then(|entity| tile_q.get_mut(entity).ok()) {
+                let ship = &mut *ship;
+                let done = if ship.delivering {
+                    drop_off(&mut ship.cargo, &mut tile.stock);
+                    ship.cargo.total() == 0
+                } else {
+                    pick_up(&mut ship.cargo, &mut tile.stock) > 0
+                };
+                if done {ship.turn_back();} else {ship.status = ShipStatus::Waiting;}
+            }
+        }
+
+        for near in within(ship.pos, PIRATE_RANGE) {
+            if ship.raided_by.contains(&near) {continue;}
+            let Some(tile) = map_grid.get(near.0, near.1).and_then(|entity| tile_q.get(entity).ok()) else {continue;};
+            if tile.enemy.enemy_type != EnemyTileType::PirateShip {continue;}
+            ship.raided_by.push(near);
+            let taken = plunder(&mut ship.cargo, &tile.enemy, crew.map_or(0, |loonka| loonka.vigor));
+            if taken > 0 {
+                messages.send(MessageEvent(format!("Pirates boarded ship {} and took {} goods!", ship.id, taken)));
+            }
+        }
+
+        //drawn part of the way to the next tile
+        let (x, y) = grid_to_world(ship.pos.0 as f32, ship.pos.1 as f32);
+        let (next_x, next_y) = ship.path.first().map_or((x, y), |next| grid_to_world(next.0 as f32, next.1 as f32));
+        transform.translation.x = x + (next_x - x) * ship.progress;
+        transform.translation.y = y + (next_y - y) * ship.progress;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walkers::carry_capacity;
+
+    const SUGAR: ResourceItemType = ResourceItemType::Mine(MineType::Sugar);
+
+    fn pirate(damage: i32) -> EnemyTile {
+        EnemyTile { enemy_type: EnemyTileType::PirateShip, yields: [ResourceItemType::None; 5], amount_mod: 0, health: 50, damage }
+    }
+
+    #[test]
+    fn ships_sail_round_pirates_when_they_can() {
+        //a 7 by 5 sea with pirates in the middle of the bottom edge
+        let pirates = (3, 0);
+        let sea = |pos: (usize, usize)| {
+            if pos.0 >= 7 || pos.1 >= 5 {return None;}
+            sailing_cost(TileBaseType::DeepWater, within(pos, PIRATE_RANGE).any(|near| near == pirates))
+        };
+        let route = find_route((0, 0), (6, 0), sea).unwrap();
+        assert!(route.iter().all(|pos| pos.0.abs_diff(3) + pos.1 > PIRATE_RANGE), "{:?}", route);
+        assert_eq!(within((0, 0), 1).count(), 3, "off the edge of the map isn't counted");
+    }
+
+    #[test]
+    fn ships_wait_before_searching_for_a_route_again() {
+        let searches = std::cell::Cell::new(0);
+        let lake = |pos: (usize, usize)| {
+            searches.set(searches.get() + 1);
+            if pos.0 < 3 && pos.1 < 3 {sailing_cost(TileBaseType::Water, false)} else {None}
+        };
+        let mut ship = Ship::new(0, [(0, 0), (5, 5)], [(0, 0), (5, 5)], 0);
+        ship.turn_back();
+        assert!(!ship.advance(lake, Some(50), 0.1));
+        assert_eq!(ship.status, ShipStatus::NoRoute);
+        let searched = searches.get();
+        for _ in 0..40 {
+            ship.advance(lake, Some(50), 0.1);
+        }
+        assert_eq!(searches.get(), searched, "searched again before the delay was up");
+        ship.turn_back();
+        assert!(ship.advance(lake, Some(50), 0.), "already docked at the first port");
+        ship.turn_back();
+        ship.advance(lake, Some(50), 0.1);
+        assert!(searches.get() > searched, "a new leg looks for a route straight away");
+    }
+
+    #[test]
+    fn ships_dock_on_water_and_carry_more_than_walkers() {
+        let water = |pos: (usize, usize)| pos.0 >= 3;
+        assert_eq!(dock_for((4, 4), water), Some((4, 4)), "ship outposts are on the water");
+        assert_eq!(dock_for((2, 4), water), Some((3, 4)), "coastal stockpiles are next to it");
+        assert_eq!(dock_for((0, 4), water), None);
+        assert!(SHIP_CAPACITY > 10 * carry_capacity(100));
+
+        let mut ship = Ship::new(0, [(2, 0), (2, 4)], [(3, 0), (3, 4)], 0);
+        let sea = |pos: (usize, usize)| if pos.0 >= 3 && pos.0 < 6 && pos.1 < 5 {sailing_cost(TileBaseType::Water, false)} else {None};
+        assert!(ship.advance(sea, Some(50), 0.), "starts docked");
+        ship.turn_back();
+        assert!(!ship.advance(sea, None, 10.));
+        assert_eq!(ship.status, ShipStatus::NoCrew);
+        assert!(!ship.advance(sea, Some(50), 1.));
+        assert!(ship.advance(sea, Some(50), 0.4), "4 tiles at 3 a second");
+        assert_eq!(ship.port(), (2, 4));
+    }
+
+    #[test]
+    fn pirates_take_less_from_hardy_crews() {
+        let mut cargo = Stockpile::new(SHIP_CAPACITY);
+        cargo.add(SUGAR, 100);
+        assert_eq!(plunder(&mut cargo, &pirate(60), 0), 30);
+        assert_eq!(cargo.count(SUGAR), 70);
+        assert_eq!(plunder(&mut cargo, &pirate(60), 100), 4, "5% of 70, rounded up");
+        assert_eq!(plunder(&mut cargo, &pirate(40), 100), 0);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use crate::items::Quality;
+use crate::tile_data::ResourceItemType;
+
+///Number of items an ordinary tile can hold.
+pub const TILE_STOCK_CAPACITY: u32 = 200;
+///Number of items an outpost tile can hold.
+pub const OUTPOST_STOCK_CAPACITY: u32 = 1000;
+
+///Count of each item stored on a tile, limited to a total capacity shared between all items.
+///Items of a type are mixed together, so they share one average quality.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stockpile {
+    items: HashMap<ResourceItemType, u32>,
+    quality: HashMap<ResourceItemType, Quality>,
+    capacity: u32,
+}
+
+impl Stockpile {
+    pub fn new(capacity: u32) -> Stockpile {
+        Stockpile { items: HashMap::new(), quality: HashMap::new(), capacity }
+    }
+
+    ///Returns how many of the item are stored.
+    pub fn count(&self, item: ResourceItemType) -> u32 {
+        self.items.get(&item).copied().unwrap_or(0)
+    }
+
+    ///Returns the average quality of the stored items of a type.
+    pub fn quality(&self, item: ResourceItemType) -> Quality {
+        self.quality.get(&item).copied().unwrap_or_default()
+    }
+
+    ///Returns the number of items stored, of all types.
+    pub fn total(&self) -> u32 {
+        self.items.values().sum()
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    ///Returns how many more items fit.
+    pub fn space(&self) -> u32 {
+        self.capacity.saturating_sub(self.total())
+    }
+
+    ///Adds up to `amount` of the item at standard quality, limited by the free space. Returns how many were added.
+    #[cfg(test)]
+    pub fn add(&mut self, item: ResourceItemType, amount: u32) -> u32 {
+        self.add_with_quality(item, amount, Quality::STANDARD)
+    }
+
+    ///Adds up to `amount` of the item, mixing its quality into what is stored. Returns how many were added.
+    pub fn add_with_quality(&mut self, item: ResourceItemType, amount: u32, quality: Quality) -> u32 {
+        if item == ResourceItemType::None {return 0;}
+        let added = amount.min(self.space());
+        if added > 0 {
+            let stored = self.count(item);
+            self.quality.insert(item, self.quality(item).blend(stored, quality, added));
+            self.items.insert(item, stored + added);
+        }
+        added
+    }
+
+    ///Removes up to `amount` of the item, limited by how many are stored. Returns how many were removed.
+    pub fn remove(&mut self, item: ResourceItemType, amount: u32) -> u32 {
+        let stored = self.count(item);
+        let removed = amount.min(stored);
+        if removed == stored {
+            self.items.remove(&item);
+            self.quality.remove(&item);
+        } else {
+            self.items.insert(item, stored - removed);
+        }
+        removed
+    }
+
+    ///Moves up to `amount` of the item into another stockpile, limited by what is stored here and the space there.
+    ///Returns how many were moved.
+    pub fn transfer(&mut self, to: &mut Stockpile, item: ResourceItemType, amount: u32) -> u32 {
+        let moved = amount.min(self.count(item)).min(to.space());
+        let quality = self.quality(item);
+        self.remove(item, moved);
+        to.add_with_quality(item, moved, quality);
+        moved
+    }
+
+    ///Iterates over the stored items and their counts, sorted by count with the largest first.
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceItemType, u32)> {
+        let mut items: Vec<(ResourceItemType, u32)> = self.items.iter().map(|(item, count)| (*item, *count)).collect();
+        items.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile_data::*;
+
+    const SUGAR: ResourceItemType = ResourceItemType::Mine(MineType::Sugar);
+    const WALNUT: ResourceItemType = ResourceItemType::Nut(NutType::Walnut);
+
+    #[test]
+    fn add_is_limited_by_shared_capacity() {
+        let mut stock = Stockpile::new(10);
+        assert_eq!(stock.add(SUGAR, 6), 6);
+        assert_eq!(stock.add(WALNUT, 6), 4);
+        assert_eq!(stock.total(), 10);
+        assert_eq!(stock.space(), 0);
+        assert_eq!(stock.add(SUGAR, 1), 0);
+    }
+
+    #[test]
+    fn remove_is_limited_by_stored_count() {
+        let mut stock = Stockpile::new(10);
+        stock.add(SUGAR, 3);
+        assert_eq!(stock.remove(SUGAR, 5), 3);
+        assert_eq!(stock.count(SUGAR), 0);
+        assert_eq!(stock.iter().count(), 0);
+    }
+
+    #[test]
+    fn transfer_is_limited_by_destination_space() {
+        let mut from = Stockpile::new(20);
+        let mut to = Stockpile::new(5);
+        from.add(SUGAR, 8);
+        to.add(WALNUT, 2);
+        assert_eq!(from.transfer(&mut to, SUGAR, 8), 3);
+        assert_eq!(from.count(SUGAR), 5);
+        assert_eq!(to.count(SUGAR), 3);
+    }
+
+    #[test]
+    fn quality_mixes_and_moves_with_items() {
+        let mut from = Stockpile::new(20);
+        let mut to = Stockpile::new(20);
+        from.add_with_quality(SUGAR, 2, Quality::new(90));
+        from.add_with_quality(SUGAR, 2, Quality::new(30));
+        assert_eq!(from.quality(SUGAR), Quality::new(60));
+        to.add_with_quality(SUGAR, 1, Quality::new(0));
+        from.transfer(&mut to, SUGAR, 3);
+        assert_eq!(from.quality(SUGAR), Quality::new(60), "removing keeps the average");
+        assert_eq!(to.quality(SUGAR), Quality::new(45));
+    }
+}
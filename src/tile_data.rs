@@ -1,12 +1,9 @@
-use std::vec;
-
-use bevy::prelude::Component;
+use bevy::prelude::{Component, Entity};
 use rand::Rng;
-use rand::distributions::{Distribution, Standard};
 use rand::seq::{IteratorRandom, SliceRandom};
 
-
-
+use crate::items::{ItemTag, ProcessedType, Quality};
+use crate::stockpile::Stockpile;
 
 
 ///Stores tile data including location, resources, base type, and workers.
@@ -14,10 +11,90 @@ use rand::seq::{IteratorRandom, SliceRandom};
 pub struct Tile {
     pub location :(f32,f32), 
     pub base_type: TileBaseType, 
-    pub loonkas: Vec<Loonka>,
+    pub loonkas: Vec<usize>, //ids of the loonkas assigned here
     pub outpost: OutpostTile,
     pub harvest: HarvestableTile,
     pub enemy: EnemyTile,
+    pub stock: Stockpile, //items harvested, processed or delivered here
+    pub last_harvest: Option<f32>, //seconds since startup of the last manual harvest
+    pub processor: Option<Entity>, //processor building on this tile
+}
+
+///Seconds a tile needs to recover before it can be harvested by hand again.
+pub const HARVEST_COOLDOWN: f32 = 5.;
+///Water collected from a water tile per manual harvest.
+pub const WATER_HARVEST_AMOUNT: u32 = 5;
+///Dexterity used for the quality of harvests when no harvester works the tile.
+pub const UNSKILLED_DEXTERITY: i32 = 25;
+
+///Reasons a manual harvest can fail.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HarvestError {
+    Nothing, //the tile has nothing to harvest
+    Cooldown(f32), //seconds left until the tile can be harvested again
+    StockFull,
+    NoWarriors, //an enemy tile can only be raided by warriors assigned to it
+}
+
+///Outcome of a raid that went ahead.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Raid {
+    Repelled(i32), //the enemy still stands and hits the warriors for this much vigor
+    Cleared(Vec<(ResourceItemType, u32, Quality)>), //the enemy is gone and its loot was stored on the tile
+}
+
+impl Tile {
+    ///Harvests the tile by hand into its own stock: water from water tiles, whatever the tile yields,
+    ///and the loot of any enemy raided on it. Quality comes from the tile's modifiers and the harvester's dexterity.
+    ///Returns the items, amounts and qualities that were added.
+    pub fn harvest(&mut self, now: f32, dexterity: i32) -> Result<Vec<(ResourceItemType, u32, Quality)>, HarvestError> {
+        let mut yields = vec![];
+        if self.base_type.is_water() {
+            yields.push((ResourceItemType::Water, WATER_HARVEST_AMOUNT, Quality::harvested(Quality::STANDARD.value() as i32, dexterity)));
+        }
+        if self.harvest.yields != ResourceItemType::None {
+            yields.push((self.harvest.yields, self.harvest.amount(), Quality::harvested(self.harvest.amount_mod, dexterity)));
+        }
+        if yields.is_empty() {return Err(HarvestError::Nothing);}
+
+        if let Some(last) = self.last_harvest {
+            let remaining = HARVEST_COOLDOWN - (now - last);
+            if remaining > 0. {return Err(HarvestError::Cooldown(remaining));}
+        }
+
+        let added: Vec<(ResourceItemType, u32, Quality)> = yields.into_iter()
+            .map(|(item, amount, quality)| (item, self.stock.add_with_quality(item, amount, quality), quality))
+            .filter(|(_, amount, _)| *amount > 0)
+            .collect();
+        if added.is_empty() {return Err(HarvestError::StockFull);}
+
+        self.last_harvest = Some(now);
+        Ok(added)
+    }
+
+    ///Sends the warriors on this tile against its enemy, sharing the harvest cooldown.
+    ///Their strength wears down the enemy's health; the enemy is only looted and cleared once it has none left.
+    pub fn raid(&mut self, now: f32, strength: i32, dexterity: i32) -> Result<Raid, HarvestError> {
+        if self.enemy.enemy_type == EnemyTileType::None {return Err(HarvestError::Nothing);}
+        if strength <= 0 {return Err(HarvestError::NoWarriors);}
+
+        if let Some(last) = self.last_harvest {
+            let remaining = HARVEST_COOLDOWN - (now - last);
+            if remaining > 0. {return Err(HarvestError::Cooldown(remaining));}
+        }
+        self.last_harvest = Some(now);
+
+        self.enemy.health -= strength;
+        if self.enemy.health > 0 {return Ok(Raid::Repelled(self.enemy.damage));}
+
+        let quality = Quality::harvested(self.enemy.amount_mod, dexterity);
+        let looted = self.enemy.loot().into_iter()
+            .map(|(item, amount)| (item, self.stock.add_with_quality(item, amount, quality), quality))
+            .filter(|(_, amount, _)| *amount > 0)
+            .collect();
+        self.enemy = EnemyTile::NONE;
+        Ok(Raid::Cleared(looted))
+    }
 }
 
 ///Stores type of the outpost.
@@ -26,13 +103,13 @@ pub enum OutpostType { None, Outpost, ForestOutpost, MountainOutpost, ShipOutpos
 impl Eq for OutpostType {} //partialEq is implemented
 
 impl OutpostType {
-    pub fn get_rand(base: TileBaseType) -> OutpostType {
+    pub fn get_rand(base: TileBaseType, rng: &mut impl Rng) -> OutpostType {
         let options: Vec<i32>;
         if base == TileBaseType::Mountain || base == TileBaseType::DarkMountain {options = vec![2]} 
         else if base == TileBaseType::Water || base == TileBaseType::DeepWater || base == TileBaseType::Pond || base == TileBaseType::River {options = vec![1]} //if mountain, only mines or spices
         else if base == TileBaseType::Forest || base == TileBaseType::DarkForest || base == TileBaseType::Jungle {options = vec![0]}
         else {options = vec![3]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
+        let random = options.iter().choose(rng).unwrap();
         match random {
             0 => OutpostType::ForestOutpost,
             1 => OutpostType::ShipOutpost,
@@ -50,11 +127,28 @@ pub struct OutpostTile {
 }
 impl Eq for OutpostTile {} //partialEq is implemented
 
+impl OutpostTile {
+    ///Gold paid for each item of a lot, or None if this isn't an outpost or the item isn't candy.
+    ///Higher quality candy and outposts with a higher sales modifier (up to double) pay more.
+    pub fn price(&self, item: ResourceItemType, quality: Quality) -> Option<i32> {
+        if self.outpost_type == OutpostType::None || !item.has_tag(ItemTag::Candy) {return None;}
+        let price = item.info().value as f32 * quality.price_multiplier() * (1. + self.sales_mod as f32 / 100.);
+        Some((price.round() as i32).max(1))
+    }
+}
+
 ///Stores type of yield and amount of a harvestable tile.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
 pub struct HarvestableTile {
     pub yields: ResourceItemType,
-    pub amount_mod: i32,
+    pub amount_mod: i32, //0-100
+}
+
+impl HarvestableTile {
+    ///Items gained per harvest, from 1 to 5 depending on the amount modifier.
+    pub fn amount(&self) -> u32 {
+        1 + self.amount_mod.clamp(0, 100) as u32 / 25
+    }
 }
 
 ///Stores data about a loonka worker.
@@ -70,6 +164,7 @@ pub struct Loonka{
     pub charisma: i32, //(trader), (clown)
     pub intellect: i32, //(researcher)
     pub icon_num: i32,
+    pub tile: Option<Entity>, //tile the loonka is assigned to
 }
 
 ///Stores details of a Loonka's job
@@ -103,195 +198,470 @@ pub struct EnemyTile {
 }
 impl Eq for EnemyTile {} //partialEq is implemented
 
+impl EnemyTile {
+    pub const NONE: EnemyTile = EnemyTile { enemy_type: EnemyTileType::None, yields: [ResourceItemType::None; 5], amount_mod: 0, health: 0, damage: 0 };
+
+    ///Items taken per raid: each distinct drop, from 1 to 5 of each depending on the amount modifier.
+    pub fn loot(&self) -> Vec<(ResourceItemType, u32)> {
+        if self.enemy_type == EnemyTileType::None {return vec![];}
+        let amount = 1 + self.amount_mod.clamp(0, 100) as u32 / 25;
+        let mut loot: Vec<(ResourceItemType, u32)> = vec![];
+        for item in self.yields {
+            if item != ResourceItemType::None && !loot.iter().any(|(looted, _)| *looted == item) {
+                loot.push((item, amount));
+            }
+        }
+        loot
+    }
+}
+
 ///Stores type of the enemy tile
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
 pub enum EnemyTileType { None, PirateShip, BeepleBillage}
 
 impl EnemyTileType {
-    pub fn get_rand(base: TileBaseType) -> EnemyTileType {
+    pub fn get_rand(base: TileBaseType, rng: &mut impl Rng) -> EnemyTileType {
         let options: Vec<i32>;
         if base == TileBaseType::Water || base == TileBaseType::DeepWater || base == TileBaseType::Pond || base == TileBaseType::River {options = vec![2]} //if mountain, only mines or spices
         else if base == TileBaseType::Forest || base == TileBaseType::Plains {options = vec![0]}
         else {options = vec![1]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
+        let random = options.iter().choose(rng).unwrap();
         match random {
             0 => EnemyTileType::BeepleBillage,
             1 => EnemyTileType::None,
             _ => EnemyTileType::PirateShip,
         }
     }
+
+    ///Returns the weighted table of items this enemy can drop.
+    pub fn drop_table(self) -> &'static [SpawnEntry] {
+        match self {
+            EnemyTileType::BeepleBillage => BEEPLE_DROPS,
+            EnemyTileType::PirateShip => PIRATE_DROPS,
+            EnemyTileType::None => &[],
+        }
+    }
+
+    ///Picks a random drop from the drop table of this enemy.
+    pub fn get_rand_drop(self, rng: &mut impl Rng) -> ResourceItemType {
+        match self.drop_table().choose_weighted(rng, |(_, weight)| *weight) {
+            Ok((item, _)) => *item,
+            Err(_) => ResourceItemType::None,
+        }
+    }
 }
 
 ///Stores type of the resource tile, and yield of that tile.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
-pub enum ResourceItemType { None, Nut(NutType),Bush(BushType),Fruit(FruitType),Mine(MineType),Spice(SpiceType)}
+pub enum ResourceItemType { None, Water, Nut(NutType),Bush(BushType),Fruit(FruitType),Mine(MineType),Spice(SpiceType),Bee(BeeType),Processed(ProcessedType)}
+impl Eq for ResourceItemType {} //partialEq is implemented
 
 impl ResourceItemType {
-    pub fn get_rand(base: TileBaseType) -> ResourceItemType {
-        let options: Vec<i32>;
-        if base == TileBaseType::Mountain || base == TileBaseType::DarkMountain {options = vec![3,4]}
-        else if base == TileBaseType::Water || base == TileBaseType::DeepWater || base == TileBaseType::Pond || base == TileBaseType::River {options = vec![4]} //only spice if water for seamint
-        else {options = vec![0,1,2,4]}; //if mountain, only mines or spices
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
-        match random {
-            0 => ResourceItemType::Nut(NutType::get_rand(base)),
-            1 => ResourceItemType::Bush(BushType::get_rand(base)),
-            2 => ResourceItemType::Fruit(FruitType::get_rand(base)),
-            3 => ResourceItemType::Mine(MineType::get_rand(base)),
-            _ => ResourceItemType::Spice(SpiceType::get_rand(base)),
+    ///Picks a random resource from the spawn table of the given biome.
+    pub fn get_rand(base: TileBaseType, rng: &mut impl Rng) -> ResourceItemType {
+        match spawn_table(base).choose_weighted(rng, |(_, weight)| *weight) {
+            Ok((item, _)) => *item,
+            Err(_) => ResourceItemType::None, //empty table
         }
     }
 }
 
 ///Stores type of nut.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
-pub enum NutType {None, Walnut, Chestnut, Hazelnut, Macademia, Pistachio, Cocoa, Almond, Peanut}
-
-impl NutType {
-    pub fn get_rand(base: TileBaseType) -> NutType {
-        let options: Vec<i32>;
-        if base == TileBaseType::DarkForest {options = vec![0,4]} 
-        else if base == TileBaseType::Jungle {options = vec![3,5,7]} 
-        else if base == TileBaseType::DarkForest {options = vec![0,1,2]}
-        else if base == TileBaseType::Forest {options = vec![0,6]}
-        else if base == TileBaseType::All {options = vec![0,1,2,3,4,5,6,7]}
-        else {options = vec![6]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
-        match random {
-            0 => NutType::Walnut,
-            1 => NutType::Chestnut,
-            2 => NutType::Hazelnut,
-            3 => NutType::Macademia,
-            4 => NutType::Pistachio,
-            5 => NutType::Cocoa,
-            6 => NutType::Almond,
-            _ => NutType::Peanut,
-        }
-    }
-}
+pub enum NutType {Walnut, Chestnut, Hazelnut, Macademia, Pistachio, Cocoa, Almond, Peanut}
 
 ///Stores type of bush.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
-pub enum BushType{None, Strawberry, Blueberry, Blackberry, Grapes, Raspberry, Coffee, Marshmallow, Rhubarb}
-
-impl BushType {
-    pub fn get_rand(base: TileBaseType) -> BushType {
-        let options: Vec<i32>;
-        if base == TileBaseType::DarkForest {options = vec![2,4]} 
-        else if base == TileBaseType::Jungle {options = vec![5]} 
-        else if base == TileBaseType::DarkForest {options = vec![5,7]}
-        else if base == TileBaseType::Forest {options = vec![0,1,2,4]}
-        else if base == TileBaseType::All {options = vec![0,1,2,3,4,5,6,7]}
-        else {options = vec![3,6]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
-        match random {
-            0 => BushType::Strawberry,
-            1 => BushType::Blueberry,
-            2 => BushType::Blackberry,
-            3 => BushType::Grapes,
-            4 => BushType::Raspberry,
-            5 => BushType::Coffee,
-            6 => BushType::Marshmallow,
-            _ => BushType::Rhubarb,
-        }
-    }
-}
+pub enum BushType{Strawberry, Blueberry, Blackberry, Grapes, Raspberry, Coffee, Marshmallow, Rhubarb}
 
 ///Stores type of fruit.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
-pub enum FruitType {None, Banana, Coconut, Cherry, Apple, Peach, Plum, Apricot, Pear, Elderberry, Goji, Lemon, Lime, MapleSyrup, Orange, Pineapple}
-
-impl FruitType {
-    pub fn get_rand(base: TileBaseType) -> FruitType {
-        let options: Vec<i32>;
-        if base == TileBaseType::Jungle {options = vec![0,1,9,14]} 
-        else if base == TileBaseType::DarkForest {options = vec![2,5,8,12]}
-        else if base == TileBaseType::Forest {options = vec![3,4,6,7,10,11,13]}
-        else if base == TileBaseType::Mud || base == TileBaseType::Dirt || base == TileBaseType::DarkDirt {options = vec![5,8]}
-        else if base == TileBaseType::All {options = vec![0,1,2,3,4,5,6,7,8,9,10,11,12,13,14]}
-        else {options = vec![3,13]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
-        match random {
-            0  => FruitType::Banana,
-            1  => FruitType::Coconut,
-            2  => FruitType::Cherry,
-            3  => FruitType::Apple,
-            4  => FruitType::Peach,
-            5  => FruitType::Plum,
-            6  => FruitType::Apricot,
-            7  => FruitType::Pear,
-            8  => FruitType::Elderberry,
-            9  => FruitType::Goji,
-            10 => FruitType::Lemon,
-            11 => FruitType::Lime,
-            12 => FruitType::MapleSyrup,
-            13 => FruitType::Orange,
-            _ => FruitType::Pineapple,
-        }
-    }
-}
+pub enum FruitType {Banana, Coconut, Cherry, Apple, Peach, Plum, Apricot, Pear, Elderberry, Goji, Lemon, Lime, MapleSyrup, Orange, Pineapple}
 
 ///Stores type of mine.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
-pub enum MineType {None, Sugar, JellyCrystals, Salt, Milkgem, Maltgem}
-
-impl MineType {
-    pub fn get_rand(base: TileBaseType) -> MineType {
-        let options: Vec<i32>;
-        if base == TileBaseType::Mountain {options = vec![0,2,3]} 
-        else if base == TileBaseType::DarkMountain {options = vec![1,4]} 
-        else if base == TileBaseType::All {options = vec![0,1,2,3,4]}
-        else {options = vec![3,6]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
-        match random {
-            0 => MineType::Sugar,
-            1 => MineType::JellyCrystals,
-            2 => MineType::Salt,
-            3 => MineType::Milkgem,
-            _ => MineType::Maltgem,
-        }
-    }
-}
+pub enum MineType {Sugar, JellyCrystals, Salt, Milkgem, Maltgem}
 
 ///Stores type of spice.
 #[derive(PartialEq, Hash, Clone, Copy, Debug)]
-pub enum SpiceType {None, Cinammon, Ginger, EssenceOfBear, EssenceOfWorm, Peppermint, Icemint, Seamint, Spearmint, GumgumBush, Rose, LicoriceRoot, Violet, Vanilla}
-impl SpiceType {
-    pub fn get_rand(base: TileBaseType) -> SpiceType {
-        let options: Vec<i32>;
-        if base == TileBaseType::Water || base == TileBaseType::DeepWater || base == TileBaseType::Pond || base == TileBaseType::River {options = vec![6]} 
-        else if base == TileBaseType::Jungle {options = vec![0,1,8,12]} 
-        else if base == TileBaseType::DarkForest {options = vec![0,2,10,12]}
-        else if base == TileBaseType::Forest {options = vec![0,1,4]}
-        else if base == TileBaseType::Mountain || base == TileBaseType::DarkMountain {options = vec![2,7,9,11,5]} //bear, ice, spear, violet, rose
-        else if base == TileBaseType::Dirt || base == TileBaseType::DarkDirt {options = vec![3]}
-        else if base == TileBaseType::All {options = vec![0,1,2,3,4,5,6,7,8,9,10,11,12]}
-        else {options = vec![4,5,9,11]}
-        let random = options.iter().choose(&mut rand::thread_rng()).unwrap();
-        match random {
-            0  => SpiceType::Cinammon,
-            1  => SpiceType::Ginger,
-            2  => SpiceType::EssenceOfBear,
-            3  => SpiceType::EssenceOfWorm,
-            4  => SpiceType::Peppermint,
-            5  => SpiceType::Icemint,
-            6  => SpiceType::Seamint, //done
-            7  => SpiceType::Spearmint,
-            8  => SpiceType::GumgumBush,
-            9  => SpiceType::Rose,
-            10 => SpiceType::LicoriceRoot,
-            11  => SpiceType::Violet,
-            _  => SpiceType::Vanilla,
-        }
+pub enum SpiceType {Cinammon, Ginger, EssenceOfBear, EssenceOfWorm, Peppermint, Icemint, Seamint, Spearmint, GumgumBush, Rose, LicoriceRoot, Violet, Vanilla}
+
+///Stores type of beeple product.
+#[derive(PartialEq, Hash, Clone, Copy, Debug)]
+pub enum BeeType {Honey, ButterbeeButter, Molasses, Wafer, Honeycomb}
+
+///A resource that can spawn on a biome, and its weight relative to the other resources in the same table.
+pub type SpawnEntry = (ResourceItemType, u32);
+
+///Returns the weighted table of resources that can spawn on the given biome.
+pub fn spawn_table(base: TileBaseType) -> &'static [SpawnEntry] {
+    match base {
+        TileBaseType::Plains | TileBaseType::Sand => PLAINS_SPAWNS,
+        TileBaseType::Forest => FOREST_SPAWNS,
+        TileBaseType::DarkForest => DARK_FOREST_SPAWNS,
+        TileBaseType::Jungle => JUNGLE_SPAWNS,
+        TileBaseType::Mountain => MOUNTAIN_SPAWNS,
+        TileBaseType::DarkMountain => DARK_MOUNTAIN_SPAWNS,
+        TileBaseType::Dirt | TileBaseType::DarkDirt => DIRT_SPAWNS,
+        TileBaseType::Mud => MUD_SPAWNS,
+        TileBaseType::Water | TileBaseType::DeepWater | TileBaseType::River | TileBaseType::Pond => WATER_SPAWNS,
     }
 }
 
+///Every biome a map tile can have.
+pub const BIOMES: [TileBaseType; 14] = [
+    TileBaseType::Plains, TileBaseType::Forest, TileBaseType::DarkForest, TileBaseType::Jungle, TileBaseType::Mountain, TileBaseType::DarkMountain, TileBaseType::Sand,
+    TileBaseType::Dirt, TileBaseType::DarkDirt, TileBaseType::Mud, TileBaseType::Water, TileBaseType::DeepWater, TileBaseType::River, TileBaseType::Pond,
+];
+
+///Returns every biome whose spawn table includes the item.
+pub fn spawn_biomes(item: ResourceItemType) -> Vec<TileBaseType> {
+    BIOMES.into_iter()
+        .filter(|base| spawn_table(*base).iter().any(|(spawn, _)| *spawn == item))
+        .collect()
+}
+
+//Land tables give each of their categories (nut, bush, fruit, spice) a total weight of 12.
+const PLAINS_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Nut(NutType::Almond), 12),
+    (ResourceItemType::Bush(BushType::Grapes), 6),
+    (ResourceItemType::Bush(BushType::Marshmallow), 6),
+    (ResourceItemType::Fruit(FruitType::Apple), 6),
+    (ResourceItemType::Fruit(FruitType::Orange), 6),
+    (ResourceItemType::Spice(SpiceType::Peppermint), 3),
+    (ResourceItemType::Spice(SpiceType::Icemint), 3),
+    (ResourceItemType::Spice(SpiceType::Rose), 3),
+    (ResourceItemType::Spice(SpiceType::Violet), 3),
+];
+
+const FOREST_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Nut(NutType::Walnut), 4),
+    (ResourceItemType::Nut(NutType::Almond), 4),
+    (ResourceItemType::Nut(NutType::Chestnut), 2),
+    (ResourceItemType::Nut(NutType::Hazelnut), 2),
+    (ResourceItemType::Bush(BushType::Strawberry), 3),
+    (ResourceItemType::Bush(BushType::Blueberry), 3),
+    (ResourceItemType::Bush(BushType::Blackberry), 3),
+    (ResourceItemType::Bush(BushType::Raspberry), 3),
+    (ResourceItemType::Fruit(FruitType::Apple), 2),
+    (ResourceItemType::Fruit(FruitType::Peach), 2),
+    (ResourceItemType::Fruit(FruitType::Apricot), 2),
+    (ResourceItemType::Fruit(FruitType::Pear), 2),
+    (ResourceItemType::Fruit(FruitType::Orange), 2),
+    (ResourceItemType::Fruit(FruitType::Lemon), 1),
+    (ResourceItemType::Fruit(FruitType::Lime), 1),
+    (ResourceItemType::Spice(SpiceType::Cinammon), 4),
+    (ResourceItemType::Spice(SpiceType::Ginger), 4),
+    (ResourceItemType::Spice(SpiceType::Peppermint), 4),
+];
+
+const DARK_FOREST_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Nut(NutType::Walnut), 6),
+    (ResourceItemType::Nut(NutType::Pistachio), 6),
+    (ResourceItemType::Bush(BushType::Blackberry), 4),
+    (ResourceItemType::Bush(BushType::Raspberry), 4),
+    (ResourceItemType::Bush(BushType::Rhubarb), 4),
+    (ResourceItemType::Fruit(FruitType::Cherry), 3),
+    (ResourceItemType::Fruit(FruitType::Plum), 3),
+    (ResourceItemType::Fruit(FruitType::Elderberry), 3),
+    (ResourceItemType::Fruit(FruitType::MapleSyrup), 3),
+    (ResourceItemType::Spice(SpiceType::Cinammon), 3),
+    (ResourceItemType::Spice(SpiceType::EssenceOfBear), 3),
+    (ResourceItemType::Spice(SpiceType::LicoriceRoot), 3),
+    (ResourceItemType::Spice(SpiceType::Vanilla), 3),
+];
+
+const JUNGLE_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Nut(NutType::Macademia), 4),
+    (ResourceItemType::Nut(NutType::Cocoa), 4),
+    (ResourceItemType::Nut(NutType::Peanut), 4),
+    (ResourceItemType::Bush(BushType::Coffee), 12),
+    (ResourceItemType::Fruit(FruitType::Banana), 3),
+    (ResourceItemType::Fruit(FruitType::Coconut), 3),
+    (ResourceItemType::Fruit(FruitType::Goji), 3),
+    (ResourceItemType::Fruit(FruitType::Pineapple), 3),
+    (ResourceItemType::Spice(SpiceType::Cinammon), 3),
+    (ResourceItemType::Spice(SpiceType::Ginger), 3),
+    (ResourceItemType::Spice(SpiceType::GumgumBush), 3),
+    (ResourceItemType::Spice(SpiceType::Vanilla), 3),
+];
+
+const DIRT_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Nut(NutType::Almond), 12),
+    (ResourceItemType::Bush(BushType::Grapes), 6),
+    (ResourceItemType::Bush(BushType::Marshmallow), 6),
+    (ResourceItemType::Fruit(FruitType::Plum), 6),
+    (ResourceItemType::Fruit(FruitType::Elderberry), 6),
+    (ResourceItemType::Spice(SpiceType::EssenceOfWorm), 12),
+];
+
+const MUD_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Nut(NutType::Almond), 12),
+    (ResourceItemType::Bush(BushType::Grapes), 6),
+    (ResourceItemType::Bush(BushType::Marshmallow), 6),
+    (ResourceItemType::Fruit(FruitType::Plum), 6),
+    (ResourceItemType::Fruit(FruitType::Elderberry), 6),
+    (ResourceItemType::Spice(SpiceType::Peppermint), 3),
+    (ResourceItemType::Spice(SpiceType::Icemint), 3),
+    (ResourceItemType::Spice(SpiceType::Rose), 3),
+    (ResourceItemType::Spice(SpiceType::Violet), 3),
+];
+
+//Mountains only have mines and spices.
+const MOUNTAIN_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Mine(MineType::Sugar), 4),
+    (ResourceItemType::Mine(MineType::Salt), 4),
+    (ResourceItemType::Mine(MineType::Milkgem), 4),
+    (ResourceItemType::Spice(SpiceType::EssenceOfBear), 3),
+    (ResourceItemType::Spice(SpiceType::Spearmint), 3),
+    (ResourceItemType::Spice(SpiceType::Icemint), 2),
+    (ResourceItemType::Spice(SpiceType::Rose), 2),
+    (ResourceItemType::Spice(SpiceType::Violet), 2),
+];
+
+const DARK_MOUNTAIN_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Mine(MineType::JellyCrystals), 6),
+    (ResourceItemType::Mine(MineType::Maltgem), 6),
+    (ResourceItemType::Spice(SpiceType::EssenceOfBear), 3),
+    (ResourceItemType::Spice(SpiceType::Spearmint), 3),
+    (ResourceItemType::Spice(SpiceType::Icemint), 2),
+    (ResourceItemType::Spice(SpiceType::Rose), 2),
+    (ResourceItemType::Spice(SpiceType::Violet), 2),
+];
+
+//Seamint is the only thing that grows in water.
+const WATER_SPAWNS: &[SpawnEntry] = &[
+    (ResourceItemType::Spice(SpiceType::Seamint), 1),
+];
+
+//Beeple products only come from beeple billages, honey being the most common.
+const BEEPLE_DROPS: &[SpawnEntry] = &[
+    (ResourceItemType::Bee(BeeType::Honey), 4),
+    (ResourceItemType::Bee(BeeType::ButterbeeButter), 2),
+    (ResourceItemType::Bee(BeeType::Molasses), 2),
+    (ResourceItemType::Bee(BeeType::Wafer), 2),
+    (ResourceItemType::Bee(BeeType::Honeycomb), 2),
+];
+
+//Pirates carry what they take from tropical islands and passing ships.
+const PIRATE_DROPS: &[SpawnEntry] = &[
+    (ResourceItemType::Fruit(FruitType::Coconut), 4),
+    (ResourceItemType::Fruit(FruitType::Banana), 3),
+    (ResourceItemType::Fruit(FruitType::Pineapple), 3),
+    (ResourceItemType::Fruit(FruitType::Lime), 3),
+    (ResourceItemType::Mine(MineType::Sugar), 3),
+    (ResourceItemType::Mine(MineType::Salt), 2),
+    (ResourceItemType::Spice(SpiceType::Seamint), 2),
+    (ResourceItemType::Spice(SpiceType::Cinammon), 1),
+    (ResourceItemType::Spice(SpiceType::Vanilla), 1),
+];
+
 ///Stores tile base type
 #[derive(Component, Hash, PartialEq, Clone, Copy, Debug)]
 pub enum TileBaseType {
-    Plains, Forest, DarkForest, Jungle, Mountain, DarkMountain, Sand, Dirt, DarkDirt, Mud, Water, DeepWater, River, Pond, All}
+    Plains, Forest, DarkForest, Jungle, Mountain, DarkMountain, Sand, Dirt, DarkDirt, Mud, Water, DeepWater, River, Pond}
 impl Eq for TileBaseType {
     //partialEq is implemented
 }
 
+impl TileBaseType {
+    pub fn is_water(self) -> bool {
+        matches!(self, TileBaseType::Water | TileBaseType::DeepWater | TileBaseType::River | TileBaseType::Pond)
+    }
+
+    ///How hard the tile is to cross on foot, or None if it can't be walked.
+    pub fn move_cost(self) -> Option<u32> {
+        match self {
+            TileBaseType::Plains | TileBaseType::Dirt | TileBaseType::DarkDirt => Some(1),
+            TileBaseType::Sand | TileBaseType::Forest => Some(2),
+            TileBaseType::DarkForest | TileBaseType::Mud => Some(3),
+            TileBaseType::Jungle | TileBaseType::Mountain | TileBaseType::River => Some(4),
+            TileBaseType::DarkMountain | TileBaseType::Pond | TileBaseType::Water => Some(6), //wading
+            TileBaseType::DeepWater => None,
+        }
+    }
+
+    ///How hard the tile is to sail across, or None if ships can't go there.
+    pub fn sail_cost(self) -> Option<u32> {
+        match self {
+            TileBaseType::Water | TileBaseType::DeepWater => Some(1),
+            TileBaseType::River | TileBaseType::Pond => Some(2), //narrow and shallow
+            _ => None,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    ///Every resource that grows or is mined on the map.
+    fn all_resources() -> Vec<ResourceItemType> {
+        ResourceItemType::all().into_iter()
+            .filter(|item| matches!(item, ResourceItemType::Nut(_) | ResourceItemType::Bush(_) | ResourceItemType::Fruit(_) | ResourceItemType::Mine(_) | ResourceItemType::Spice(_)))
+            .collect()
+    }
+
+    ///Mines only on mountains, seamint only in water, nothing else in water, and no trees or bushes on mountains.
+    fn is_legal(base: TileBaseType, item: ResourceItemType) -> bool {
+        let water = base.is_water();
+        let mountain = matches!(base, TileBaseType::Mountain | TileBaseType::DarkMountain);
+        match item {
+            ResourceItemType::None | ResourceItemType::Water => false, //water comes from the tile itself, not a spawn
+            ResourceItemType::Spice(SpiceType::Seamint) => water,
+            _ if water => false,
+            ResourceItemType::Mine(_) => mountain,
+            ResourceItemType::Nut(_) | ResourceItemType::Bush(_) | ResourceItemType::Fruit(_) => !mountain,
+            ResourceItemType::Spice(_) => true,
+            ResourceItemType::Bee(_) => false, //beeple only
+            ResourceItemType::Processed(_) => false,
+        }
+    }
 
+    #[test]
+    fn every_resource_spawns_on_some_biome() {
+        for item in all_resources() {
+            assert!(BIOMES.iter().any(|base| spawn_table(*base).iter().any(|(entry, _)| *entry == item)),
+                "{:?} can't spawn on any biome", item);
+        }
+    }
+
+    #[test]
+    fn spawn_tables_are_non_empty_with_positive_weights() {
+        for base in BIOMES {
+            let table = spawn_table(base);
+            assert!(!table.is_empty(), "{:?} has an empty spawn table", base);
+            assert!(table.iter().all(|(_, weight)| *weight > 0), "{:?} has a zero weight entry", base);
+        }
+    }
+
+    #[test]
+    fn biomes_only_list_legal_resources() {
+        for base in BIOMES {
+            for (item, _) in spawn_table(base) {
+                assert!(is_legal(base, *item), "{:?} shouldn't spawn on {:?}", item, base);
+            }
+        }
+    }
+
+    #[test]
+    fn biomes_only_roll_legal_resources() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for base in BIOMES {
+            for _ in 0..500 {
+                let item = ResourceItemType::get_rand(base, &mut rng);
+                assert!(is_legal(base, item), "rolled {:?} on {:?}", item, base);
+            }
+        }
+    }
+
+    #[test]
+    fn beeple_drop_every_bee_product_and_nothing_else() {
+        let bee_products = [BeeType::Honey, BeeType::ButterbeeButter, BeeType::Molasses, BeeType::Wafer, BeeType::Honeycomb];
+        let drops = EnemyTileType::BeepleBillage.drop_table();
+        for product in bee_products {
+            assert!(drops.iter().any(|(item, _)| *item == ResourceItemType::Bee(product)), "beeple don't drop {:?}", product);
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..500 {
+            let item = EnemyTileType::BeepleBillage.get_rand_drop(&mut rng);
+            assert!(matches!(item, ResourceItemType::Bee(_)), "beeple dropped {:?}", item);
+        }
+    }
+
+    fn test_tile(base_type: TileBaseType, yields: ResourceItemType) -> Tile {
+        Tile {
+            location: (0., 0.),
+            base_type,
+            loonkas: vec![],
+            outpost: OutpostTile { outpost_type: OutpostType::None, sales_mod: 0 },
+            harvest: HarvestableTile { yields, amount_mod: 100 },
+            enemy: EnemyTile::NONE,
+            stock: Stockpile::new(10),
+            last_harvest: None,
+            processor: None,
+        }
+    }
+
+    #[test]
+    fn water_tiles_always_harvest_water() {
+        let mut tile = test_tile(TileBaseType::River, ResourceItemType::None);
+        assert_eq!(tile.harvest(0., 50), Ok(vec![(ResourceItemType::Water, WATER_HARVEST_AMOUNT, Quality::STANDARD)]));
+        assert_eq!(tile.stock.count(ResourceItemType::Water), WATER_HARVEST_AMOUNT);
+        assert_eq!(test_tile(TileBaseType::Plains, ResourceItemType::None).harvest(0., 50), Err(HarvestError::Nothing));
+    }
+
+    #[test]
+    fn raiding_takes_each_distinct_drop_once() {
+        let honey = ResourceItemType::Bee(BeeType::Honey);
+        let wafer = ResourceItemType::Bee(BeeType::Wafer);
+        let mut tile = test_tile(TileBaseType::Forest, ResourceItemType::None);
+        tile.enemy = EnemyTile { enemy_type: EnemyTileType::BeepleBillage, yields: [honey, wafer, honey, ResourceItemType::None, wafer], amount_mod: 0, health: 0, damage: 0 };
+        let quality = Quality::harvested(0, 50);
+        assert_eq!(tile.raid(0., 10, 50), Ok(Raid::Cleared(vec![(honey, 1, quality), (wafer, 1, quality)])));
+    }
+
+    #[test]
+    fn enemies_fight_back_until_their_health_runs_out() {
+        let honey = ResourceItemType::Bee(BeeType::Honey);
+        let mut tile = test_tile(TileBaseType::Forest, ResourceItemType::None);
+        tile.enemy = EnemyTile { enemy_type: EnemyTileType::BeepleBillage, yields: [honey; 5], amount_mod: 0, health: 50, damage: 30 };
+        assert_eq!(tile.harvest(0., 50), Err(HarvestError::Nothing), "enemies can't be harvested");
+        assert_eq!(tile.raid(0., 0, 50), Err(HarvestError::NoWarriors));
+        assert_eq!(tile.raid(0., 30, 50), Ok(Raid::Repelled(30)));
+        assert_eq!(tile.enemy.health, 20);
+        assert_eq!(tile.stock.count(honey), 0);
+        assert_eq!(tile.raid(1., 30, 50), Err(HarvestError::Cooldown(HARVEST_COOLDOWN - 1.)));
+        assert_eq!(tile.raid(HARVEST_COOLDOWN, 30, 50), Ok(Raid::Cleared(vec![(honey, 1, Quality::harvested(0, 50))])));
+        assert_eq!(tile.enemy, EnemyTile::NONE, "a defeated enemy is gone");
+        assert_eq!(tile.raid(HARVEST_COOLDOWN * 2., 30, 50), Err(HarvestError::Nothing));
+    }
+
+    #[test]
+    fn pirates_drop_from_their_own_table() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..500 {
+            let item = EnemyTileType::PirateShip.get_rand_drop(&mut rng);
+            assert!(PIRATE_DROPS.iter().any(|(drop, _)| *drop == item), "pirates dropped {:?}", item);
+        }
+        assert_eq!(EnemyTileType::None.get_rand_drop(&mut rng), ResourceItemType::None);
+    }
+
+    #[test]
+    fn harvest_respects_cooldown_and_stock_space() {
+        let almond = ResourceItemType::Nut(NutType::Almond);
+        let mut tile = test_tile(TileBaseType::Forest, almond);
+        let quality = Quality::harvested(100, 50);
+        assert_eq!(tile.harvest(0., 50), Ok(vec![(almond, 5, quality)]));
+        assert_eq!(tile.harvest(1., 50), Err(HarvestError::Cooldown(HARVEST_COOLDOWN - 1.)));
+        assert_eq!(tile.harvest(HARVEST_COOLDOWN, 50), Ok(vec![(almond, 5, quality)]));
+        assert_eq!(tile.harvest(HARVEST_COOLDOWN * 2., 50), Err(HarvestError::StockFull));
+    }
+
+    #[test]
+    fn outposts_pay_more_for_better_candy() {
+        let toffee = ResourceItemType::Processed(ProcessedType::Toffee);
+        let outpost = OutpostTile { outpost_type: OutpostType::Outpost, sales_mod: 0 };
+        let value = toffee.info().value as i32;
+        assert_eq!(outpost.price(toffee, Quality::new(0)), Some(value / 2));
+        assert_eq!(outpost.price(toffee, Quality::new(100)), Some(value * 3 / 2));
+        assert_eq!(OutpostTile { sales_mod: 100, ..outpost }.price(toffee, Quality::new(100)), Some(value * 3));
+        assert_eq!(outpost.price(ResourceItemType::Nut(NutType::Almond), Quality::new(100)), None, "only candy sells");
+    }
+
+    #[test]
+    fn better_tiles_and_harvesters_harvest_better_goods() {
+        let almond = ResourceItemType::Nut(NutType::Almond);
+        let mut rich = test_tile(TileBaseType::Forest, almond);
+        let mut poor = test_tile(TileBaseType::Forest, almond);
+        poor.harvest.amount_mod = 20;
+        rich.harvest(0., 100).unwrap();
+        poor.harvest(0., UNSKILLED_DEXTERITY).unwrap();
+        assert_eq!(rich.stock.quality(almond).grade(), "Superb");
+        assert_eq!(poor.stock.quality(almond).grade(), "Poor");
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{Color, Commands, Component, DespawnRecursiveExt, Entity, EventWriter, Local, Query, Res, ResMut, Resource, Time, Transform};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::map_grid::{grid_to_world, MapGrid};
+use crate::rails::{Blocks, RailGraph};
+use crate::ships::Ship;
+use crate::stockpile::Stockpile;
+use crate::tile_data::*;
+use crate::walkers::{travelling_loonkas, Walker};
+use crate::{MessageEvent, PlayerStats};
+
+///Items a train can carry.
+pub const TRAIN_CAPACITY: u32 = 50;
+///Tiles a second a train moves with a traveller of 50 speed.
+pub const TRAIN_TILES_PER_SECOND: f32 = 2.;
+///Gold a new train costs.
+pub const TRAIN_COST: i32 = 40;
+///Color of train sprites.
+pub const TRAIN_COLOR: Color = Color::srgb(0.75, 0.2, 0.25);
+
+///Whether the train panel is open and which train it shows. Stops are added to the selected train.
+#[derive(Resource, Default, Debug)]
+pub struct TrainPanel {
+    pub open: bool,
+    pub selected: Option<Entity>,
+}
+
+///The trains and the train panel, with the hauls and ships whose loonkas can't also crew a train.
+#[derive(SystemParam)]
+pub struct Fleet<'w, 's> {
+    pub panel: ResMut<'w, TrainPanel>,
+    pub train_q: Query<'w, 's, (Entity, &'static mut Train)>,
+    pub walker_q: Query<'w, 's, &'static Walker>,
+    pub ship_q: Query<'w, 's, &'static Ship>,
+}
+
+impl Fleet<'_, '_> {
+    ///Ids of the loonkas away crewing trains or ships or hauling on foot.
+    pub fn travelling(&self) -> HashSet<usize> {
+        travelling_loonkas(self.train_q.iter().map(|(_, train)| train), self.walker_q.iter(), self.ship_q.iter())
+    }
+}
+
+///A stop in a train's schedule: a tile with track, and what to do with the tile's stock there.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Stop {
+    pub pos: (usize, usize), //grid coordinates
+    pub unload: bool, //put all cargo into the tile's stock
+    pub load: bool, //take all of the tile's stock, except what was just unloaded
+}
+
+impl Stop {
+    pub fn new(pos: (usize, usize)) -> Stop {
+        Stop { pos, unload: true, load: true }
+    }
+}
+
+///Signal blocks with a train in them, or about to move into them, and the id of that train.
+pub type Occupied = HashMap<usize, usize>;
+
+///What a train is doing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TrainStatus {
+    NoCrew,
+    NoSchedule,
+    Travelling,
+    NoRoute,
+    Blocked(usize), //waiting at a signal for the train with this id to clear the next block
+}
+
+impl TrainStatus {
+    pub fn describe(self) -> String {
+        match self {
+            TrainStatus::NoCrew => "No crew".to_string(),
+            TrainStatus::NoSchedule => "No schedule".to_string(),
+            TrainStatus::Travelling => "Travelling".to_string(),
+            TrainStatus::NoRoute => "No route".to_string(),
+            TrainStatus::Blocked(other) => format!("Waiting for train {}", other),
+        }
+    }
+}
+
+///A train running on the track, crewed by a traveller loonka and following a schedule of stops in order.
+#[derive(Component, Clone, Debug)]
+pub struct Train {
+    pub id: usize,
+    pub stops: Vec<Stop>,
+    pub next_stop: usize, //index into stops
+    pub pos: (usize, usize), //the tile the train is on, or leaving
+    pub path: Vec<(usize, usize)>, //track still to run to the next stop
+    pub progress: f32, //how far towards the first tile of the path, from 0 to 1
+    pub cargo: Stockpile,
+    pub crew: Option<usize>, //id of the traveller driving it
+    pub status: TrainStatus,
+}
+
+impl Train {
+    pub fn new(id: usize, pos: (usize, usize)) -> Train {
+        Train {
+            id,
+            stops: vec![],
+            next_stop: 0,
+            pos,
+            path: vec![],
+            progress: 0.,
+            cargo: Stockpile::new(TRAIN_CAPACITY),
+            crew: None,
+            status: TrainStatus::NoSchedule,
+        }
+    }
+
+    ///The stop the train is heading for.
+    pub fn destination(&self) -> Option<Stop> {
+        self.stops.get(self.next_stop).copied()
+    }
+
+    ///The signal blocks the train is in: the one it is on, and the one it is moving into.
+    pub fn occupies<'a>(&'a self, blocks: &'a Blocks) -> impl Iterator<Item = usize> + 'a {
+        let entering = self.path.first().filter(|_| self.progress > 0.);
+        std::iter::once(self.pos).chain(entering.copied()).filter_map(|pos| blocks.block(pos))
+    }
+
+    ///The train in the way, if the next tile is in another block and that block is taken.
+    fn blocked_by(&self, blocks: &Blocks, occupied: &Occupied) -> Option<usize> {
+        let next = blocks.block(*self.path.first()?)?;
+        if blocks.block(self.pos) == Some(next) {return None;}
+        occupied.get(&next).copied().filter(|other| *other != self.id)
+    }
+
+    ///Takes the block of the next tile, so no other train moves into it.
+    fn claim_next(&self, blocks: &Blocks, occupied: &mut Occupied) {
+        if let Some(next) = self.path.first().and_then(|pos| blocks.block(*pos)) {
+            occupied.entry(next).or_insert(self.id);
+        }
+    }
+
+    ///Moves the train along the track for `dt` seconds with a crew of the given speed.
+    ///A train only moves into another signal block if no other train is in it. If one is, it takes a way round
+    ///the blocks other trains are in, or waits at the signal if there is none.
+    ///Returns the stop it arrived at, if it got to one. The caller services the stop, then calls `depart`.
+    pub fn advance(&mut self, graph: &RailGraph, blocks: &Blocks, occupied: &mut Occupied, crew_speed: Option<i32>, dt: f32) -> Option<Stop> {
+        let Some(crew_speed) = crew_speed else {
+            self.status = TrainStatus::NoCrew;
+            return None;
+        };
+        let stop = self.destination()?;
+        if self.pos == stop.pos {return Some(stop);}
+
+        if self.path.is_empty() || !graph.has_track(self.pos) || self.path.iter().any(|pos| !graph.has_track(*pos)) { //new leg, or the track changed under it
+            match graph.find_path(self.pos, stop.pos) {
+                Some(path) => self.path = path,
+                None => {
+                    self.path.clear();
+                    self.status = TrainStatus::NoRoute;
+                    return None;
+                }
+            }
+        }
+        if self.progress == 0. {
+            if let Some(other) = self.blocked_by(blocks, occupied) {
+                let taken = |pos| blocks.block(pos).and_then(|block| occupied.get(&block)).is_some_and(|id| *id != self.id);
+                match graph.find_path_avoiding(self.pos, stop.pos, taken) {
+                    Some(path) => self.path = path,
+                    None => {
+                        self.status = TrainStatus::Blocked(other);
+                        return None;
+                    }
+                }
+            }
+            self.claim_next(blocks, occupied);
+        }
+
+        self.status = TrainStatus::Travelling;
+        self.progress += dt * TRAIN_TILES_PER_SECOND * crew_speed as f32 / 50.;
+        while self.progress >= 1. && !self.path.is_empty() {
+            self.pos = self.path.remove(0);
+            self.progress -= 1.;
+            if let Some(other) = self.blocked_by(blocks, occupied) { //stop at the signal
+                self.progress = 0.;
+                self.status = TrainStatus::Blocked(other);
+                break;
+            }
+            self.claim_next(blocks, occupied);
+        }
+        if self.path.is_empty() {
+            self.progress = 0.;
+        }
+        if self.pos == stop.pos {Some(stop)} else {None}
+    }
+
+    ///Leaves the current stop for the next one in the schedule.
+    pub fn depart(&mut self) {
+        if self.stops.is_empty() {return;}
+        self.next_stop = (self.next_stop + 1) % self.stops.len();
+        self.path.clear();
+    }
+}
+
+///Works out which blocks each train is in, before any of them move this tick.
+pub fn occupied_blocks<'a>(trains: impl Iterator<Item = &'a Train>, blocks: &Blocks) -> Occupied {
+    let mut occupied = Occupied::new();
+    for train in trains {
+        for block in train.occupies(blocks) {
+            occupied.entry(block).or_insert(train.id);
+        }
+    }
+    occupied
+}
+
+///Finds groups of trains that are all waiting on each other at signals, and so will never move again.
+///Each group is the ids of its trains, smallest first.
+pub fn deadlocks(trains: &[(usize, TrainStatus)]) -> Vec<Vec<usize>> {
+    let waiting_for: HashMap<usize, usize> = trains.iter()
+        .filter_map(|(id, status)| match status {
+            TrainStatus::Blocked(other) => Some((*id, *other)),
+            _ => None,
+        })
+        .collect();
+    let mut found = HashSet::new();
+    for start in waiting_for.keys() {
+        let mut chain = vec![*start];
+        while let Some(next) = waiting_for.get(chain.last().unwrap()) {
+            if let Some(index) = chain.iter().position(|id| id == next) {
+                let mut cycle = chain.split_off(index);
+                cycle.sort();
+                found.insert(cycle);
+                break;
+            }
+            chain.push(*next);
+        }
+    }
+    let mut found: Vec<Vec<usize>> = found.into_iter().collect();
+    found.sort();
+    found
+}
+
+///Carries out a stop's orders between the train's cargo and the tile's stock.
+///Returns how many items were unloaded and loaded.
+pub fn service_stop(stop: Stop, cargo: &mut Stockpile, stock: &mut Stockpile) -> (u32, u32) {
+    let mut unloaded_items = vec![];
+    let mut unloaded = 0;
+    if stop.unload {
+        for (item, count) in cargo.iter().collect::<Vec<_>>() {
+            let moved = cargo.transfer(stock, item, count);
+            if moved > 0 {
+                unloaded += moved;
+                unloaded_items.push(item);
+            }
+        }
+    }
+    let mut loaded = 0;
+    if stop.load {
+        for (item, count) in stock.iter().collect::<Vec<_>>() {
+            if unloaded_items.contains(&item) {continue;}
+            loaded += stock.transfer(cargo, item, count);
+        }
+    }
+    (unloaded, loaded)
+}
+
+///Warns about each deadlock once, when it forms.
+#[derive(SystemParam)]
+pub struct DeadlockWarnings<'w, 's> {
+    reported: Local<'s, Vec<Vec<usize>>>, //deadlocks already warned about
+    messages: EventWriter<'w, MessageEvent>,
+}
+
+impl DeadlockWarnings<'_, '_> {
+    ///Warns about the deadlocks that weren't there last time.
+    fn report(&mut self, deadlocks: Vec<Vec<usize>>) {
+        for deadlock in deadlocks.iter().filter(|deadlock| !self.reported.contains(deadlock)) {
+            let trains: Vec<String> = deadlock.iter().map(|id| id.to_string()).collect();
+            self.messages.send(MessageEvent(format!("Deadlock! Trains {} are all waiting on each other at signals.", trains.join(", "))));
+        }
+        *self.reported = deadlocks;
+    }
+}
+
+///Moves every train along its route and carries out the orders of the stops it arrives at.
+///Warns when trains deadlock waiting on each other at signals.
+pub fn run_trains(
+    time: Res<Time>,
+    rail_graph: Res<RailGraph>,
+    map_grid: Res<MapGrid>,
+    mut train_q: Query<(&mut Train, &mut Transform)>,
+    mut tile_q: Query<&mut Tile>,
+    player_stats_q: Query<&PlayerStats>,
+    mut deadlock_warnings: DeadlockWarnings,
+) {
+    let Ok(player_stats) = player_stats_q.get_single() else {return;};
+    let blocks = rail_graph.blocks();
+    let mut occupied = occupied_blocks(train_q.iter().map(|(train, _)| train), &blocks);
+    for (mut train, mut transform) in train_q.iter_mut() {
+        let crew_speed = player_stats.loonkas.iter()
+            .find(|loonka| Some(loonka.id) == train.crew && loonka.current_job == LoonkaJob::Traveller)
+            .map(|loonka| loonka.speed);
+        if let Some(stop) = train.advance(&rail_graph, &blocks, &mut occupied, crew_speed, time.delta_seconds()) {
+            if let Some(mut tile) = map_grid.get(stop.pos.0, stop.pos.1).and_then(|entity| tile_q.get_mut(entity).ok()) {
+                service_stop(stop, &mut train.cargo, &mut tile.stock);
+            }
+            train.depart();
+        }
+
+        //drawn part of the way to the next tile
+        let (x, y) = grid_to_world(train.pos.0 as f32, train.pos.1 as f32);
+        let (next_x, next_y) = train.path.first().map_or((x, y), |next| grid_to_world(next.0 as f32, next.1 as f32));
+        transform.translation.x = x + (next_x - x) * train.progress;
+        transform.translation.y = y + (next_y - y) * train.progress;
+    }
+
+    let statuses: Vec<(usize, TrainStatus)> = train_q.iter().map(|(train, _)| (train.id, train.status)).collect();
+    deadlock_warnings.report(deadlocks(&statuses));
+}
+
+///Draws the train panel while it is open: the trains, and the selected train's crew, cargo and schedule. T opens and closes it.
+pub fn train_panel_window(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    fleet: Fleet,
+    mut player_stats_q: Query<&mut PlayerStats>,
+) {
+    let Fleet { mut panel, mut train_q, walker_q, ship_q } = fleet;
+    if !panel.open {return;}
+    let Ok(mut player_stats) = player_stats_q.get_single_mut() else {return;};
+    let mut trains: Vec<(Entity, usize, Option<usize>)> = train_q.iter().map(|(entity, train)| (entity, train.id, train.crew)).collect();
+    trains.sort_by_key(|(_, id, _)| *id);
+    let mut open = panel.open;
+
+    egui::Window::new("Trains").open(&mut open).default_width(320.).show(contexts.ctx_mut(), |ui| {
+        ui.label("Right-click track and choose Add Stop to add a stop to the selected train, or to build a new train there.");
+        ui.horizontal_wrapped(|ui| {
+            for (entity, id, _) in &trains {
+                if ui.selectable_label(panel.selected == Some(*entity), format!("Train {}", id)).clicked() {
+                    panel.selected = Some(*entity);
+                }
+            }
+            if ui.selectable_label(panel.selected.is_none(), "New train").clicked() {
+                panel.selected = None;
+            }
+        });
+        ui.separator();
+
+        let Some((entity, mut train)) = panel.selected.and_then(|entity| train_q.get_mut(entity).ok()) else {
+            ui.label(format!("Add a stop to build a train for {} gold.", TRAIN_COST));
+            return;
+        };
+        ui.label(format!("Status: {}", train.status.describe()));
+
+        //travellers without a tile, another train, a ship or a haul can crew it
+        let crew_name = |id: Option<usize>| player_stats.loonkas.iter().find(|loonka| Some(loonka.id) == id).map_or("Nobody".to_string(), |loonka| loonka.name.clone());
+        let mut crew = train.crew;
+        egui::ComboBox::from_label("Crew").selected_text(crew_name(crew)).show_ui(ui, |ui| {
+            ui.selectable_value(&mut crew, None, "Nobody");
+            for loonka in player_stats.loonkas.iter().filter(|loonka| loonka.current_job == LoonkaJob::Traveller && loonka.tile.is_none()) {
+                if trains.iter().any(|(other, _, other_crew)| *other != entity && *other_crew == Some(loonka.id)) {continue;}
+                if walker_q.iter().any(|walker| walker.loonka == loonka.id) || ship_q.iter().any(|ship| ship.crew == Some(loonka.id)) {continue;}
+                ui.selectable_value(&mut crew, Some(loonka.id), format!("{} (speed {})", loonka.name, loonka.speed));
+            }
+        });
+        train.crew = crew;
+
+        ui.label(format!("Cargo ({}/{})", train.cargo.total(), train.cargo.capacity()));
+        for (item, count) in train.cargo.iter() {
+            ui.label(format!("  {}: {} ({})", item.info().name, count, train.cargo.quality(item).grade()));
+        }
+
+        ui.label("Stops:");
+        let mut removed = None;
+        let next_stop = train.next_stop;
+        for (index, stop) in train.stops.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}{} ({}, {})", if index == next_stop {"> "} else {"  "}, index + 1, stop.pos.0, stop.pos.1));
+                ui.checkbox(&mut stop.unload, "Unload");
+                ui.checkbox(&mut stop.load, "Load");
+                if ui.small_button("x").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            train.stops.remove(index);
+            train.next_stop = if train.stops.is_empty() {0} else {train.next_stop % train.stops.len()};
+            train.path.clear();
+        }
+
+        if ui.button(format!("Scrap train (+{} gold)", TRAIN_COST)).clicked() {
+            commands.entity(entity).despawn_recursive();
+            player_stats.gold += TRAIN_COST;
+            panel.selected = None;
+        }
+    });
+    panel.open = open;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUGAR: ResourceItemType = ResourceItemType::Mine(MineType::Sugar);
+    const TOFFEE: ResourceItemType = ResourceItemType::Processed(crate::items::ProcessedType::Toffee);
+
+    fn line(length: usize) -> RailGraph {
+        let mut graph = RailGraph::default();
+        graph.lay(&(0..length).map(|x| (x, 0)).collect::<Vec<_>>());
+        graph
+    }
+
+    fn advance(train: &mut Train, graph: &RailGraph, crew_speed: Option<i32>, dt: f32) -> Option<Stop> {
+        train.advance(graph, &graph.blocks(), &mut Occupied::new(), crew_speed, dt)
+    }
+
+    fn shuttle(id: usize, stops: &[(usize, usize)]) -> Train {
+        let mut train = Train::new(id, stops[0]);
+        train.stops = stops.iter().map(|pos| Stop::new(*pos)).collect();
+        train
+    }
+
+    ///Runs the trains for a while the way the game does, checking no two are ever in the same block.
+    ///Returns how many stops each train got to.
+    fn run(trains: &mut [Train], graph: &RailGraph, seconds: f32) -> Vec<u32> {
+        let blocks = graph.blocks();
+        let mut arrivals = vec![0; trains.len()];
+        for _ in 0..(seconds * 64.) as usize {
+            let mut occupied = occupied_blocks(trains.iter(), &blocks);
+            for (index, train) in trains.iter_mut().enumerate() {
+                if train.advance(graph, &blocks, &mut occupied, Some(50), 1. / 64.).is_some() {
+                    arrivals[index] += 1;
+                    train.depart();
+                }
+            }
+            let mut seen = HashMap::new();
+            for train in trains.iter() {
+                for block in train.occupies(&blocks) {
+                    let other = *seen.entry(block).or_insert(train.id);
+                    assert_eq!(other, train.id, "trains {} and {} share a block", other, train.id);
+                }
+            }
+        }
+        arrivals
+    }
+
+    fn statuses(trains: &[Train]) -> Vec<(usize, TrainStatus)> {
+        trains.iter().map(|train| (train.id, train.status)).collect()
+    }
+
+    #[test]
+    fn trains_run_between_their_stops() {
+        let graph = line(5);
+        let mut train = Train::new(0, (0, 0));
+        train.stops = vec![Stop::new((0, 0)), Stop::new((4, 0))];
+        assert_eq!(advance(&mut train, &graph, None, 1.), None);
+        assert_eq!(train.status, TrainStatus::NoCrew);
+        assert_eq!(advance(&mut train, &graph, Some(50), 0.), Some(train.stops[0]), "starts at the first stop");
+        train.depart();
+        assert_eq!(advance(&mut train, &graph, Some(50), 1.), None);
+        assert_eq!(train.pos, (2, 0));
+        assert_eq!(advance(&mut train, &graph, Some(100), 0.5), Some(train.stops[1]), "faster crews arrive sooner");
+        train.depart();
+        assert_eq!(train.destination(), Some(train.stops[0]));
+    }
+
+    #[test]
+    fn trains_wait_when_the_track_is_cut() {
+        let mut graph = line(5);
+        let mut train = Train::new(0, (0, 0));
+        train.stops = vec![Stop::new((4, 0))];
+        advance(&mut train, &graph, Some(50), 0.5);
+        assert_eq!(train.pos, (1, 0));
+        graph.remove((3, 0));
+        assert_eq!(advance(&mut train, &graph, Some(50), 10.), None);
+        assert_eq!(train.status, TrainStatus::NoRoute);
+        graph.lay(&[(2, 0), (3, 0), (4, 0)]);
+        advance(&mut train, &graph, Some(50), 10.);
+        assert_eq!(train.pos, (4, 0));
+    }
+
+    #[test]
+    fn trains_share_a_signalled_loop() {
+        //the edge of a 5 by 5 square, with a signal halfway along each side
+        let mut graph = RailGraph::default();
+        graph.lay(&[(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (4, 1), (4, 2), (4, 3), (4, 4), (3, 4), (2, 4), (1, 4), (0, 4), (0, 3), (0, 2), (0, 1), (0, 0)]);
+        for pos in [(2, 0), (4, 2), (2, 4), (0, 2)] {
+            graph.toggle_signal(pos);
+        }
+        //all going the same way round, corner to corner
+        let corners = [(0, 0), (4, 0), (4, 4), (0, 4)];
+        let mut trains: Vec<Train> = (0..3).map(|id| shuttle(id, &[corners[id], corners[(id + 1) % 4], corners[(id + 2) % 4], corners[(id + 3) % 4]])).collect();
+        let arrivals = run(&mut trains, &graph, 60.);
+        assert!(arrivals.iter().all(|count| *count >= 5), "{:?}", arrivals);
+        assert!(deadlocks(&statuses(&trains)).is_empty());
+    }
+
+    #[test]
+    fn trains_pass_on_a_siding() {
+        //a main line with a siding right beside its middle, and signals where the two split
+        let mut graph = line(7);
+        graph.lay(&[(1, 0), (1, 1), (2, 1), (3, 1), (4, 1), (5, 1), (5, 0)]);
+        for pos in [(2, 0), (4, 0), (2, 1), (4, 1)] {
+            graph.toggle_signal(pos);
+        }
+        let mut trains = vec![shuttle(0, &[(0, 0), (6, 0)]), shuttle(1, &[(6, 0), (0, 0)])];
+        let arrivals = run(&mut trains, &graph, 60.);
+        assert!(arrivals.iter().all(|count| *count >= 5), "{:?}", arrivals);
+        assert!(deadlocks(&statuses(&trains)).is_empty());
+    }
+
+    #[test]
+    fn trains_meeting_on_a_single_line_deadlock() {
+        let mut graph = line(7);
+        graph.toggle_signal((3, 0));
+        let mut trains = vec![shuttle(0, &[(0, 0), (6, 0)]), shuttle(1, &[(6, 0), (0, 0)])];
+        run(&mut trains, &graph, 10.);
+        assert_eq!(deadlocks(&statuses(&trains)), vec![vec![0, 1]]);
+        assert_eq!(deadlocks(&[(0, TrainStatus::Blocked(1)), (1, TrainStatus::Travelling)]), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn stops_unload_before_loading() {
+        let mut cargo = Stockpile::new(TRAIN_CAPACITY);
+        let mut stock = Stockpile::new(100);
+        cargo.add(SUGAR, 10);
+        stock.add(SUGAR, 5);
+        stock.add(TOFFEE, 3);
+        assert_eq!(service_stop(Stop::new((0, 0)), &mut cargo, &mut stock), (10, 3));
+        assert_eq!(stock.count(SUGAR), 15, "unloaded sugar stays unloaded");
+        assert_eq!(cargo.count(TOFFEE), 3);
+        let load_only = Stop { unload: false, ..Stop::new((0, 0)) };
+        assert_eq!(service_stop(load_only, &mut cargo, &mut stock), (0, 15));
+    }
+}
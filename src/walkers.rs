@@ -0,0 +1,389 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{Color, Commands, Component, DespawnRecursiveExt, Entity, Query, Res, Resource, Time, Transform};
+
+use crate::logistics::Delivery;
+use crate::map_grid::{grid_to_world, MapGrid};
+use crate::ships::Ship;
+use crate::stockpile::Stockpile;
+use crate::tile_data::*;
+use crate::trains::Train;
+use crate::{PlayerStats, GRID_COLS, GRID_ROWS};
+
+///Tiles a second a loonka of 50 speed walks over plains.
+pub const WALK_TILES_PER_SECOND: f32 = 1.5;
+///Seconds a loonka or ship that found no route waits before searching the map for one again.
+pub const REROUTE_DELAY: f32 = 5.;
+///Color of loonkas hauling goods on foot.
+pub const WALKER_COLOR: Color = Color::srgb(0.95, 0.8, 0.3);
+
+///Items a loonka can carry on foot: a few, and more the more vigor it has.
+pub fn carry_capacity(vigor: i32) -> u32 {
+    2 + vigor.max(0) as u32 / 10
+}
+
+///Finds the cheapest walk from one tile to another with A*, not counting the tile it starts on.
+///`cost` gives how hard each tile is to step onto, or None if it can't be walked.
+pub fn find_route(from: (usize, usize), to: (usize, usize), cost: impl Fn((usize, usize)) -> Option<u32>) -> Option<Vec<(usize, usize)>> {
+    cost(to)?;
+    let distance = |pos: (usize, usize)| (pos.0.abs_diff(to.0) + pos.1.abs_diff(to.1)) as u32; //no tile costs less than 1
+    let mut came_from = HashMap::new();
+    let mut best = HashMap::from([(from, 0)]);
+    let mut open = BinaryHeap::from([Reverse((distance(from), 0, from))]);
+    while let Some(Reverse((_, spent, pos))) = open.pop() {
+        if pos == to {
+            let mut route = vec![];
+            let mut step = to;
+            while step != from {
+                route.push(step);
+                step = came_from[&step];
+            }
+            route.reverse();
+            return Some(route);
+        }
+        if spent > best[&pos] {continue;} //already reached more cheaply
+        for next in MapGrid::neighbours(pos.0, pos.1) {
+            let Some(step_cost) = cost(next) else {continue;};
+            let total = spent + step_cost;
+            if best.get(&next).is_some_and(|known| *known <= total) {continue;}
+            best.insert(next, total);
+            came_from.insert(next, pos);
+            open.push(Reverse((total + distance(next), total, next)));
+        }
+    }
+    None
+}
+
+///Adds up how hard the cheapest walk from one tile to another is, or None if there's no way there.
+pub fn route_cost(from: (usize, usize), to: (usize, usize), cost: impl Fn((usize, usize)) -> Option<u32>) -> Option<u32> {
+    find_route(from, to, &cost)?.into_iter().map(&cost).sum()
+}
+
+///Which stretch of land each tile is on. Loonkas can only walk between tiles on the same stretch,
+///so this answers whether a walk is possible without searching the map for a route.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct WalkRegions {
+    regions: Vec<Option<u32>>, //indexed by x * GRID_ROWS + y, None where the tile can't be walked
+}
+
+impl WalkRegions {
+    ///Floods out from each walkable tile not yet reached to number the stretches of land.
+    pub fn new(walkable: impl Fn((usize, usize)) -> bool) -> WalkRegions {
+        let mut regions = vec![None; GRID_COLS * GRID_ROWS];
+        let mut next = 0;
+        for start in 0..regions.len() {
+            let pos = (start / GRID_ROWS, start % GRID_ROWS);
+            if regions[start].is_some() || !walkable(pos) {continue;}
+            regions[start] = Some(next);
+            let mut open = vec![pos];
+            while let Some(pos) = open.pop() {
+                for neighbour in MapGrid::neighbours(pos.0, pos.1) {
+                    let index = neighbour.0 * GRID_ROWS + neighbour.1;
+                    if regions[index].is_none() && walkable(neighbour) {
+                        regions[index] = Some(next);
+                        open.push(neighbour);
+                    }
+                }
+            }
+            next += 1;
+        }
+        WalkRegions { regions }
+    }
+
+    ///Returns true if a loonka could walk from one tile to the other.
+    pub fn connected(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let region = |pos: (usize, usize)| self.regions.get(pos.0 * GRID_ROWS + pos.1).copied().flatten();
+        region(from).is_some() && region(from) == region(to)
+    }
+}
+
+///What a walker is doing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum WalkerStatus { Walking, Waiting, NoRoute }
+
+impl WalkerStatus {
+    pub fn describe(self) -> &'static str {
+        match self {
+            WalkerStatus::Walking => "Walking",
+            WalkerStatus::Waiting => "Waiting to pick up or drop off",
+            WalkerStatus::NoRoute => "No way there on foot",
+        }
+    }
+}
+
+///A traveller loonka carrying goods on foot from one stockpile to another and walking back for more.
+#[derive(Component, Clone, Debug)]
+pub struct Walker {
+    pub loonka: usize, //id of the traveller
+    pub source: (usize, usize), //grid coordinates of the stockpile it picks up from
+    pub destination: (usize, usize), //and the one it delivers to
+    pub pos: (usize, usize),
+    pub path: Vec<(usize, usize)>, //tiles still to walk to the end of this leg
+    pub progress: f32, //how far towards the first tile of the path, from 0 to 1
+    pub load: Stockpile,
+    pub delivering: bool, //heading for the destination rather than the source
+    pub order: Option<Delivery>, //a single delivery for the logistics network, done once dropped off
+    pub retry_in: f32, //seconds until it looks for a route again after finding none
+    pub status: WalkerStatus,
+}
+
+impl Walker {
+    ///A walker starting out at the source, carrying as much as the loonka's vigor allows.
+    pub fn new(loonka: usize, vigor: i32, source: (usize, usize), destination: (usize, usize)) -> Walker {
+        Walker {
+            loonka,
+            source,
+            destination,
+            pos: source,
+            path: vec![],
+            progress: 0.,
+            load: Stockpile::new(carry_capacity(vigor)),
+            delivering: false,
+            order: None,
+            retry_in: 0.,
+            status: WalkerStatus::Waiting,
+        }
+    }
+
+    ///A walker making one delivery for the logistics network.
+    pub fn for_delivery(loonka: usize, vigor: i32, delivery: Delivery) -> Walker {
+        Walker { order: Some(delivery), ..Walker::new(loonka, vigor, delivery.from, delivery.to) }
+    }
+
+    ///The end of the leg being walked.
+    pub fn target(&self) -> (usize, usize) {
+        if self.delivering {self.destination} else {self.source}
+    }
+
+    ///Walks for `dt` seconds at a pace set by the loonka's speed, slowed by the cost of the tile being walked onto.
+    ///Returns true once it is at the end of its leg. The caller picks up or drops off, then calls `turn_back`.
+    pub fn advance(&mut self, cost: impl Fn((usize, usize)) -> Option<u32>, speed: i32, dt: f32) -> bool {
+        let target = self.target();
+        if self.pos == target {return true;}
+        if self.path.is_empty() {
+            if self.retry_in > 0. { //no way there last time, so don't search the whole map again every frame
+                self.retry_in -= dt;
+                return false;
+            }
+            match find_route(self.pos, target, &cost) {
+                Some(path) => self.path = path,
+                None => {
+                    self.status = WalkerStatus::NoRoute;
+                    self.retry_in = REROUTE_DELAY;
+                    return false;
+                }
+            }
+        }
+        self.status = WalkerStatus::Walking;
+        let mut time = dt;
+        while let Some(next) = self.path.first().copied() {
+            let Some(step_cost) = cost(next) else { //the way got cut
+                self.path.clear();
+                self.progress = 0.;
+                return false;
+            };
+            let tiles_per_second = WALK_TILES_PER_SECOND * speed.max(1) as f32 / 50. / step_cost as f32;
+            let needed = (1. - self.progress) / tiles_per_second;
+            if time < needed {
+                self.progress += time * tiles_per_second;
+                break;
+            }
+            time -= needed;
+            self.pos = self.path.remove(0);
+            self.progress = 0.;
+        }
+        self.pos == target
+    }
+
+    ///Heads off on the other leg of the haul.
+    pub fn turn_back(&mut self) {
+        self.delivering = !self.delivering;
+        self.path.clear();
+        self.progress = 0.;
+        self.retry_in = 0.;
+    }
+}
+
+///Fills a walker's load from a stockpile, most plentiful items first. Returns how many it picked up.
+pub fn pick_up(load: &mut Stockpile, stock: &mut Stockpile) -> u32 {
+    stock.iter().collect::<Vec<_>>().into_iter().map(|(item, count)| stock.transfer(load, item, count)).sum()
+}
+
+///Empties a walker's load into a stockpile. Returns how many it dropped off; the rest stays carried.
+pub fn drop_off(load: &mut Stockpile, stock: &mut Stockpile) -> u32 {
+    load.iter().collect::<Vec<_>>().into_iter().map(|(item, count)| load.transfer(stock, item, count)).sum()
+}
+
+///Ids of loonkas out crewing trains or ships or hauling on foot, who can't also work a tile.
+pub fn travelling_loonkas<'a>(
+    trains: impl Iterator<Item = &'a Train>,
+    walkers: impl Iterator<Item = &'a Walker>,
+    ships: impl Iterator<Item = &'a Ship>,
+) -> HashSet<usize> {
+    trains.filter_map(|train| train.crew)
+        .chain(walkers.map(|walker| walker.loonka))
+        .chain(ships.filter_map(|ship| ship.crew))
+        .collect()
+}
+
+///The trains, hauls and ships loonkas travel with.
+#[derive(SystemParam)]
+pub struct Travellers<'w, 's> {
+    pub train_q: Query<'w, 's, &'static Train>,
+    pub walker_q: Query<'w, 's, &'static Walker>,
+    pub ship_q: Query<'w, 's, &'static Ship>,
+}
+
+impl Travellers<'_, '_> {
+    ///Ids of the loonkas away travelling.
+    pub fn loonkas(&self) -> HashSet<usize> {
+        travelling_loonkas(self.train_q.iter(), self.walker_q.iter(), self.ship_q.iter())
+    }
+}
+
+///Walks every hauling loonka along its route over the terrain, picking up at one end and dropping off at the other.
+pub fn run_walkers(
+    mut commands: Commands,
+    time: Res<Time>,
+    map_grid: Res<MapGrid>,
+    mut walker_q: Query<(Entity, &mut Walker, &mut Transform)>,
+    mut tile_q: Query<&mut Tile>,
+    player_stats_q: Query<&PlayerStats>,
+) {
+    let Ok(player_stats) = player_stats_q.get_single() else {return;};
+    for (entity, mut walker, mut transform) in walker_q.iter_mut() {
+        let Some(loonka) = player_stats.loonkas.iter().find(|loonka| loonka.id == walker.loonka) else {continue;};
+        let cost = |pos: (usize, usize)| map_grid.get(pos.0, pos.1)
+            .and_then(|entity| tile_q.get(entity).ok())
+            .and_then(|tile| tile.base_type.move_cost());
+        if walker.advance(cost, loonka.speed, time.delta_seconds()) {
+            if let Some(mut tile) = map_grid.get(walker.pos.0, walker.pos.1).and_then(|entity| tile_q.get_mut(entity).ok()) {
+                let walker = &mut *walker;
+                let done = if walker.delivering {
+                    drop_off(&mut walker.load, &mut tile.stock);
+                    walker.load.total() == 0
+                } else {
+                    match walker.order.as_mut() {
+                        Some(order) => { //just what was asked for, if it's still there
+                            order.amount = tile.stock.transfer(&mut walker.load, order.item, order.amount);
+                            order.amount > 0
+                        },
+                        None => pick_up(&mut walker.load, &mut tile.stock) > 0,
+                    }
+                };
+                if walker.order.is_some() && (done == walker.delivering) { //delivered, or nothing left to deliver
+                    commands.entity(entity).despawn_recursive();
+                    continue;
+                }
+                if done {walker.turn_back();} else {walker.status = WalkerStatus::Waiting;}
+            }
+        } else if walker.order.is_some() && walker.status == WalkerStatus::NoRoute && walker.load.total() == 0 {
+            commands.entity(entity).despawn_recursive(); //frees the courier for a delivery it can make
+            continue;
+        }
+
+        //drawn part of the way to the next tile
+        let (x, y) = grid_to_world(walker.pos.0 as f32, walker.pos.1 as f32);
+        let (next_x, next_y) = walker.path.first().map_or((x, y), |next| grid_to_world(next.0 as f32, next.1 as f32));
+        transform.translation.x = x + (next_x - x) * walker.progress;
+        transform.translation.y = y + (next_y - y) * walker.progress;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///A 5 by 5 patch of plains with some other terrain on it.
+    fn terrain(other: &[((usize, usize), TileBaseType)]) -> impl Fn((usize, usize)) -> Option<u32> + '_ {
+        move |pos| {
+            if pos.0 >= 5 || pos.1 >= 5 {return None;}
+            other.iter().find(|(at, _)| *at == pos).map_or(TileBaseType::Plains, |(_, base)| *base).move_cost()
+        }
+    }
+
+    #[test]
+    fn routes_go_round_slow_ground_and_deep_water() {
+        let plains = terrain(&[]);
+        assert_eq!(find_route((0, 0), (3, 0), &plains), Some(vec![(1, 0), (2, 0), (3, 0)]));
+        //a mountain in the way is slower to climb than to walk round
+        let mountain = [((1, 0), TileBaseType::Mountain)];
+        let route = find_route((0, 0), (2, 0), terrain(&mountain)).unwrap();
+        assert!(!route.contains(&(1, 0)), "{:?}", route);
+        assert_eq!(route.len(), 4);
+        //deep water can't be crossed at all
+        let river: Vec<_> = (0..5).map(|y| ((2, y), TileBaseType::DeepWater)).collect();
+        assert_eq!(find_route((0, 0), (4, 0), terrain(&river)), None);
+        assert_eq!(find_route((0, 0), (2, 0), terrain(&river)), None);
+    }
+
+    #[test]
+    fn regions_split_where_land_cannot_be_walked() {
+        let river: Vec<_> = (0..5).map(|y| ((2, y), TileBaseType::DeepWater)).collect();
+        let regions = WalkRegions::new(|pos| terrain(&river)(pos).is_some());
+        assert!(regions.connected((0, 0), (1, 4)));
+        assert!(regions.connected((3, 0), (4, 4)));
+        assert!(!regions.connected((0, 0), (4, 0)), "the river is in the way");
+        assert!(!regions.connected((0, 0), (2, 0)), "deep water can't be walked on");
+        assert!(!regions.connected((0, 0), (0, 6)), "off the 5 by 5 patch");
+
+        assert_eq!(route_cost((0, 0), (1, 1), terrain(&[((1, 0), TileBaseType::Mountain)])), Some(2));
+        assert_eq!(route_cost((0, 0), (4, 0), terrain(&river)), None);
+    }
+
+    #[test]
+    fn walkers_are_slowed_by_terrain_and_sped_up_by_speed() {
+        let jungle: Vec<_> = (0..5).flat_map(|x| (0..5).map(move |y| ((x, y), TileBaseType::Jungle))).collect();
+        let mut walker = Walker::new(0, 50, (0, 0), (0, 2));
+        walker.turn_back();
+        assert!(!walker.advance(terrain(&[]), 50, 1.), "two tiles of plains take over a second");
+        assert_eq!(walker.pos, (0, 1));
+        assert!(walker.advance(terrain(&[]), 100, 0.5));
+
+        let mut walker = Walker::new(0, 50, (0, 0), (0, 2));
+        walker.turn_back();
+        assert!(!walker.advance(terrain(&jungle), 100, 1.), "jungle is 4 times slower");
+        assert_eq!(walker.pos, (0, 0));
+        assert!(walker.advance(terrain(&jungle), 100, 3.));
+    }
+
+    #[test]
+    fn walkers_wait_before_searching_for_a_route_again() {
+        let river: Vec<_> = (0..5).map(|y| ((2, y), TileBaseType::DeepWater)).collect();
+        let searches = std::cell::Cell::new(0);
+        let cost = |pos| {
+            searches.set(searches.get() + 1);
+            terrain(&river)(pos)
+        };
+        let mut walker = Walker::new(0, 50, (0, 0), (4, 0));
+        walker.turn_back();
+        assert!(!walker.advance(cost, 50, 0.1));
+        assert_eq!(walker.status, WalkerStatus::NoRoute);
+        let searched = searches.get();
+        for _ in 0..40 {
+            walker.advance(cost, 50, 0.1);
+        }
+        assert_eq!(searches.get(), searched, "searched again before the delay was up");
+        for _ in 0..20 {
+            walker.advance(cost, 50, 0.1);
+        }
+        assert!(searches.get() > searched);
+        assert_eq!(walker.status, WalkerStatus::NoRoute);
+    }
+
+    #[test]
+    fn vigor_sets_how_much_is_carried() {
+        const SUGAR: ResourceItemType = ResourceItemType::Mine(MineType::Sugar);
+        let mut stock = Stockpile::new(100);
+        stock.add(SUGAR, 20);
+        let mut weak = Walker::new(0, 10, (0, 0), (1, 0));
+        let mut strong = Walker::new(1, 80, (0, 0), (1, 0));
+        assert_eq!(pick_up(&mut weak.load, &mut stock), 3);
+        assert_eq!(pick_up(&mut strong.load, &mut stock), 10);
+        let mut destination = Stockpile::new(100);
+        assert_eq!(drop_off(&mut strong.load, &mut destination), 10);
+        assert_eq!((destination.count(SUGAR), stock.count(SUGAR)), (10, 7));
+    }
+}
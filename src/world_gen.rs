@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::Resource;
+use noise::{NoiseFn, Perlin};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Normal, Distribution};
+use serde::Deserialize;
+
+use crate::tile_data::*;
+use crate::map_grid::MapGrid;
+use crate::{GRID_COLS, GRID_ROWS};
+
+///World generation presets file, relative to the assets folder.
+const WORLD_GEN_CONFIG_PATH: &str = "worldgen.ron";
+///Spritesheet index used when a tile has no resource overlay.
+const NO_RESOURCE_INDEX: usize = 192;
+
+///Tunable world generation parameters. Any field left out of a preset keeps its default value.
+#[derive(Resource, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WorldGenConfig {
+    pub noise_scale: f64, //map grid perlin noise scale
+    //Base height thresholds
+    pub water_level: f64,
+    pub deep_water_level: f64,
+    pub sand_level: f64,
+    pub plains_level: f64,
+    pub dark_mountain_level: f64, //anything higher is mountain
+    pub water_jitter: f64, //std deviation of the random offset added to water heights
+    pub land_jitter: f64, //std deviation of the random offset added to land and overlay values
+    //Overlay noise thresholds
+    pub forest_threshold: f64,
+    pub dark_forest_threshold: f64,
+    pub jungle_threshold: f64,
+    pub dirt_threshold: f64,
+    pub dark_dirt_threshold: f64,
+    pub mud_threshold: f64,
+    pub pond_threshold: f64,
+    pub pond_mud_threshold: f64,
+    pub island_threshold: f64,
+    pub shallows_threshold: f64,
+    //Chance for each tile to spawn a resource
+    pub outpost_chance: f64,
+    pub enemy_chance: f64,
+    pub harvest_chance: f64,
+    //Rivers
+    pub river_source_chance: f64, //chance for each mountain tile to be the source of a river
+    pub river_max_length: usize,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        WorldGenConfig {
+            noise_scale: 65.,
+            water_level: 0.10,
+            deep_water_level: 0.01,
+            sand_level: 0.25,
+            plains_level: 0.65,
+            dark_mountain_level: 0.85,
+            water_jitter: 0.02,
+            land_jitter: 0.05,
+            forest_threshold: 0.9,
+            dark_forest_threshold: 0.9,
+            jungle_threshold: 0.9,
+            dirt_threshold: 0.8,
+            dark_dirt_threshold: 0.9,
+            mud_threshold: 0.9,
+            pond_threshold: 0.9,
+            pond_mud_threshold: 0.8,
+            island_threshold: 0.95,
+            shallows_threshold: 0.85,
+            outpost_chance: 0.002,
+            enemy_chance: 0.002,
+            harvest_chance: 0.003,
+            river_source_chance: 0.002,
+            river_max_length: 400,
+        }
+    }
+}
+
+impl WorldGenConfig {
+    ///Loads the named preset from the world generation presets file, falling back to the defaults if it can't be read.
+    pub fn load(preset: &str) -> WorldGenConfig {
+        let path = FileAssetReader::get_base_path().join("assets").join(WORLD_GEN_CONFIG_PATH);
+        let presets = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| ron::from_str::<HashMap<String, WorldGenConfig>>(&text).map_err(|err| err.to_string()));
+        match presets {
+            Ok(mut presets) => match presets.remove(preset) {
+                Some(config) => config,
+                None => {
+                    println!("No world generation preset named '{}', using the defaults.", preset);
+                    WorldGenConfig::default()
+                }
+            },
+            Err(err) => {
+                println!("Couldn't load {}: {}. Using the default world generation settings.", path.display(), err);
+                WorldGenConfig::default()
+            }
+        }
+    }
+}
+
+///Data for a single generated map tile, before it is spawned into the world.
+#[derive(Clone, Copy, Debug)]
+pub struct GeneratedTile {
+    pub grid_pos: (usize, usize),
+    pub base_type: TileBaseType,
+    pub outpost: OutpostTile,
+    pub harvest: HarvestableTile,
+    pub enemy: EnemyTile,
+    pub texture_index: usize, //base terrain sprite
+    pub resource_index: Option<usize>, //outpost, harvestable or enemy overlay sprite
+}
+
+///Generates the map tiles, data, resources and sprite indices. The same seed always produces the same map.
+pub fn generate_tiles(seed: u64, config: &WorldGenConfig) -> Vec<GeneratedTile> {
+    //Every random value below is drawn from this rng so the map is reproducible from its seed.
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    //Generates set of perlin noises for the map
+    let base_perlin = Perlin::new(rng.gen());
+    let forest_perlin = Perlin::new(rng.gen());
+    let dark_forest_perlin = Perlin::new(rng.gen());
+    let jungle_perlin = Perlin::new(rng.gen());
+    let dirt_perlin = Perlin::new(rng.gen());
+    let mud_perlin = Perlin::new(rng.gen());
+    let pond_perlin = Perlin::new(rng.gen());
+    let islands_perlin = Perlin::new(rng.gen());
+
+
+    //Create base tiles and use perlin noise to fill in the tilebasetype. Kept in vecs indexed by x * GRID_ROWS + y so iteration order is stable.
+    let mut base_types = Vec::with_capacity(GRID_COLS * GRID_ROWS);
+    let mut heights = Vec::with_capacity(GRID_COLS * GRID_ROWS);
+    for x in 0..GRID_COLS {
+        for y in 0..GRID_ROWS {
+            let height = base_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let mut value = height;
+            let tile_base_type = if value <= config.water_level {
+                let normalized_rand = Normal::new(0.0,config.water_jitter).unwrap();
+                let normal_rng_val = normalized_rand.sample(&mut rng);
+                value = *clamp(&(value + (normal_rng_val)), &-1.00, &config.water_level);
+
+                    match value {
+                        v if v < config.deep_water_level => TileBaseType::DeepWater,
+                        _ => TileBaseType::Water,
+                    }
+            } else {
+                let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+                let normal_rng_val = normalized_rand.sample(&mut rng);
+                value = *clamp(&(value + (normal_rng_val)), &-1.00, &1.00);
+    
+                match value {
+                    v if v < config.sand_level => TileBaseType::Sand,
+                    v if v < config.plains_level => TileBaseType::Plains,
+                    v if v < config.dark_mountain_level => TileBaseType::DarkMountain,
+                    _ => TileBaseType::Mountain,
+                }
+            };
+
+            //Generate forests
+            let mut value = forest_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+            let normal_rng_val = normalized_rand.sample(&mut rng);
+            value = *clamp(&(value + (normal_rng_val)), &0.00, &1.00);
+            let tile_base_type = match value {
+                v if (v > config.forest_threshold && tile_base_type == TileBaseType::Plains) => TileBaseType::Forest,
+                _ => {tile_base_type}
+            };
+            
+            //Generate dark forests
+            let mut value = dark_forest_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+            let normal_rng_val = normalized_rand.sample(&mut rng);
+            value = *clamp(&(value + (normal_rng_val)), &0.00, &1.00);
+            let tile_base_type = match value {
+                v if (v > config.dark_forest_threshold && tile_base_type == TileBaseType::Plains) => TileBaseType::DarkForest,
+                _ => {tile_base_type}
+            };
+            
+            //Generate jungles
+            let mut value = jungle_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+            let normal_rng_val = normalized_rand.sample(&mut rng);
+            value = *clamp(&(value + (normal_rng_val)), &0.00, &1.00);
+            let tile_base_type = match value {
+                v if (v > config.jungle_threshold && (tile_base_type == TileBaseType::Plains || tile_base_type == TileBaseType::DarkMountain)) => TileBaseType::Jungle,
+                _ => {tile_base_type}
+            };
+            
+            //Generate dirt
+            let mut value = dirt_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+            let normal_rng_val = normalized_rand.sample(&mut rng);
+            value = *clamp(&(value + (normal_rng_val)), &0.00, &1.00);
+            let tile_base_type = match value {
+                v if (v > config.dark_dirt_threshold && tile_base_type == TileBaseType::Plains) => TileBaseType::DarkDirt,
+                v if (v > config.dirt_threshold && tile_base_type == TileBaseType::Plains) => TileBaseType::Dirt,
+                _ => {tile_base_type}
+            };
+            
+            //Generate mud
+            let mut value = mud_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+            let normal_rng_val = normalized_rand.sample(&mut rng);
+            value = *clamp(&(value + (normal_rng_val)), &0.00, &1.00);
+            let tile_base_type = match value {
+                v if (v > config.mud_threshold && tile_base_type == TileBaseType::Sand) => TileBaseType::Mud,
+                _ => {tile_base_type}
+            };
+
+            //Generate ponds
+            let mut value = pond_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let normalized_rand = Normal::new(0.0,config.land_jitter).unwrap();
+            let normal_rng_val = normalized_rand.sample(&mut rng);
+            value = *clamp(&(value + (normal_rng_val)), &0.00, &1.00);
+            let tile_base_type = match value {
+                v if (v > config.pond_threshold && (tile_base_type == TileBaseType::Plains || tile_base_type == TileBaseType::DarkMountain || tile_base_type == TileBaseType::Mountain)) => TileBaseType::Pond,
+                v if (v > config.pond_threshold && tile_base_type == TileBaseType::Sand) => TileBaseType::Mud,
+                v if (v > config.pond_mud_threshold && (tile_base_type == TileBaseType::Plains || tile_base_type == TileBaseType::DarkMountain || tile_base_type == TileBaseType::Mountain)) => TileBaseType::Mud,
+                _ => {tile_base_type}
+            };
+
+            //Generate islands
+            let value = islands_perlin.get([(x as f64 / config.noise_scale),(y as f64 / config.noise_scale)]);
+            let tile_base_type = match value {
+                v if (v > config.island_threshold && (tile_base_type == TileBaseType::DeepWater || tile_base_type == TileBaseType::Water)) => TileBaseType::Sand,
+                v if (v > config.shallows_threshold && (tile_base_type == TileBaseType::DeepWater || tile_base_type == TileBaseType::Water)) => TileBaseType::Water,
+                _ => {tile_base_type}
+            };
+
+            base_types.push(tile_base_type);
+            heights.push(height);
+        }
+    }
+
+    //Rivers run downhill from the mountains, so they are carved once the whole height field is known.
+    carve_rivers(&mut base_types, &heights, config, &mut rng);
+
+    //Generate resources on the final terrain.
+    let mut tiles = Vec::with_capacity(GRID_COLS * GRID_ROWS);
+    for x in 0..GRID_COLS {
+        for y in 0..GRID_ROWS {
+            let tile_base_type = base_types[x * GRID_ROWS + y];
+            let resource_value: f64 = rng.gen();
+            let resource_type = match resource_value {
+                v if v < config.outpost_chance => "outpost",
+                v if v < config.outpost_chance + config.enemy_chance => "enemy",
+                v if v < config.outpost_chance + config.enemy_chance + config.harvest_chance => "harvest",
+                _ => "none",
+            };
+
+            let mut resource_data = (
+                OutpostTile{ outpost_type: OutpostType::None, sales_mod: 0 },
+                HarvestableTile{yields: ResourceItemType::None, amount_mod: 0},
+                EnemyTile::NONE);
+
+
+            if resource_type == "outpost" {
+                let sales_mod_rand = rng.gen_range(0..=100);
+                resource_data.0 = OutpostTile{ outpost_type: OutpostType::get_rand(tile_base_type, &mut rng), sales_mod: sales_mod_rand };
+            } else if  resource_type == "enemy" {
+                let amount_mod_rand = rng.gen_range(0..=100);
+                let health_mod_rand = rng.gen_range(0..=100);
+                let damage_mod_rand = rng.gen_range(0..=100);
+                let enemy_type = EnemyTileType::get_rand(tile_base_type, &mut rng);
+                let drops = [(); 5].map(|_| enemy_type.get_rand_drop(&mut rng));
+                resource_data.2 = EnemyTile { enemy_type, yields: drops, amount_mod: amount_mod_rand, health: health_mod_rand, damage: damage_mod_rand }
+                
+            } else if resource_type == "harvest" {
+                let rand_item_type = ResourceItemType::get_rand(tile_base_type, &mut rng);
+                let rand_amount = rng.gen_range(0..=100);
+                resource_data.1 = HarvestableTile {yields: rand_item_type, amount_mod: rand_amount};
+                
+            };
+            // Insert into the tiles collection as a point and a tile type enum
+            tiles.push(((x, y), tile_base_type, resource_data.0, resource_data.1, resource_data.2));
+        }
+    }
+
+    //Go through each tile and get the sprite for its tilebasetype enum and resource overlay.
+    let mut generated = Vec::with_capacity(tiles.len());
+    for ((x,y), tile_base_type, outpost_data, harvest_data, enemy_data) in tiles.iter() {
+        let tile_randomizer = rng.gen_range(0..=3); //4 possible max states, most tiles use less hence the clamp.
+
+        let texture_index = match tile_base_type {
+            TileBaseType::Plains => {*clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::Forest => {3 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::DarkForest => {6 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::Jungle => {9 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::Mountain => {12 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::DarkMountain => {15 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::Sand => {18 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::Dirt => {21 + *clamp(&tile_randomizer, &0, &2) as usize},
+            TileBaseType::DarkDirt => {24 + *clamp(&tile_randomizer, &0, &1) as usize},
+            TileBaseType::Mud => {26 + *clamp(&tile_randomizer, &0, &1) as usize},
+            TileBaseType::Water => {28 + *clamp(&tile_randomizer, &0, &1) as usize},
+            TileBaseType::DeepWater => {30 + *clamp(&tile_randomizer, &0, &1) as usize},
+            TileBaseType::River => {32 + *clamp(&tile_randomizer, &0, &1) as usize},
+            TileBaseType::Pond => {34 + *clamp(&tile_randomizer, &0, &1) as usize},
+        };
+
+        //Gets the random tile type or color.
+        let resource_index = if outpost_data.outpost_type != OutpostType::None {
+            let tile_resource_type = outpost_data.outpost_type;
+            let rand_colorval = rng.gen_range(0..=11); //0 to 11 inclusive
+            match tile_resource_type {
+                OutpostType::ForestOutpost => {48 + rand_colorval},
+                OutpostType::MountainOutpost => {60 + rand_colorval},
+                OutpostType::ShipOutpost => {72 + rand_colorval},
+                _ => {36 + rand_colorval},
+            }
+        } else if harvest_data.yields != ResourceItemType::None {
+            let tile_resource_type = &harvest_data.yields;
+            //let rand_colorval = rng.gen_range(0..=11); //0 to 11 inclusive
+            match tile_resource_type {
+                ResourceItemType::Nut(_) => {120 + rng.gen_range(0..=2)},
+                ResourceItemType::Bush(_) => {132 + rng.gen_range(0..=8)},
+                ResourceItemType::Fruit(_) => {108 + rng.gen_range(0..=11)},
+                ResourceItemType::Spice(SpiceType::Seamint) => 149, //seamint has a unique icon
+                ResourceItemType::Spice(_) => {144 + rng.gen_range(0..=4)},
+                _ => {96 + rng.gen_range(0..=4)}, //Mine
+            }
+        } else if enemy_data.enemy_type != EnemyTileType::None {
+            let tile_resource_type = &enemy_data.enemy_type;
+            match tile_resource_type {
+                EnemyTileType::BeepleBillage => {85},
+                _ => {84}, //pirate ship
+            }
+        } else {
+            NO_RESOURCE_INDEX //default = none
+        };
+
+        generated.push(GeneratedTile {
+            grid_pos: (*x, *y),
+            base_type: *tile_base_type,
+            outpost: *outpost_data,
+            harvest: *harvest_data,
+            enemy: *enemy_data,
+            texture_index,
+            resource_index: if resource_index < NO_RESOURCE_INDEX {Some(resource_index)} else {None},
+        });
+    }
+    generated
+}
+
+///Starts rivers on random mountain tiles and flows each one to its lowest neighbour along the base height field.
+///A river ends when it reaches the sea, a pond or another river, or turns into a pond when it has nowhere lower to go.
+fn carve_rivers(base_types: &mut [TileBaseType], heights: &[f64], config: &WorldGenConfig, rng: &mut impl Rng) {
+    let sources: Vec<usize> = (0..base_types.len())
+        .filter(|&i| base_types[i] == TileBaseType::Mountain)
+        .filter(|_| rng.gen_bool(config.river_source_chance))
+        .collect();
+
+    for source in sources {
+        let (mut x, mut y) = (source / GRID_ROWS, source % GRID_ROWS);
+        for _ in 0..config.river_max_length {
+            base_types[x * GRID_ROWS + y] = TileBaseType::River;
+
+            let lowest = MapGrid::neighbours(x, y)
+                .min_by(|a, b| heights[a.0 * GRID_ROWS + a.1].total_cmp(&heights[b.0 * GRID_ROWS + b.1]));
+            let Some((next_x, next_y)) = lowest else {break;};
+            let next = next_x * GRID_ROWS + next_y;
+
+            if heights[next] >= heights[x * GRID_ROWS + y] { //nowhere lower to flow, so the river pools
+                base_types[x * GRID_ROWS + y] = TileBaseType::Pond;
+                break;
+            }
+            if base_types[next].is_water() {
+                break;
+            }
+            (x, y) = (next_x, next_y);
+        }
+    }
+}
+
+fn clamp<'a, T: PartialOrd>(x: &'a T, min: &'a T, max: &'a T) -> &'a T { //Clamps a partially ordered value between a min and max value, inclusive.
+    if x >= max {
+        return max;
+    } else if x <= min {
+        return min;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(x: usize, y: usize) -> usize {x * GRID_ROWS + y}
+
+    fn heights(height: impl Fn(usize, usize) -> f64) -> Vec<f64> {
+        (0..GRID_COLS * GRID_ROWS).map(|i| height(i / GRID_ROWS, i % GRID_ROWS)).collect()
+    }
+
+    fn carve(before: &[TileBaseType], heights: &[f64]) -> Vec<TileBaseType> {
+        let config = WorldGenConfig { river_source_chance: 1., river_max_length: GRID_COLS * GRID_ROWS, ..WorldGenConfig::default() };
+        let mut after = before.to_vec();
+        carve_rivers(&mut after, heights, &config, &mut StdRng::seed_from_u64(0));
+        after
+    }
+
+    ///Checks every carved tile is fed from higher up, starting on a mountain,
+    ///and flows strictly downhill until it reaches water or pools into a pond.
+    fn assert_rivers_run_downhill(before: &[TileBaseType], after: &[TileBaseType], heights: &[f64]) {
+        let carved = |i: usize| after[i] != before[i];
+        for i in (0..after.len()).filter(|&i| carved(i)) {
+            let (x, y) = (i / GRID_ROWS, i % GRID_ROWS);
+            let neighbours: Vec<usize> = MapGrid::neighbours(x, y).map(|(nx, ny)| index(nx, ny)).collect();
+
+            if before[i] != TileBaseType::Mountain {
+                assert!(neighbours.iter().any(|&n| carved(n) && heights[n] > heights[i]),
+                    "river at {:?} has no source upstream", (x, y));
+            }
+
+            let lowest = neighbours.iter().map(|&n| heights[n]).fold(f64::INFINITY, f64::min);
+            match after[i] {
+                TileBaseType::Pond => assert!(lowest >= heights[i], "pond at {:?} could still flow", (x, y)),
+                TileBaseType::River => {
+                    assert!(lowest < heights[i], "river at {:?} flows uphill", (x, y));
+                    assert!(neighbours.iter().any(|&n| heights[n] == lowest && (carved(n) || before[n].is_water())),
+                        "river at {:?} stops before reaching water", (x, y));
+                },
+                other => panic!("{:?} carved into {:?}", (x, y), other),
+            }
+        }
+    }
+
+    #[test]
+    fn rivers_run_downhill_into_the_sea() {
+        let heights = heights(|x, _| x as f64); //slopes down to a sea along the west edge
+        let mut before = vec![TileBaseType::Plains; GRID_COLS * GRID_ROWS];
+        for y in 0..GRID_ROWS {
+            for x in 0..3 {before[index(x, y)] = TileBaseType::Water;}
+        }
+        for (x, y) in [(10, 10), (20, 40), (15, 11)] {
+            before[index(x, y)] = TileBaseType::Mountain;
+        }
+        before[index(30, 10)] = TileBaseType::Forest; //higher than the mountains, but not a source
+
+        let after = carve(&before, &heights);
+        assert_rivers_run_downhill(&before, &after, &heights);
+        assert!((3..=10).all(|x| after[index(x, 10)] == TileBaseType::River));
+        assert_eq!(after[index(30, 10)], TileBaseType::Forest);
+        assert_eq!(after.iter().filter(|base| **base == TileBaseType::River).count(), 8 + 18 + 13);
+    }
+
+    #[test]
+    fn rivers_pool_where_nothing_is_lower() {
+        let heights = heights(|x, y| x.abs_diff(50) as f64 + y.abs_diff(50) as f64); //a bowl around (50, 50)
+        let mut before = vec![TileBaseType::Plains; GRID_COLS * GRID_ROWS];
+        before[index(55, 52)] = TileBaseType::Mountain;
+
+        let after = carve(&before, &heights);
+        assert_rivers_run_downhill(&before, &after, &heights);
+        assert_eq!(after[index(50, 50)], TileBaseType::Pond);
+        assert_eq!(after[index(55, 52)], TileBaseType::River);
+    }
+}